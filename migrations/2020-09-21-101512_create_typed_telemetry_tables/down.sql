@@ -0,0 +1,5 @@
+DROP TABLE txpool_imports;
+DROP TABLE system_intervals;
+DROP TABLE notify_finalized;
+DROP TABLE afg_finalized;
+DROP TABLE block_imports;
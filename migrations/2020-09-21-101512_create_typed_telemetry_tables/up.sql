@@ -0,0 +1,67 @@
+CREATE TABLE block_imports
+(
+    id                 SERIAL    PRIMARY KEY,
+    peer_connection_id INTEGER   REFERENCES peer_connections (id) ON DELETE CASCADE NOT NULL,
+    created_at         TIMESTAMP NOT NULL,
+    best               VARCHAR   NOT NULL,
+    height             BIGINT    NOT NULL,
+    origin             VARCHAR
+);
+CREATE INDEX block_imports_peer_connection_id_created_at_idx ON block_imports (peer_connection_id, created_at);
+CREATE INDEX block_imports_created_at_idx ON block_imports (created_at);
+
+CREATE TABLE afg_finalized
+(
+    id                 SERIAL    PRIMARY KEY,
+    peer_connection_id INTEGER   REFERENCES peer_connections (id) ON DELETE CASCADE NOT NULL,
+    created_at         TIMESTAMP NOT NULL,
+    finalized_hash     VARCHAR   NOT NULL,
+    finalized_number   BIGINT    NOT NULL
+);
+CREATE INDEX afg_finalized_peer_connection_id_created_at_idx ON afg_finalized (peer_connection_id, created_at);
+CREATE INDEX afg_finalized_created_at_idx ON afg_finalized (created_at);
+
+CREATE TABLE notify_finalized
+(
+    id                 SERIAL    PRIMARY KEY,
+    peer_connection_id INTEGER   REFERENCES peer_connections (id) ON DELETE CASCADE NOT NULL,
+    created_at         TIMESTAMP NOT NULL,
+    best               VARCHAR   NOT NULL,
+    height             BIGINT    NOT NULL
+);
+CREATE INDEX notify_finalized_peer_connection_id_created_at_idx ON notify_finalized (peer_connection_id, created_at);
+CREATE INDEX notify_finalized_created_at_idx ON notify_finalized (created_at);
+
+CREATE TABLE system_intervals
+(
+    id                    SERIAL           PRIMARY KEY,
+    peer_connection_id    INTEGER          REFERENCES peer_connections (id) ON DELETE CASCADE NOT NULL,
+    created_at            TIMESTAMP        NOT NULL,
+    peers                 BIGINT,
+    best                  VARCHAR,
+    height                BIGINT,
+    finalized_hash        VARCHAR,
+    finalized_height      BIGINT,
+    txcount               BIGINT,
+    bandwidth_download    BIGINT,
+    bandwidth_upload      BIGINT,
+    cpu                   DOUBLE PRECISION,
+    memory                BIGINT,
+    disk_read_per_sec     BIGINT,
+    disk_write_per_sec    BIGINT,
+    used_state_cache_size BIGINT,
+    used_db_cache_size    BIGINT
+);
+CREATE INDEX system_intervals_peer_connection_id_created_at_idx ON system_intervals (peer_connection_id, created_at);
+CREATE INDEX system_intervals_created_at_idx ON system_intervals (created_at);
+
+CREATE TABLE txpool_imports
+(
+    id                 SERIAL    PRIMARY KEY,
+    peer_connection_id INTEGER   REFERENCES peer_connections (id) ON DELETE CASCADE NOT NULL,
+    created_at         TIMESTAMP NOT NULL,
+    ready              BIGINT    NOT NULL,
+    future             BIGINT    NOT NULL
+);
+CREATE INDEX txpool_imports_peer_connection_id_created_at_idx ON txpool_imports (peer_connection_id, created_at);
+CREATE INDEX txpool_imports_created_at_idx ON txpool_imports (created_at);
@@ -0,0 +1,21 @@
+ALTER TABLE substrate_logs RENAME TO substrate_logs_partitioned;
+ALTER SEQUENCE substrate_logs_id_seq OWNED BY NONE;
+
+CREATE TABLE substrate_logs
+(
+    id                 INTEGER   PRIMARY KEY DEFAULT nextval('substrate_logs_id_seq'),
+    created_at         TIMESTAMP NOT NULL DEFAULT NOW(),
+    logs               JSONB     NOT NULL,
+    peer_connection_id INTEGER   REFERENCES peer_connections (id) NOT NULL
+);
+
+INSERT INTO substrate_logs (id, created_at, logs, peer_connection_id)
+SELECT id, created_at, logs, peer_connection_id
+FROM substrate_logs_partitioned;
+
+DROP TABLE substrate_logs_partitioned;
+ALTER SEQUENCE substrate_logs_id_seq OWNED BY substrate_logs.id;
+
+CREATE INDEX substrate_logs_msg_type_idx ON substrate_logs USING BTREE ((logs->>'msg'));
+CREATE INDEX substrate_logs_created_at_msg_idx ON substrate_logs USING BTREE (created_at, (logs->>'msg'));
+CREATE INDEX substrate_logs_peerset_nodes_idx ON substrate_logs USING GIN ((logs->'state'->'peerset'->'nodes') jsonb_path_ops);
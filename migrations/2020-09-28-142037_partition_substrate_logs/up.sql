@@ -0,0 +1,55 @@
+-- Requires PostgreSQL 11 or later.
+-- `substrate_logs` is partitioned by `audit`, audit logs are kept in a single partition that is never dropped,
+-- all other logs are partitioned by `created_at` ranges which are created ahead of time and dropped once expired.
+ALTER TABLE substrate_logs RENAME TO substrate_logs_unpartitioned;
+ALTER SEQUENCE substrate_logs_id_seq OWNED BY NONE;
+
+CREATE TABLE substrate_logs
+(
+    id                 INTEGER   NOT NULL DEFAULT nextval('substrate_logs_id_seq'),
+    created_at         TIMESTAMP NOT NULL DEFAULT NOW(),
+    logs               JSONB     NOT NULL,
+    peer_connection_id INTEGER   REFERENCES peer_connections (id) NOT NULL,
+    audit              BOOLEAN   NOT NULL DEFAULT FALSE,
+    PRIMARY KEY (id, audit, created_at)
+) PARTITION BY LIST (audit);
+
+CREATE TABLE substrate_logs_audit PARTITION OF substrate_logs FOR VALUES IN (TRUE);
+CREATE TABLE substrate_logs_expiring PARTITION OF substrate_logs FOR VALUES IN (FALSE) PARTITION BY RANGE (created_at);
+-- Catches anything outside of the created time ranges
+CREATE TABLE substrate_logs_expiring_default PARTITION OF substrate_logs_expiring DEFAULT;
+
+-- Create daily partitions for the existing logs, so they don't all end up in the default partition,
+-- which would stop the partitions for those ranges being created later.
+DO $$
+DECLARE
+    day TIMESTAMP;
+BEGIN
+    FOR day IN
+        SELECT DISTINCT date_trunc('day', sl.created_at)
+        FROM substrate_logs_unpartitioned sl
+        INNER JOIN peer_connections pc ON sl.peer_connection_id = pc.id
+        WHERE NOT pc.audit
+    LOOP
+        EXECUTE format(
+            'CREATE TABLE %I PARTITION OF substrate_logs_expiring FOR VALUES FROM (%L) TO (%L)',
+            'substrate_logs_' || to_char(day, 'YYYYMMDDHH24') || '_' || to_char(day + INTERVAL '1 day', 'YYYYMMDDHH24'),
+            day,
+            day + INTERVAL '1 day'
+        );
+    END LOOP;
+END
+$$;
+
+INSERT INTO substrate_logs (id, created_at, logs, peer_connection_id, audit)
+SELECT sl.id, sl.created_at, sl.logs, sl.peer_connection_id, pc.audit
+FROM substrate_logs_unpartitioned sl
+INNER JOIN peer_connections pc ON sl.peer_connection_id = pc.id;
+
+DROP TABLE substrate_logs_unpartitioned;
+ALTER SEQUENCE substrate_logs_id_seq OWNED BY substrate_logs.id;
+
+CREATE INDEX substrate_logs_msg_type_idx ON substrate_logs USING BTREE ((logs->>'msg'));
+CREATE INDEX substrate_logs_created_at_msg_idx ON substrate_logs USING BTREE (created_at, (logs->>'msg'));
+CREATE INDEX substrate_logs_peerset_nodes_idx ON substrate_logs USING GIN ((logs->'state'->'peerset'->'nodes') jsonb_path_ops);
+CREATE INDEX substrate_logs_peer_connection_id_idx ON substrate_logs (peer_connection_id);
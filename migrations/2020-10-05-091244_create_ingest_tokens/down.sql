@@ -0,0 +1 @@
+DROP TABLE ingest_tokens;
\ No newline at end of file
@@ -0,0 +1,11 @@
+CREATE TABLE ingest_tokens
+(
+    id          SERIAL    PRIMARY KEY,
+    token_hash  VARCHAR   NOT NULL UNIQUE,
+    description VARCHAR,
+    scope       VARCHAR   NOT NULL CHECK (scope IN ('normal', 'audit')),
+    chain       VARCHAR,
+    expires_at  TIMESTAMP,
+    revoked_at  TIMESTAMP,
+    created_at  TIMESTAMP NOT NULL DEFAULT (NOW() AT TIME ZONE 'utc')
+);
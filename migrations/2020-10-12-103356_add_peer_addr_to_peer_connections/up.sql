@@ -0,0 +1 @@
+ALTER TABLE peer_connections ADD COLUMN peer_addr VARCHAR;
@@ -0,0 +1,2 @@
+ALTER TABLE peer_connections DROP COLUMN ingest_token_id;
+ALTER TABLE peer_connections DROP COLUMN http_ingest;
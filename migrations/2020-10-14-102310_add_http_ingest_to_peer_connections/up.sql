@@ -0,0 +1,2 @@
+ALTER TABLE peer_connections ADD COLUMN http_ingest BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE peer_connections ADD COLUMN ingest_token_id INTEGER;
@@ -0,0 +1,3 @@
+DROP INDEX peer_connections_peer_id_created_at_idx;
+ALTER TABLE peer_connections DROP COLUMN disconnect_reason;
+ALTER TABLE peer_connections DROP COLUMN disconnected_at;
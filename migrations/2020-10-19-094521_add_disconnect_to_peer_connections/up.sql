@@ -0,0 +1,3 @@
+ALTER TABLE peer_connections ADD COLUMN disconnected_at TIMESTAMP;
+ALTER TABLE peer_connections ADD COLUMN disconnect_reason VARCHAR;
+CREATE INDEX peer_connections_peer_id_created_at_idx ON peer_connections (peer_id, created_at);
@@ -0,0 +1,4 @@
+ALTER TABLE benchmarks
+    DROP COLUMN state,
+    DROP COLUMN finished_at,
+    DROP COLUMN summary;
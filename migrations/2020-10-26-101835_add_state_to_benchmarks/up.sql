@@ -0,0 +1,11 @@
+ALTER TABLE benchmarks
+    ADD COLUMN state       VARCHAR NOT NULL DEFAULT 'running'
+        CHECK (state IN ('running', 'finished', 'failed', 'aborted')),
+    ADD COLUMN finished_at TIMESTAMP,
+    ADD COLUMN summary     JSONB;
+
+-- Benchmarks recorded so far are over, they are left without a summary
+UPDATE benchmarks b
+SET state       = 'finished',
+    finished_at = COALESCE((SELECT max(be.created_at) FROM benchmark_events be WHERE be.benchmark_id = b.id),
+                           b.created_at);
@@ -0,0 +1,23 @@
+-- Time ranges of the logs of a node that are kept past `LOG_EXPIRY_H` for a benchmark
+CREATE TABLE log_pins
+(
+    id           SERIAL    PRIMARY KEY,
+    benchmark_id INTEGER   REFERENCES benchmarks (id) NOT NULL,
+    peer_id      VARCHAR   NOT NULL,
+    start_time   TIMESTAMP NOT NULL,
+    -- NULL while the benchmark is running
+    end_time     TIMESTAMP,
+    created_at   TIMESTAMP NOT NULL DEFAULT (NOW() AT TIME ZONE 'utc')
+);
+CREATE INDEX log_pins_benchmark_id_idx ON log_pins (benchmark_id);
+CREATE INDEX log_pins_peer_id_idx ON log_pins (peer_id);
+
+-- Pin the logs of the benchmarks recorded so far that may not have expired yet
+INSERT INTO log_pins (benchmark_id, peer_id, start_time, end_time)
+SELECT b.id,
+       b.setup->'substrate'->>'peerId',
+       LEAST(b.created_at, COALESCE((SELECT min(be.created_at) FROM benchmark_events be WHERE be.benchmark_id = b.id),
+                                    b.created_at)),
+       b.finished_at
+FROM benchmarks b
+WHERE b.setup->'substrate'->>'peerId' IS NOT NULL;
@@ -0,0 +1 @@
+ALTER TABLE peer_connections ADD COLUMN instance_id VARCHAR;
@@ -453,7 +453,10 @@ mod tests {
                 RwLock::new(crate::db::create_pool());
         }
         let pool = POOL.read().unwrap().to_owned();
-        let db_arbiter = SyncArbiter::start(1, move || DbExecutor::new(pool.clone()));
+        let copy_pool = crate::db::bulk::create_copy_pool().unwrap();
+        let db_arbiter = SyncArbiter::start(1, move || {
+            DbExecutor::new(pool.clone(), copy_pool.clone(), Default::default())
+        });
         Cache::new(db_arbiter.clone())
     }
 
@@ -0,0 +1,90 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate Analytics.
+
+// Substrate Analytics is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate Analytics is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
+
+use super::{invalid_input, Args};
+use crate::db::{self, filters::Filters, DbExecutor};
+use crate::export::{
+    csv_chunk, csv_header, ndjson_chunk, ExportCursor, ExportFormat, Fields, ParquetExport,
+};
+use crate::web::metrics::Metrics;
+use actix::prelude::*;
+use std::fs::File;
+use std::io::{self, Write};
+
+pub const SWITCHES: &[&str] = &[];
+
+fn other(e: impl ToString) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+/// Export stored logs to a file, or to stdout for NDJSON and CSV
+pub async fn run(args: Args) -> io::Result<()> {
+    let format = args
+        .parse_value::<ExportFormat>("format")
+        .map_err(invalid_input)?
+        .unwrap_or(ExportFormat::NdJson);
+    let fields = args
+        .parse_value::<Fields>("fields")
+        .map_err(invalid_input)?
+        .unwrap_or_default();
+    let filters = Filters {
+        start_time: args.parse_value("start-time").map_err(invalid_input)?,
+        end_time: args.parse_value("end-time").map_err(invalid_input)?,
+        limit: args.parse_value("limit").map_err(invalid_input)?,
+        peer_id: args.get("peer-id").map(String::from),
+        target: args.get("target").map(String::from),
+        msg: args.get("msg").map(String::from),
+        ..Default::default()
+    };
+    let output = args.get("output");
+
+    let pool = db::create_pool();
+    let copy_pool = db::bulk::create_copy_pool().map_err(other)?;
+    let db = SyncArbiter::start(1, move || {
+        DbExecutor::new(pool.clone(), copy_pool.clone(), Metrics::default())
+    });
+    let mut cursor = ExportCursor::new(filters);
+    let mut total = 0;
+
+    if format == ExportFormat::Parquet {
+        let path =
+            output.ok_or_else(|| invalid_input("--output is required for parquet".to_string()))?;
+        let mut parquet = ParquetExport::new(File::create(path)?, fields).map_err(other)?;
+        while let Some(rows) = cursor.next(&db).await.map_err(other)? {
+            parquet.write(&rows).map_err(other)?;
+            total += rows.len();
+        }
+        parquet.close().map_err(other)?;
+    } else {
+        let mut out: Box<dyn Write> = match output {
+            Some(path) => Box::new(io::BufWriter::new(File::create(path)?)),
+            None => Box::new(io::BufWriter::new(io::stdout())),
+        };
+        if format == ExportFormat::Csv {
+            out.write_all(&csv_header(&fields))?;
+        }
+        while let Some(rows) = cursor.next(&db).await.map_err(other)? {
+            match format {
+                ExportFormat::Csv => out.write_all(&csv_chunk(&rows, &fields))?,
+                _ => out.write_all(&ndjson_chunk(&rows))?,
+            }
+            total += rows.len();
+        }
+        out.flush()?;
+    }
+    eprintln!("Exported {} logs", total);
+    Ok(())
+}
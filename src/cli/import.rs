@@ -0,0 +1,305 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate Analytics.
+
+// Substrate Analytics is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate Analytics is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
+
+use super::{invalid_input, Args};
+use crate::db::{
+    self,
+    models::{NewPeerConnection, NewSubstrateLog, PeerConnection},
+    sessions::RecordDisconnect,
+    DbExecutor, FindPeerConnection, LogBatch,
+};
+use crate::web::metrics::Metrics;
+use crate::web::root::{unwrap_envelope, update_peer_info};
+use crate::DB_BATCH_SIZE;
+use actix::prelude::*;
+use chrono::{DateTime, FixedOffset, Utc};
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+pub const SWITCHES: &[&str] = &["audit", "rewrite-timestamps"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DumpFormat {
+    /// A JSON array of log messages, eg. `telemetry_messages.json`
+    Json,
+    /// One log message per line
+    NdJson,
+    /// Output of `psql` with the log message in the first column, eg. `log_messages.txt`
+    Psql,
+}
+
+impl FromStr for DumpFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(DumpFormat::Json),
+            "ndjson" => Ok(DumpFormat::NdJson),
+            "psql" => Ok(DumpFormat::Psql),
+            _ => Err(format!("Unknown format: {}", s)),
+        }
+    }
+}
+
+impl DumpFormat {
+    fn detect(content: &str) -> Self {
+        match content.trim_start().chars().next() {
+            Some('[') => DumpFormat::Json,
+            Some('{') => DumpFormat::NdJson,
+            _ => DumpFormat::Psql,
+        }
+    }
+
+    /// Parse the log messages in `content`, returning the number of entries that could not be parsed
+    fn parse(self, content: &str) -> (Vec<Value>, usize) {
+        match self {
+            DumpFormat::Json => match serde_json::from_str::<Vec<Value>>(content) {
+                Ok(logs) => (logs, 0),
+                Err(e) => {
+                    error!("Unable to parse JSON array: {}", e);
+                    (Vec::new(), 1)
+                }
+            },
+            DumpFormat::NdJson => parse_lines(content.lines()),
+            // Skip the header, separator and row count, and anything after the first column
+            DumpFormat::Psql => parse_lines(
+                content
+                    .lines()
+                    .map(str::trim_start)
+                    .filter(|line| line.starts_with('{')),
+            ),
+        }
+    }
+}
+
+fn parse_lines<'a>(lines: impl Iterator<Item = &'a str>) -> (Vec<Value>, usize) {
+    let mut logs = Vec::new();
+    let mut failed = 0;
+    for line in lines.filter(|l| !l.trim().is_empty()) {
+        // Only the first JSON value on the line is used, so trailing psql columns are ignored
+        match serde_json::Deserializer::from_str(line)
+            .into_iter::<Value>()
+            .next()
+        {
+            Some(Ok(log)) => logs.push(log),
+            Some(Err(e)) => {
+                warn!("Unable to parse line: {} - {}", line, e);
+                failed += 1;
+            }
+            None => (),
+        }
+    }
+    (logs, failed)
+}
+
+/// Shift every timestamp by the same amount so that the most recent one is now
+fn rewrite_timestamps(logs: &mut [(DateTime<FixedOffset>, Value)]) {
+    let latest = match logs.iter().map(|(ts, _)| *ts).max() {
+        Some(latest) => latest,
+        None => return,
+    };
+    let offset = Utc::now().signed_duration_since(latest);
+    for (ts, log) in logs.iter_mut() {
+        *ts = *ts + offset;
+        log["ts"] = json!(ts.to_rfc3339());
+    }
+}
+
+fn read_logs(path: &str, format: Option<DumpFormat>) -> io::Result<(Vec<Value>, usize)> {
+    let content = fs::read_to_string(Path::new(path))?;
+    let format = format.unwrap_or_else(|| DumpFormat::detect(&content));
+    info!("Reading {} as {:?}", path, format);
+    Ok(format.parse(&content))
+}
+
+async fn peer_connection(
+    db: &Addr<DbExecutor>,
+    args: &Args,
+    audit: bool,
+) -> io::Result<PeerConnection> {
+    let to_io = |e| io::Error::other(format!("{}", e));
+    if let Some(connection_id) = args
+        .parse_value::<i32>("connection-id")
+        .map_err(invalid_input)?
+    {
+        return db
+            .send(FindPeerConnection {
+                id: Some(connection_id),
+                peer_id: None,
+                audit,
+                ingest: None,
+            })
+            .await
+            .map_err(to_io)?
+            .map_err(io::Error::other)?
+            .ok_or_else(|| {
+                invalid_input(format!("No peer connection with id: {}", connection_id))
+            });
+    }
+    db.send(NewPeerConnection {
+        ip_addr: "import".to_string(),
+        peer_addr: None,
+        peer_id: args.get("peer-id").map(String::from),
+        name: args.get("name").map(String::from),
+        chain: args.get("chain").map(String::from),
+        version: None,
+        authority: None,
+        startup_time: None,
+        implementation: None,
+        http_ingest: false,
+        ingest_token_id: None,
+        audit,
+    })
+    .await
+    .map_err(to_io)?
+    .map_err(io::Error::other)
+}
+
+/// Import recorded log messages into the DB
+pub async fn run(args: Args) -> io::Result<()> {
+    if args.positional.is_empty() {
+        return Err(invalid_input("No files to import".to_string()));
+    }
+    let format = args
+        .parse_value::<DumpFormat>("format")
+        .map_err(invalid_input)?;
+    let audit = args.is_set("audit");
+
+    let mut logs = Vec::new();
+    let mut skipped = 0;
+    for path in &args.positional {
+        let (file_logs, failed) = read_logs(path, format)?;
+        skipped += failed;
+        for log in file_logs {
+            let (_, log) = unwrap_envelope(log);
+            match log["ts"].as_str().map(DateTime::parse_from_rfc3339) {
+                Some(Ok(ts)) => logs.push((ts, log)),
+                _ => {
+                    warn!("Missing or invalid timestamp, skipping: {}", log);
+                    skipped += 1;
+                }
+            }
+        }
+    }
+    if args.is_set("rewrite-timestamps") {
+        rewrite_timestamps(&mut logs);
+    }
+
+    let pool = db::create_pool();
+    let copy_pool =
+        db::bulk::create_copy_pool().map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let db = SyncArbiter::start(1, move || {
+        DbExecutor::new(pool.clone(), copy_pool.clone(), Metrics::default())
+    });
+
+    let mut peer_connection = peer_connection(&db, &args, audit).await?;
+    let original = peer_connection.clone();
+    for (_, log) in &logs {
+        if peer_connection.peer_id.is_none() || log["msg"].as_str() == Some("system.connected") {
+            update_peer_info(&mut peer_connection, log);
+        }
+    }
+    if peer_connection != original {
+        match db.send(peer_connection.clone()).await {
+            Ok(Ok(())) => (),
+            Ok(Err(e)) => error!("Unable to update peer connection: {}", e),
+            Err(e) => error!("Unable to update peer connection: {:?}", e),
+        }
+    }
+
+    let total = logs.len();
+    let mut logs = logs.into_iter().map(|(ts, logs)| NewSubstrateLog {
+        peer_connection_id: peer_connection.id,
+        created_at: ts.naive_utc(),
+        audit,
+        logs,
+    });
+    let mut result = Ok(());
+    loop {
+        let batch: Vec<NewSubstrateLog> = logs.by_ref().take(*DB_BATCH_SIZE).collect();
+        if batch.is_empty() {
+            break;
+        }
+        match db.send(LogBatch(batch)).await {
+            Ok(Ok(())) => (),
+            Ok(Err(LogBatch(batch))) => {
+                result = Err(io::Error::other(format!(
+                    "DB unavailable, {} logs not imported",
+                    batch.len()
+                )));
+                break;
+            }
+            Err(e) => {
+                result = Err(io::Error::other(e.to_string()));
+                break;
+            }
+        }
+    }
+    // A connection recorded for the import ends with it, otherwise it would be taken as connected
+    if args.get("connection-id").is_none() {
+        let disconnect = RecordDisconnect {
+            peer_connection_ids: vec![peer_connection.id],
+            disconnected_at: Utc::now().naive_utc(),
+            reason: "import finished".to_string(),
+        };
+        match db.send(disconnect).await {
+            Ok(Ok(())) => (),
+            Ok(Err(e)) => error!("Unable to close peer connection: {}", e),
+            Err(e) => error!("Unable to close peer connection: {:?}", e),
+        }
+    }
+    result?;
+    println!(
+        "Imported {} logs for peer connection {} ({} skipped)",
+        total, peer_connection.id, skipped
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_psql_table_test() {
+        let content = "                  logs                  | ?column?\n\
+                       ----------------------------------------+----------\n \
+                       {\"ts\": \"2020-02-14T16:59:34+00:00\", \"msg\": \"a|b\"} | a|b\n \
+                       {\"ts\": \"2020-02-14T16:59:35+00:00\", \"msg\": \"c\"}   | c\n\
+                       (2 rows)\n";
+        assert_eq!(DumpFormat::detect(content), DumpFormat::Psql);
+        let (logs, failed) = DumpFormat::Psql.parse(content);
+        assert_eq!(failed, 0);
+        assert_eq!(logs[0]["msg"], "a|b");
+        assert_eq!(logs[1]["msg"], "c");
+    }
+
+    #[test]
+    fn rewrite_keeps_spacing_test() {
+        let ts = |s| DateTime::parse_from_rfc3339(s).unwrap();
+        let mut logs = vec![
+            (ts("2020-02-14T16:59:30+00:00"), json!({})),
+            (ts("2020-02-14T17:00:00+00:00"), json!({})),
+        ];
+        rewrite_timestamps(&mut logs);
+        assert_eq!((logs[1].0 - logs[0].0).num_seconds(), 30);
+        assert!(Utc::now().signed_duration_since(logs[1].0).num_seconds() < 5);
+        assert!(logs[0].1["ts"].is_string());
+    }
+}
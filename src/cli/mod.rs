@@ -0,0 +1,132 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate Analytics.
+
+// Substrate Analytics is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate Analytics is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
+
+pub mod export;
+pub mod import;
+
+use std::collections::HashMap;
+use std::io;
+
+const USAGE: &str = "Usage:
+    substrate-analytics                 start the server
+    substrate-analytics import [OPTIONS] FILE...
+        --format <json|ndjson|psql>     format of the files, detected from the content if not given
+        --connection-id <ID>            append to an existing peer connection
+        --peer-id <PEER_ID>             record the logs for a new connection with this peer id
+        --name <NAME>                   node name for the new connection
+        --chain <CHAIN>                 chain for the new connection
+        --audit                         save as audit logs, which are not purged
+        --rewrite-timestamps            shift timestamps so that the most recent log is now
+    substrate-analytics export [OPTIONS]
+        --format <ndjson|csv|parquet>   output format, default: ndjson
+        --output <FILE>                 file to write to, default: stdout (required for parquet)
+        --fields <FIELDS>               comma separated log message fields to include as CSV or Parquet columns
+        --start-time <TIME>             eg. 2020-10-01T00:00:00
+        --end-time <TIME>
+        --peer-id <PEER_ID>
+        --msg <MSG>
+        --target <TARGET>
+        --limit <N>";
+
+/// Options given as `--name value` or, for `switches`, just `--name`, and positional arguments
+#[derive(Debug, Default)]
+pub struct Args {
+    options: HashMap<String, String>,
+    switches: Vec<String>,
+    pub positional: Vec<String>,
+}
+
+impl Args {
+    pub fn parse(args: &[String], switches: &[&str]) -> Result<Self, String> {
+        let mut parsed = Args::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some(name) if switches.contains(&name) => parsed.switches.push(name.to_string()),
+                Some(name) => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| format!("Missing value for --{}", name))?;
+                    parsed.options.insert(name.to_string(), value.to_string());
+                }
+                None => parsed.positional.push(arg.to_string()),
+            }
+        }
+        Ok(parsed)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(String::as_str)
+    }
+
+    pub fn parse_value<T: std::str::FromStr>(&self, name: &str) -> Result<Option<T>, String> {
+        match self.get(name) {
+            Some(v) => v
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("Invalid value for --{}: {}", name, v)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn is_set(&self, switch: &str) -> bool {
+        self.switches.iter().any(|s| s == switch)
+    }
+}
+
+fn invalid_input(msg: String) -> io::Error {
+    eprintln!("{}\n\n{}", msg, USAGE);
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+/// Run a subcommand if one was given on the command line, otherwise returns `None` so the server is started
+pub async fn run(args: &[String]) -> Option<io::Result<()>> {
+    let (command, rest) = args.split_first()?;
+    let result = match command.as_str() {
+        "import" => match Args::parse(rest, import::SWITCHES) {
+            Ok(args) => import::run(args).await,
+            Err(e) => Err(invalid_input(e)),
+        },
+        "export" => match Args::parse(rest, export::SWITCHES) {
+            Ok(args) => export::run(args).await,
+            Err(e) => Err(invalid_input(e)),
+        },
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        other => Err(invalid_input(format!("Unknown command: {}", other))),
+    };
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_args_test() {
+        let args: Vec<String> = vec!["--audit", "--peer-id", "Qm1", "a.json", "b.json"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let args = Args::parse(&args, &["audit"]).unwrap();
+        assert!(args.is_set("audit"));
+        assert_eq!(args.get("peer-id"), Some("Qm1"));
+        assert_eq!(args.positional, vec!["a.json", "b.json"]);
+        assert!(Args::parse(&["--peer-id".to_string()], &[]).is_err());
+    }
+}
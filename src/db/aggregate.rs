@@ -0,0 +1,467 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate Analytics.
+
+// Substrate Analytics is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate Analytics is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
+
+use actix::prelude::*;
+use chrono::{NaiveDateTime, Utc};
+use diesel::sql_types::*;
+use diesel::{result::QueryResult, sql_query, RunQueryDsl};
+use failure::Error;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use super::{
+    filters::{max_age_start, Filters},
+    nodes::{field_predicates_sql, predicates_json},
+    DbExecutor,
+};
+
+/// Most buckets returned per series
+pub const MAX_AGGREGATE_BUCKETS: i64 = 10_000;
+/// Window aggregated when no `start_time` or `max_age_s` is given
+const DEFAULT_AGGREGATE_AGE_S: i64 = 3600;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AggregateFunction {
+    Count,
+    Sum,
+    Mean,
+    Min,
+    Max,
+    Stddev,
+    /// Continuous percentile, eg. `p99.9`, stored as the requested percent
+    Percentile(f64),
+}
+
+impl FromStr for AggregateFunction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "count" => Ok(AggregateFunction::Count),
+            "sum" => Ok(AggregateFunction::Sum),
+            "mean" => Ok(AggregateFunction::Mean),
+            "min" => Ok(AggregateFunction::Min),
+            "max" => Ok(AggregateFunction::Max),
+            "stddev" => Ok(AggregateFunction::Stddev),
+            _ => match s.strip_prefix('p').map(str::parse::<f64>) {
+                Some(Ok(p)) if (0.0..=100.0).contains(&p) => Ok(AggregateFunction::Percentile(p)),
+                _ => Err(format!(
+                    "Unknown function `{}`, expected one of count, sum, mean, min, max, stddev \
+                     or a percentile such as p95",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+impl fmt::Display for AggregateFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AggregateFunction::Count => write!(f, "count"),
+            AggregateFunction::Sum => write!(f, "sum"),
+            AggregateFunction::Mean => write!(f, "mean"),
+            AggregateFunction::Min => write!(f, "min"),
+            AggregateFunction::Max => write!(f, "max"),
+            AggregateFunction::Stddev => write!(f, "stddev"),
+            AggregateFunction::Percentile(p) => write!(f, "p{}", p),
+        }
+    }
+}
+
+/// Property of the node connection that series are split by
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GroupBy {
+    PeerId,
+    Chain,
+    Version,
+    Name,
+}
+
+impl GroupBy {
+    fn column(self) -> &'static str {
+        match self {
+            GroupBy::PeerId => "pc.peer_id",
+            GroupBy::Chain => "pc.chain",
+            GroupBy::Version => "pc.version",
+            GroupBy::Name => "pc.name",
+        }
+    }
+}
+
+impl FromStr for GroupBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "peer_id" => Ok(GroupBy::PeerId),
+            "chain" => Ok(GroupBy::Chain),
+            "version" => Ok(GroupBy::Version),
+            "name" => Ok(GroupBy::Name),
+            _ => Err(format!(
+                "Unknown group_by `{}`, expected one of peer_id, chain, version or name",
+                s
+            )),
+        }
+    }
+}
+
+/// Aggregation parameters as they appear in a querystring
+#[derive(Deserialize, Debug, Default)]
+pub struct AggregateParams {
+    pub msg: Option<String>,
+    /// `.` separated path of a numeric field of the log message
+    pub field: Option<String>,
+    pub bucket_s: Option<i64>,
+    pub group_by: Option<String>,
+    /// Comma separated `AggregateFunction`s, default `count`
+    pub functions: Option<String>,
+}
+
+/// Validated aggregation of `field` over logs with the given `msg`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Aggregation {
+    pub msg: String,
+    /// Without a field every log counts as the value `1`, so only `count` is meaningful
+    pub field: Option<Vec<String>>,
+    pub bucket_s: i64,
+    pub group_by: Option<GroupBy>,
+    pub functions: Vec<AggregateFunction>,
+}
+
+impl Aggregation {
+    pub fn from_params(params: AggregateParams) -> Result<Self, String> {
+        let msg = params.msg.ok_or("`msg` is required")?;
+        let field = match params.field {
+            Some(field) => {
+                let path: Vec<String> = field.split('.').map(str::to_owned).collect();
+                if path.iter().any(|s| {
+                    s.is_empty() || !s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                }) {
+                    return Err(format!("Invalid field path `{}`", field));
+                }
+                Some(path)
+            }
+            None => None,
+        };
+        let bucket_s = params.bucket_s.unwrap_or(60);
+        if bucket_s < 1 {
+            return Err("`bucket_s` must be at least 1".into());
+        }
+        let group_by = params.group_by.as_deref().map(str::parse).transpose()?;
+        let mut functions = Vec::new();
+        for f in params.functions.as_deref().unwrap_or("count").split(',') {
+            let f: AggregateFunction = f.trim().parse()?;
+            if !functions.contains(&f) {
+                functions.push(f);
+            }
+        }
+        if field.is_none() && functions.iter().any(|f| *f != AggregateFunction::Count) {
+            return Err("`field` is required for functions other than `count`".into());
+        }
+        Ok(Aggregation {
+            msg,
+            field,
+            bucket_s,
+            group_by,
+            functions,
+        })
+    }
+
+    /// Fractions in `0..=1` for `percentile_cont`
+    fn percentiles(&self) -> Vec<f64> {
+        self.functions
+            .iter()
+            .filter_map(|f| match f {
+                AggregateFunction::Percentile(p) => Some(p / 100.0),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct AggregatePoint {
+    pub time: NaiveDateTime,
+    /// Value of each requested function, keyed by its name, eg. `mean` or `p95`
+    #[serde(flatten)]
+    pub values: Map<String, Value>,
+}
+
+/// Time bucketed values for one group, or all logs if there is no `group_by`
+#[derive(Serialize, Debug, PartialEq)]
+pub struct AggregateSeries {
+    pub group: Option<String>,
+    pub points: Vec<AggregatePoint>,
+}
+
+#[derive(QueryableByName, Debug)]
+struct AggregateRow {
+    #[sql_type = "Timestamp"]
+    bucket: NaiveDateTime,
+    #[sql_type = "Nullable<Text>"]
+    group_key: Option<String>,
+    #[sql_type = "BigInt"]
+    count: i64,
+    #[sql_type = "Nullable<Double>"]
+    sum: Option<f64>,
+    #[sql_type = "Nullable<Double>"]
+    mean: Option<f64>,
+    #[sql_type = "Nullable<Double>"]
+    min: Option<f64>,
+    #[sql_type = "Nullable<Double>"]
+    max: Option<f64>,
+    #[sql_type = "Nullable<Double>"]
+    stddev: Option<f64>,
+    #[sql_type = "Nullable<Array<Double>>"]
+    percentiles: Option<Vec<f64>>,
+}
+
+/// Aggregation over a resolved time range, see `AggregateQuery::new`
+pub struct AggregateQuery {
+    filters: Filters,
+    aggregation: Aggregation,
+    start_time: NaiveDateTime,
+    end_time: NaiveDateTime,
+}
+
+impl AggregateQuery {
+    /// Resolve the time range of `filters`, checking it doesn't have too many buckets
+    pub fn new(filters: Filters, aggregation: Aggregation) -> Result<Self, String> {
+        let end_time = filters.end_time.unwrap_or_else(|| Utc::now().naive_utc());
+        let start_time = match filters.start_time {
+            Some(start_time) => start_time,
+            None => max_age_start(
+                end_time,
+                filters.max_age_s.unwrap_or(DEFAULT_AGGREGATE_AGE_S),
+            )?,
+        };
+        let buckets = (end_time - start_time).num_seconds() / aggregation.bucket_s;
+        if buckets > MAX_AGGREGATE_BUCKETS {
+            return Err(format!(
+                "Time range would have {} buckets, no more than {} are allowed",
+                buckets, MAX_AGGREGATE_BUCKETS
+            ));
+        }
+        Ok(AggregateQuery {
+            filters,
+            aggregation,
+            start_time,
+            end_time,
+        })
+    }
+
+    pub fn aggregation(&self) -> &Aggregation {
+        &self.aggregation
+    }
+}
+
+impl Message for AggregateQuery {
+    type Result = Result<Vec<AggregateSeries>, Error>;
+}
+
+impl Handler<AggregateQuery> for DbExecutor {
+    type Result = Result<Vec<AggregateSeries>, Error>;
+
+    fn handle(&mut self, msg: AggregateQuery, _: &mut Self::Context) -> Self::Result {
+        self.aggregate(msg)
+    }
+}
+
+impl DbExecutor {
+    fn aggregate(&self, query: AggregateQuery) -> Result<Vec<AggregateSeries>, Error> {
+        let AggregateQuery {
+            filters,
+            aggregation,
+            start_time,
+            end_time,
+        } = query;
+        let percentiles = aggregation.percentiles();
+        let group = aggregation.group_by.map_or("NULL::TEXT", GroupBy::column);
+        match self.with_connection(|conn| {
+            let query = sql_query(format!(
+                "SELECT (to_timestamp(floor(extract(epoch FROM created_at) / $6) * $6) \
+                 AT TIME ZONE 'UTC') AS bucket, \
+                 group_key, \
+                 count(v) AS count, \
+                 sum(v) AS sum, \
+                 avg(v) AS mean, \
+                 min(v) AS min, \
+                 max(v) AS max, \
+                 stddev_samp(v) AS stddev, \
+                 percentile_cont($7) WITHIN GROUP (ORDER BY v) AS percentiles \
+                 FROM (SELECT sl.created_at, \
+                 {} AS group_key, \
+                 CASE WHEN $5::TEXT[] IS NULL THEN 1 \
+                 WHEN jsonb_typeof(sl.logs #> $5) = 'number' THEN (sl.logs #>> $5)::FLOAT8 \
+                 END AS v \
+                 FROM substrate_logs sl \
+                 LEFT JOIN peer_connections pc ON sl.peer_connection_id = pc.id \
+                 WHERE sl.logs->>'msg' = $1 \
+                 AND sl.created_at >= $2 \
+                 AND sl.created_at < $3 \
+                 AND ($4::TEXT IS NULL OR pc.peer_id = $4) \
+                 AND {}) t \
+                 GROUP BY bucket, group_key \
+                 ORDER BY group_key, bucket",
+                group,
+                field_predicates_sql(8)
+            ))
+            .bind::<Text, _>(aggregation.msg.clone())
+            .bind::<Timestamp, _>(start_time)
+            .bind::<Timestamp, _>(end_time)
+            .bind::<Nullable<Text>, _>(filters.peer_id)
+            .bind::<Nullable<Array<Text>>, _>(aggregation.field.clone())
+            .bind::<Double, _>(aggregation.bucket_s as f64)
+            .bind::<Array<Double>, _>(percentiles)
+            .bind::<Jsonb, _>(predicates_json(&filters.predicates));
+            debug!(
+                "aggregate query: {}",
+                diesel::debug_query::<diesel::pg::Pg, _>(&query)
+            );
+            let result: QueryResult<Vec<AggregateRow>> = query.get_results(conn);
+            result
+        }) {
+            Ok(Ok(rows)) => Ok(into_series(rows, &aggregation.functions)),
+            Ok(Err(e)) => Err(e.into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+fn into_series(rows: Vec<AggregateRow>, functions: &[AggregateFunction]) -> Vec<AggregateSeries> {
+    let mut series: BTreeMap<Option<String>, Vec<AggregatePoint>> = BTreeMap::new();
+    for mut row in rows {
+        let mut percentiles = row.percentiles.take().unwrap_or_default().into_iter();
+        let values = functions
+            .iter()
+            .map(|f| {
+                let value = match f {
+                    AggregateFunction::Count => json!(row.count),
+                    AggregateFunction::Sum => json!(row.sum),
+                    AggregateFunction::Mean => json!(row.mean),
+                    AggregateFunction::Min => json!(row.min),
+                    AggregateFunction::Max => json!(row.max),
+                    AggregateFunction::Stddev => json!(row.stddev),
+                    AggregateFunction::Percentile(_) => json!(percentiles.next()),
+                };
+                (f.to_string(), value)
+            })
+            .collect();
+        series
+            .entry(row.group_key)
+            .or_default()
+            .push(AggregatePoint {
+                time: row.bucket,
+                values,
+            });
+    }
+    series
+        .into_iter()
+        .map(|(group, points)| AggregateSeries { group, points })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_aggregation_test() {
+        let aggregation = Aggregation::from_params(AggregateParams {
+            msg: Some("system.interval".into()),
+            field: Some("state.peers".into()),
+            bucket_s: None,
+            group_by: Some("chain".into()),
+            functions: Some("mean,p99.9,count,mean".into()),
+        })
+        .unwrap();
+        assert_eq!(
+            aggregation.field,
+            Some(vec!["state".into(), "peers".into()])
+        );
+        assert_eq!(aggregation.bucket_s, 60);
+        assert_eq!(aggregation.group_by, Some(GroupBy::Chain));
+        assert_eq!(
+            aggregation.functions,
+            vec![
+                AggregateFunction::Mean,
+                AggregateFunction::Percentile(99.9),
+                AggregateFunction::Count
+            ]
+        );
+        assert_eq!(aggregation.percentiles(), vec![99.9 / 100.0]);
+        for function in &["p95", "p57", "p99.9"] {
+            assert_eq!(
+                function.parse::<AggregateFunction>().unwrap().to_string(),
+                *function
+            );
+        }
+
+        let invalid = |params: AggregateParams| Aggregation::from_params(params).is_err();
+        assert!(invalid(AggregateParams::default()));
+        let params = || AggregateParams {
+            msg: Some("system.interval".into()),
+            ..Default::default()
+        };
+        assert!(!invalid(params()));
+        assert!(invalid(AggregateParams {
+            functions: Some("mean".into()),
+            ..params()
+        }));
+        assert!(invalid(AggregateParams {
+            field: Some("a.".into()),
+            ..params()
+        }));
+        assert!(invalid(AggregateParams {
+            functions: Some("p101".into()),
+            ..params()
+        }));
+        assert!(invalid(AggregateParams {
+            bucket_s: Some(0),
+            ..params()
+        }));
+        assert!(invalid(AggregateParams {
+            group_by: Some("ip_addr".into()),
+            ..params()
+        }));
+    }
+
+    #[test]
+    fn aggregate_query_checks_time_range_test() {
+        let aggregation = || {
+            Aggregation::from_params(AggregateParams {
+                msg: Some("system.interval".into()),
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        let query = |max_age_s| {
+            AggregateQuery::new(
+                Filters {
+                    max_age_s: Some(max_age_s),
+                    ..Default::default()
+                },
+                aggregation(),
+            )
+        };
+        assert!(query(3600).is_ok());
+        assert!(query(60 * (MAX_AGGREGATE_BUCKETS + 1)).is_err());
+        assert!(query(i64::MAX).is_err());
+    }
+}
@@ -14,7 +14,10 @@
 // You should have received a copy of the GNU General Public License
 // along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
 
-use super::models::{Benchmark, BenchmarkEvent, NewBenchmark, NewBenchmarkEvent};
+use super::host_systems::{setup_host_system_id, share_host_system};
+use super::lifecycle::{lock_benchmark, transition, BenchmarkState, BenchmarkUpdate};
+use super::models::{Benchmark, BenchmarkEvent, HostSystem, NewBenchmark, NewBenchmarkEvent};
+use super::pins::{pin_benchmark_logs, unpin_benchmark_logs};
 use super::DbExecutor;
 use crate::db::filters::Filters;
 use actix::prelude::*;
@@ -23,6 +26,13 @@ use diesel::sql_query;
 use diesel::sql_types::*;
 use failure::Error;
 use serde_json::Value;
+use std::collections::HashMap;
+
+pub enum BenchmarkCreation {
+    Created(Benchmark),
+    /// The host system the setup refers to doesn't exist
+    UnknownHostSystem(i32),
+}
 
 #[derive(Serialize, Deserialize, Debug, QueryableByName)]
 pub struct Targets {
@@ -30,6 +40,14 @@ pub struct Targets {
     target: String,
 }
 
+/// Benchmark with the host system its setup refers to
+#[derive(Serialize, Debug)]
+pub struct BenchmarkListing {
+    #[serde(flatten)]
+    pub benchmark: Benchmark,
+    pub host_system: Option<HostSystem>,
+}
+
 pub enum Query {
     All(Filters),
     /// Targets for benchmark id
@@ -55,11 +73,11 @@ impl Handler<Query> for DbExecutor {
 }
 
 impl Message for NewBenchmark {
-    type Result = Result<Value, Error>;
+    type Result = Result<BenchmarkCreation, Error>;
 }
 
 impl Handler<NewBenchmark> for DbExecutor {
-    type Result = Result<Value, Error>;
+    type Result = Result<BenchmarkCreation, Error>;
 
     fn handle(&mut self, msg: NewBenchmark, _: &mut Self::Context) -> Self::Result {
         self.save_benchmark(msg)
@@ -67,22 +85,66 @@ impl Handler<NewBenchmark> for DbExecutor {
 }
 
 impl Message for NewBenchmarkEvent {
-    type Result = Result<Value, Error>;
+    type Result = Result<BenchmarkUpdate<BenchmarkEvent>, Error>;
 }
 
 impl Handler<NewBenchmarkEvent> for DbExecutor {
-    type Result = Result<Value, Error>;
+    type Result = Result<BenchmarkUpdate<BenchmarkEvent>, Error>;
 
     fn handle(&mut self, msg: NewBenchmarkEvent, _: &mut Self::Context) -> Self::Result {
         self.save_benchmark_event(msg)
     }
 }
 
+/// Delete a benchmark with its events, unpinning its logs. Returns `None` if it doesn't exist.
+pub struct DeleteBenchmark(pub i32);
+
+impl Message for DeleteBenchmark {
+    type Result = Result<Option<Benchmark>, Error>;
+}
+
+impl Handler<DeleteBenchmark> for DbExecutor {
+    type Result = Result<Option<Benchmark>, Error>;
+
+    fn handle(&mut self, msg: DeleteBenchmark, _: &mut Self::Context) -> Self::Result {
+        self.delete_benchmark(msg.0)
+    }
+}
+
 impl DbExecutor {
     fn get_benchmarks(&self, _filters: Filters) -> Result<Value, Error> {
         match self.with_connection(|conn| {
-            use crate::schema::benchmarks::dsl::*;
-            benchmarks.order(created_at.desc()).load::<Benchmark>(conn)
+            conn.transaction::<_, diesel::result::Error, _>(|| {
+                let bms = {
+                    use crate::schema::benchmarks::dsl::*;
+                    benchmarks
+                        .order(created_at.desc())
+                        .load::<Benchmark>(conn)?
+                };
+                let host_ids: Vec<i32> = bms
+                    .iter()
+                    .filter_map(|bm| setup_host_system_id(&bm.setup).ok().flatten())
+                    .collect();
+                let hosts: HashMap<i32, HostSystem> = {
+                    use crate::schema::host_systems::dsl::*;
+                    host_systems
+                        .filter(id.eq_any(host_ids))
+                        .load::<HostSystem>(conn)?
+                        .into_iter()
+                        .map(|h| (h.id, h))
+                        .collect()
+                };
+                Ok(bms
+                    .into_iter()
+                    .map(|benchmark| BenchmarkListing {
+                        host_system: setup_host_system_id(&benchmark.setup)
+                            .ok()
+                            .flatten()
+                            .and_then(|host_id| hosts.get(&host_id).cloned()),
+                        benchmark,
+                    })
+                    .collect::<Vec<_>>())
+            })
         }) {
             Ok(Ok(v)) => Ok(json!(v)),
             Ok(Err(e)) => Err(e.into()),
@@ -128,27 +190,87 @@ impl DbExecutor {
         }
     }
 
-    fn save_benchmark(&self, msg: NewBenchmark) -> Result<Value, Error> {
+    fn save_benchmark(&self, msg: NewBenchmark) -> Result<BenchmarkCreation, Error> {
         match self.with_connection(|conn| {
-            use crate::schema::benchmarks;
-            diesel::insert_into(benchmarks::table)
-                .values(msg)
-                .get_result::<Benchmark>(conn)
+            conn.transaction::<_, diesel::result::Error, _>(|| {
+                // The host system is kept until the benchmark referring to it is committed
+                if let Ok(Some(host_id)) = setup_host_system_id(&msg.setup) {
+                    if share_host_system(conn, host_id)?.is_none() {
+                        return Ok(BenchmarkCreation::UnknownHostSystem(host_id));
+                    }
+                }
+                use crate::schema::benchmarks;
+                let benchmark = diesel::insert_into(benchmarks::table)
+                    .values(msg)
+                    .get_result::<Benchmark>(conn)?;
+                pin_benchmark_logs(conn, &benchmark)?;
+                Ok(BenchmarkCreation::Created(benchmark))
+            })
         }) {
-            Ok(Ok(v)) => Ok(json!(v)),
+            Ok(Ok(v)) => Ok(v),
             Ok(Err(e)) => Err(e.into()),
             Err(e) => Err(e.into()),
         }
     }
 
-    fn save_benchmark_event(&self, msg: NewBenchmarkEvent) -> Result<Value, Error> {
+    fn delete_benchmark(&self, bm_id: i32) -> Result<Option<Benchmark>, Error> {
         match self.with_connection(|conn| {
-            use crate::schema::benchmark_events;
-            diesel::insert_into(benchmark_events::table)
-                .values(msg)
-                .get_result::<BenchmarkEvent>(conn)
+            conn.transaction::<_, diesel::result::Error, _>(|| {
+                if lock_benchmark(conn, bm_id)?.is_none() {
+                    return Ok(None);
+                }
+                let unpinned = unpin_benchmark_logs(conn, bm_id)?;
+                debug!("Unpinned {} log ranges of benchmark {}", unpinned, bm_id);
+                {
+                    use crate::schema::benchmark_events::dsl::*;
+                    diesel::delete(benchmark_events.filter(benchmark_id.eq(bm_id)))
+                        .execute(conn)?;
+                }
+                use crate::schema::benchmarks::dsl::*;
+                diesel::delete(benchmarks.filter(id.eq(bm_id)))
+                    .get_result::<Benchmark>(conn)
+                    .optional()
+            })
         }) {
-            Ok(Ok(v)) => Ok(json!(v)),
+            Ok(Ok(v)) => Ok(v),
+            Ok(Err(e)) => Err(e.into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Save an event of a running benchmark, stopping the benchmark if it is a lifecycle event
+    fn save_benchmark_event(
+        &self,
+        msg: NewBenchmarkEvent,
+    ) -> Result<BenchmarkUpdate<BenchmarkEvent>, Error> {
+        match self.with_connection(|conn| {
+            conn.transaction::<_, diesel::result::Error, _>(|| {
+                let benchmark = match lock_benchmark(conn, msg.benchmark_id)? {
+                    Some(b) => b,
+                    None => return Ok(BenchmarkUpdate::NotFound),
+                };
+                if benchmark.state != BenchmarkState::Running.to_string() {
+                    return Ok(BenchmarkUpdate::Conflict(format!(
+                        "Benchmark {} is {}, only running benchmarks accept events",
+                        benchmark.id, benchmark.state
+                    )));
+                }
+                let next = BenchmarkState::from_event(&msg.name, &msg.phase);
+                let at = msg.created_at;
+                let event = {
+                    use crate::schema::benchmark_events;
+                    diesel::insert_into(benchmark_events::table)
+                        .values(msg)
+                        .get_result::<BenchmarkEvent>(conn)?
+                };
+                if let Some(next) = next {
+                    // A running benchmark can always be stopped
+                    transition(conn, benchmark, next, at)?;
+                }
+                Ok(BenchmarkUpdate::Saved(event))
+            })
+        }) {
+            Ok(Ok(v)) => Ok(v),
             Ok(Err(e)) => Err(e.into()),
             Err(e) => Err(e.into()),
         }
@@ -0,0 +1,345 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate Analytics.
+
+// Substrate Analytics is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate Analytics is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
+
+use openssl::ssl::{SslConnector, SslFiletype, SslMethod, SslVerifyMode};
+use postgres_openssl::MakeTlsConnector;
+use r2d2_postgres::postgres::config::SslMode;
+use r2d2_postgres::postgres::Config;
+use r2d2_postgres::r2d2::Pool;
+use r2d2_postgres::PostgresConnectionManager;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use super::models::NewSubstrateLog;
+use super::DbExecutor;
+use crate::{DATABASE_URL, DB_COPY_POOL_SIZE, DB_COPY_RETRIES};
+
+pub type CopyPool = Pool<PostgresConnectionManager<MakeTlsConnector>>;
+
+const COPY_STATEMENT: &str =
+    "COPY substrate_logs (logs, peer_connection_id, created_at, audit) FROM STDIN";
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Result of writing a `LogBatch` to the DB
+#[derive(Default, Debug, PartialEq)]
+pub struct BatchOutcome {
+    pub written: u64,
+    pub rejected: u64,
+    pub duration: Duration,
+}
+
+/// The DB couldn't be reached while writing a `LogBatch`
+pub struct CopyFailure {
+    pub error: String,
+    /// Logs handled before the connection was lost, which are always the first
+    /// `written + rejected` logs of the batch
+    pub outcome: BatchOutcome,
+}
+
+enum CopyError {
+    /// Unable to reach the DB, retrying or splitting the batch won't help
+    Connection(String),
+    /// The DB rejected the data, there may be bad rows in the batch
+    Data(String),
+}
+
+impl DbExecutor {
+    /// Write logs using `COPY ... FROM STDIN`
+    ///
+    /// A failed batch is retried up to `DB_COPY_RETRIES` times, after which it is repeatedly
+    /// split in half to isolate and reject the rows that the DB refuses.
+    /// Returns `Err` if the DB can't be reached, with the logs that were handled before then.
+    pub fn copy_logs(&self, logs: &[NewSubstrateLog]) -> Result<BatchOutcome, CopyFailure> {
+        let start = Instant::now();
+        let rows: Vec<String> = logs.iter().map(copy_row).collect();
+        let mut outcome = BatchOutcome::default();
+        let mut attempt = 0;
+        loop {
+            match self.copy_rows(&rows) {
+                Ok(n) => {
+                    outcome.written = n;
+                    break;
+                }
+                Err(CopyError::Connection(e)) | Err(CopyError::Data(e))
+                    if attempt < *DB_COPY_RETRIES =>
+                {
+                    warn!("Unable to COPY logs (attempt {}): {}", attempt + 1, e);
+                }
+                Err(CopyError::Connection(error)) => {
+                    outcome.duration = start.elapsed();
+                    return Err(CopyFailure { error, outcome });
+                }
+                Err(CopyError::Data(_)) => {
+                    if let Err(error) = self.copy_split(&rows, &mut outcome) {
+                        outcome.duration = start.elapsed();
+                        return Err(CopyFailure { error, outcome });
+                    }
+                    break;
+                }
+            }
+            attempt += 1;
+            std::thread::sleep(RETRY_DELAY * attempt);
+        }
+        outcome.duration = start.elapsed();
+        Ok(outcome)
+    }
+
+    /// Rows are handled in order, so when the connection is lost the rows that haven't been
+    /// written or rejected are the end of `rows`
+    fn copy_split(&self, rows: &[String], outcome: &mut BatchOutcome) -> Result<(), String> {
+        if rows.len() == 1 {
+            error!("Rejected log row: {}", rows[0]);
+            outcome.rejected += 1;
+            return Ok(());
+        }
+        let (left, right) = rows.split_at(rows.len() / 2);
+        for half in [left, right].iter() {
+            match self.copy_rows(half) {
+                Ok(n) => outcome.written += n,
+                Err(CopyError::Data(_)) => self.copy_split(half, outcome)?,
+                Err(CopyError::Connection(e)) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn copy_rows(&self, rows: &[String]) -> Result<u64, CopyError> {
+        let mut client = self
+            .copy_pool
+            .get()
+            .map_err(|e| CopyError::Connection(e.to_string()))?;
+        let mut writer = client
+            .copy_in(COPY_STATEMENT)
+            .map_err(|e| CopyError::Connection(e.to_string()))?;
+        for row in rows {
+            writer
+                .write_all(row.as_bytes())
+                .map_err(|e| CopyError::Connection(e.to_string()))?;
+        }
+        writer.finish().map_err(|e| {
+            if e.code().is_some() {
+                CopyError::Data(e.to_string())
+            } else {
+                CopyError::Connection(e.to_string())
+            }
+        })
+    }
+}
+
+/// Single line of `COPY` text format for `COPY_STATEMENT`
+fn copy_row(log: &NewSubstrateLog) -> String {
+    format!(
+        "{}\t{}\t{}\t{}\n",
+        escape_copy_text(&log.logs.to_string()),
+        log.peer_connection_id,
+        log.created_at,
+        if log.audit { "t" } else { "f" }
+    )
+}
+
+fn escape_copy_text(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Connection parameters `tokio_postgres` understands, other libpq parameters are left out of
+/// the COPY pool's configuration
+const COPY_POOL_PARAMS: &[&str] = &[
+    "user",
+    "password",
+    "dbname",
+    "options",
+    "application_name",
+    "host",
+    "port",
+    "connect_timeout",
+    "keepalives",
+    "keepalives_idle",
+    "target_session_attrs",
+    "channel_binding",
+];
+
+/// TLS parameters of a libpq connection string, which `tokio_postgres` doesn't handle itself
+#[derive(Default, Debug, PartialEq)]
+struct TlsParams {
+    sslmode: Option<String>,
+    sslrootcert: Option<String>,
+    sslcert: Option<String>,
+    sslkey: Option<String>,
+}
+
+/// Split a libpq connection string, either a URL or `key=value` pairs, into a connection string
+/// `tokio_postgres` can parse and its TLS parameters
+fn split_tls_params(url: &str) -> (String, TlsParams) {
+    let mut tls = TlsParams::default();
+    let mut keep = |param: &&str| {
+        let mut kv = param.splitn(2, '=');
+        let (key, value) = (kv.next().unwrap_or(""), kv.next().unwrap_or(""));
+        match key {
+            "sslmode" => tls.sslmode = Some(value.to_string()),
+            "sslrootcert" => tls.sslrootcert = Some(value.to_string()),
+            "sslcert" => tls.sslcert = Some(value.to_string()),
+            "sslkey" => tls.sslkey = Some(value.to_string()),
+            key if COPY_POOL_PARAMS.contains(&key) => return true,
+            key => warn!("Ignoring connection parameter for the COPY pool: {}", key),
+        }
+        false
+    };
+    let config = if url.starts_with("postgres://") || url.starts_with("postgresql://") {
+        match url.find('?') {
+            Some(i) => {
+                let params: Vec<&str> = url[i + 1..].split('&').filter(|p| keep(p)).collect();
+                if params.is_empty() {
+                    url[..i].to_string()
+                } else {
+                    format!("{}?{}", &url[..i], params.join("&"))
+                }
+            }
+            None => url.to_string(),
+        }
+    } else {
+        let params: Vec<&str> = url.split_whitespace().filter(|p| keep(p)).collect();
+        params.join(" ")
+    };
+    (config, tls)
+}
+
+/// TLS connector following libpq's `sslmode`: the server certificate is only verified for
+/// `verify-ca` and `verify-full`, or for `require` when `sslrootcert` is given, and its host
+/// name only for `verify-full`
+fn tls_connector(tls: &TlsParams) -> Result<MakeTlsConnector, String> {
+    let mut builder = SslConnector::builder(SslMethod::tls())
+        .map_err(|e| format!("Unable to create TLS connector: {}", e))?;
+    if let Some(path) = &tls.sslrootcert {
+        builder
+            .set_ca_file(path)
+            .map_err(|e| format!("Unable to load sslrootcert {}: {}", path, e))?;
+    }
+    if let Some(path) = &tls.sslcert {
+        builder
+            .set_certificate_chain_file(path)
+            .map_err(|e| format!("Unable to load sslcert {}: {}", path, e))?;
+    }
+    if let Some(path) = &tls.sslkey {
+        builder
+            .set_private_key_file(path, SslFiletype::PEM)
+            .map_err(|e| format!("Unable to load sslkey {}: {}", path, e))?;
+    }
+    let (verify, verify_hostname) = match tls.sslmode.as_deref() {
+        Some("verify-full") => (true, true),
+        Some("verify-ca") => (true, false),
+        Some("require") => (tls.sslrootcert.is_some(), false),
+        _ => (false, false),
+    };
+    builder.set_verify(if verify {
+        SslVerifyMode::PEER
+    } else {
+        SslVerifyMode::NONE
+    });
+    let mut connector = MakeTlsConnector::new(builder.build());
+    connector.set_callback(move |config, _| {
+        config.set_verify_hostname(verify_hostname);
+        Ok(())
+    });
+    Ok(connector)
+}
+
+fn ssl_mode(sslmode: Option<&str>) -> Result<SslMode, String> {
+    match sslmode {
+        Some("disable") => Ok(SslMode::Disable),
+        None | Some("allow") | Some("prefer") => Ok(SslMode::Prefer),
+        Some("require") | Some("verify-ca") | Some("verify-full") => Ok(SslMode::Require),
+        Some(mode) => Err(format!("Invalid sslmode in DATABASE_URL: {}", mode)),
+    }
+}
+
+/// Pool of `postgres` connections for `COPY`, connecting to `DATABASE_URL` with the same TLS
+/// settings libpq uses for the diesel pool
+pub fn create_copy_pool() -> Result<CopyPool, String> {
+    let (url, tls) = split_tls_params(&DATABASE_URL);
+    let mut config: Config = url
+        .parse()
+        .map_err(|e| format!("Invalid DATABASE_URL for the COPY pool: {}", e))?;
+    config.ssl_mode(ssl_mode(tls.sslmode.as_deref())?);
+    let manager = PostgresConnectionManager::new(config, tls_connector(&tls)?);
+    let pool = Pool::builder()
+        .max_size(*DB_COPY_POOL_SIZE)
+        .min_idle(Some(0))
+        .build(manager)
+        .map_err(|e| format!("Failed to create COPY pool: {}", e))?;
+    info!(
+        "Database COPY pool created with {} connections",
+        *DB_COPY_POOL_SIZE
+    );
+    Ok(pool)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDateTime;
+
+    #[test]
+    fn copy_row_test() {
+        let log = NewSubstrateLog {
+            logs: json!({"msg": "system.connected", "name": "tab\there \"quoted\""}),
+            peer_connection_id: 7,
+            created_at: NaiveDateTime::from_timestamp(1_600_000_000, 0),
+            audit: true,
+        };
+        assert_eq!(
+            copy_row(&log),
+            "{\"msg\":\"system.connected\",\"name\":\"tab\\\\there \\\\\"quoted\\\\\"\"}\
+             \t7\t2020-09-13 12:26:40\tt\n"
+        );
+    }
+
+    #[test]
+    fn split_tls_params_test() {
+        let (url, tls) = split_tls_params(
+            "postgres://user:pass@db:5432/analytics?sslmode=verify-full&sslrootcert=/ca.pem\
+             &connect_timeout=5&gssencmode=disable",
+        );
+        assert_eq!(
+            url,
+            "postgres://user:pass@db:5432/analytics?connect_timeout=5"
+        );
+        assert_eq!(
+            tls,
+            TlsParams {
+                sslmode: Some("verify-full".to_string()),
+                sslrootcert: Some("/ca.pem".to_string()),
+                ..Default::default()
+            }
+        );
+        let (url, tls) = split_tls_params("host=db dbname=analytics sslmode=require");
+        assert_eq!(url, "host=db dbname=analytics");
+        assert_eq!(tls.sslmode.as_deref(), Some("require"));
+        assert_eq!(
+            split_tls_params("postgres://db/analytics?sslmode=disable").0,
+            "postgres://db/analytics"
+        );
+    }
+}
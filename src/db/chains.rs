@@ -0,0 +1,456 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate Analytics.
+
+// Substrate Analytics is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate Analytics is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
+
+use actix::prelude::*;
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::sql_types::*;
+use diesel::{result::QueryResult, sql_query, RunQueryDsl};
+use failure::Error;
+use std::collections::BTreeMap;
+
+use super::{filters::max_age_start, DbExecutor};
+
+/// Length of the history when no `start_time` or `max_age_s` is given
+const DEFAULT_HISTORY_AGE_S: i64 = 86_400;
+/// Interval between samples of the history when no `bucket_s` is given
+const DEFAULT_HISTORY_BUCKET_S: i64 = 3_600;
+/// Each sample is a separate lookup of the sessions alive at that time
+pub const MAX_HISTORY_SAMPLES: i64 = 1_000;
+
+/// Latest session of a node currently connected
+#[derive(Debug, Clone, QueryableByName)]
+struct ChainNode {
+    #[sql_type = "Text"]
+    chain: String,
+    #[sql_type = "Nullable<Text>"]
+    version: Option<String>,
+    #[sql_type = "Nullable<Text>"]
+    implementation: Option<String>,
+    #[sql_type = "Nullable<Bool>"]
+    authority: Option<bool>,
+    #[sql_type = "Nullable<Text>"]
+    name: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct VersionCount {
+    pub version: Option<String>,
+    pub implementation: Option<String>,
+    pub nodes: i64,
+    pub authorities: i64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ChainInventory {
+    pub chain: String,
+    pub nodes: i64,
+    pub authorities: i64,
+    pub full_nodes: i64,
+    pub versions: Vec<VersionCount>,
+    /// Number of nodes running each implementation
+    pub implementations: BTreeMap<String, i64>,
+    pub names: Vec<String>,
+}
+
+/// Chains with connected nodes, optionally only `chain`
+pub struct ChainsQuery {
+    pub chain: Option<String>,
+}
+
+impl Message for ChainsQuery {
+    type Result = Result<Vec<ChainInventory>, Error>;
+}
+
+impl Handler<ChainsQuery> for DbExecutor {
+    type Result = Result<Vec<ChainInventory>, Error>;
+
+    fn handle(&mut self, msg: ChainsQuery, _: &mut Self::Context) -> Self::Result {
+        self.get_chains(msg)
+    }
+}
+
+/// Row of the history, the nodes on a chain running a version at a sample time
+#[derive(Debug, Clone, QueryableByName)]
+struct VersionSample {
+    #[sql_type = "Timestamp"]
+    time: NaiveDateTime,
+    #[sql_type = "Text"]
+    chain: String,
+    #[sql_type = "Nullable<Text>"]
+    version: Option<String>,
+    #[sql_type = "Nullable<Text>"]
+    implementation: Option<String>,
+    #[sql_type = "BigInt"]
+    nodes: i64,
+    #[sql_type = "BigInt"]
+    authorities: i64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ChainHistoryPoint {
+    pub time: NaiveDateTime,
+    pub nodes: i64,
+    pub authorities: i64,
+    pub versions: Vec<VersionCount>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ChainHistory {
+    pub chain: String,
+    pub points: Vec<ChainHistoryPoint>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct ChainParams {
+    pub chain: Option<String>,
+    pub bucket_s: Option<i64>,
+}
+
+/// Nodes connected to each chain sampled every `bucket_s` over a resolved time range,
+/// see `ChainHistoryQuery::new`
+pub struct ChainHistoryQuery {
+    chain: Option<String>,
+    start_time: NaiveDateTime,
+    end_time: NaiveDateTime,
+    bucket_s: i64,
+}
+
+impl ChainHistoryQuery {
+    /// Resolve the time range, checking it doesn't have too many samples
+    pub fn new(
+        params: ChainParams,
+        start_time: Option<NaiveDateTime>,
+        end_time: Option<NaiveDateTime>,
+        max_age_s: Option<i64>,
+    ) -> Result<Self, String> {
+        let bucket_s = params.bucket_s.unwrap_or(DEFAULT_HISTORY_BUCKET_S);
+        if bucket_s < 1 {
+            return Err("bucket_s must be at least 1".to_string());
+        }
+        let end_time = end_time.unwrap_or_else(|| Utc::now().naive_utc());
+        let start_time = match start_time {
+            Some(start_time) => start_time,
+            None => max_age_start(end_time, max_age_s.unwrap_or(DEFAULT_HISTORY_AGE_S))?,
+        };
+        let range_s = (end_time - start_time).num_seconds();
+        if params.bucket_s.is_some() && bucket_s > range_s.max(1) {
+            return Err("bucket_s must not be longer than the time range".to_string());
+        }
+        let samples = range_s / bucket_s + 1;
+        if samples > MAX_HISTORY_SAMPLES {
+            return Err(format!(
+                "Time range would have {} samples, no more than {} are allowed",
+                samples, MAX_HISTORY_SAMPLES
+            ));
+        }
+        Ok(ChainHistoryQuery {
+            chain: params.chain,
+            start_time,
+            end_time,
+            bucket_s,
+        })
+    }
+
+    /// Times the sessions are sampled at, the same as `generate_series` gives
+    fn sample_times(&self) -> Vec<NaiveDateTime> {
+        let mut times = Vec::new();
+        let step = Duration::seconds(self.bucket_s);
+        let mut t = Some(self.start_time);
+        while let Some(time) = t.filter(|time| *time <= self.end_time) {
+            times.push(time);
+            t = time.checked_add_signed(step);
+        }
+        times
+    }
+}
+
+impl Message for ChainHistoryQuery {
+    type Result = Result<Vec<ChainHistory>, Error>;
+}
+
+impl Handler<ChainHistoryQuery> for DbExecutor {
+    type Result = Result<Vec<ChainHistory>, Error>;
+
+    fn handle(&mut self, msg: ChainHistoryQuery, _: &mut Self::Context) -> Self::Result {
+        self.get_chain_history(msg)
+    }
+}
+
+impl DbExecutor {
+    fn get_chains(&self, msg: ChainsQuery) -> Result<Vec<ChainInventory>, Error> {
+        match self.with_connection(|conn| {
+            let query = sql_query(
+                "SELECT DISTINCT ON (peer_id) chain, version, implementation, authority, name \
+                 FROM peer_connections \
+                 WHERE peer_id IS NOT NULL \
+                 AND chain IS NOT NULL \
+                 AND disconnected_at IS NULL \
+                 AND ($1::TEXT IS NULL OR chain = $1) \
+                 ORDER BY peer_id, created_at DESC",
+            )
+            .bind::<Nullable<Text>, _>(msg.chain);
+            debug!(
+                "get_chains query: {}",
+                diesel::debug_query::<diesel::pg::Pg, _>(&query)
+            );
+            let result: QueryResult<Vec<ChainNode>> = query.get_results(conn);
+            result
+        }) {
+            Ok(Ok(nodes)) => Ok(inventory(nodes)),
+            Ok(Err(e)) => Err(e.into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn get_chain_history(&self, msg: ChainHistoryQuery) -> Result<Vec<ChainHistory>, Error> {
+        match self.with_connection(|conn| {
+            let query = sql_query(
+                "SELECT s.t AS time, n.chain, n.version, n.implementation, \
+                 count(*) AS nodes, \
+                 count(*) FILTER (WHERE n.authority) AS authorities \
+                 FROM generate_series($1, $2, $3 * INTERVAL '1 second') s(t) \
+                 CROSS JOIN LATERAL ( \
+                 SELECT DISTINCT ON (peer_id) chain, version, implementation, authority \
+                 FROM peer_connections \
+                 WHERE peer_id IS NOT NULL \
+                 AND chain IS NOT NULL \
+                 AND ($4::TEXT IS NULL OR chain = $4) \
+                 AND created_at <= s.t \
+                 AND (disconnected_at IS NULL OR disconnected_at > s.t) \
+                 ORDER BY peer_id, created_at DESC \
+                 ) n \
+                 GROUP BY 1, 2, 3, 4 \
+                 ORDER BY 2, 1, 3, 4",
+            )
+            .bind::<Timestamp, _>(msg.start_time)
+            .bind::<Timestamp, _>(msg.end_time)
+            .bind::<BigInt, _>(msg.bucket_s)
+            .bind::<Nullable<Text>, _>(msg.chain.clone());
+            debug!(
+                "get_chain_history query: {}",
+                diesel::debug_query::<diesel::pg::Pg, _>(&query)
+            );
+            let result: QueryResult<Vec<VersionSample>> = query.get_results(conn);
+            result
+        }) {
+            Ok(Ok(samples)) => Ok(history(&msg.sample_times(), samples)),
+            Ok(Err(e)) => Err(e.into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+fn add_to_versions(versions: &mut Vec<VersionCount>, count: VersionCount) {
+    match versions
+        .iter_mut()
+        .find(|v| v.version == count.version && v.implementation == count.implementation)
+    {
+        Some(v) => {
+            v.nodes += count.nodes;
+            v.authorities += count.authorities;
+        }
+        None => versions.push(count),
+    }
+}
+
+/// Group the connected nodes by chain, most used versions first
+fn inventory(nodes: Vec<ChainNode>) -> Vec<ChainInventory> {
+    let mut chains: BTreeMap<String, ChainInventory> = BTreeMap::new();
+    for node in nodes {
+        let authority = node.authority == Some(true);
+        let inv = chains
+            .entry(node.chain.clone())
+            .or_insert_with(|| ChainInventory {
+                chain: node.chain.clone(),
+                nodes: 0,
+                authorities: 0,
+                full_nodes: 0,
+                versions: Vec::new(),
+                implementations: BTreeMap::new(),
+                names: Vec::new(),
+            });
+        inv.nodes += 1;
+        if authority {
+            inv.authorities += 1;
+        } else {
+            inv.full_nodes += 1;
+        }
+        add_to_versions(
+            &mut inv.versions,
+            VersionCount {
+                version: node.version,
+                implementation: node.implementation.clone(),
+                nodes: 1,
+                authorities: authority as i64,
+            },
+        );
+        *inv.implementations
+            .entry(node.implementation.unwrap_or_default())
+            .or_insert(0) += 1;
+        if let Some(name) = node.name {
+            inv.names.push(name);
+        }
+    }
+    chains
+        .into_values()
+        .map(|mut inv| {
+            inv.versions.sort_by(|a, b| {
+                b.nodes
+                    .cmp(&a.nodes)
+                    .then_with(|| a.version.cmp(&b.version))
+            });
+            inv.names.sort();
+            inv
+        })
+        .collect()
+}
+
+/// Group the samples by chain, with a point for every sample time so that times
+/// without any connected node are reported as zero
+fn history(times: &[NaiveDateTime], samples: Vec<VersionSample>) -> Vec<ChainHistory> {
+    let mut chains: BTreeMap<String, BTreeMap<NaiveDateTime, ChainHistoryPoint>> = BTreeMap::new();
+    for sample in samples {
+        let time = sample.time;
+        let points = chains.entry(sample.chain).or_insert_with(|| {
+            times
+                .iter()
+                .map(|&time| {
+                    (
+                        time,
+                        ChainHistoryPoint {
+                            time,
+                            nodes: 0,
+                            authorities: 0,
+                            versions: Vec::new(),
+                        },
+                    )
+                })
+                .collect()
+        });
+        let point = points.entry(time).or_insert_with(|| ChainHistoryPoint {
+            time,
+            nodes: 0,
+            authorities: 0,
+            versions: Vec::new(),
+        });
+        point.nodes += sample.nodes;
+        point.authorities += sample.authorities;
+        add_to_versions(
+            &mut point.versions,
+            VersionCount {
+                version: sample.version,
+                implementation: sample.implementation,
+                nodes: sample.nodes,
+                authorities: sample.authorities,
+            },
+        );
+    }
+    chains
+        .into_iter()
+        .map(|(chain, points)| ChainHistory {
+            chain,
+            points: points.into_values().collect(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(chain: &str, version: &str, authority: Option<bool>, name: &str) -> ChainNode {
+        ChainNode {
+            chain: chain.to_string(),
+            version: Some(version.to_string()),
+            implementation: Some("parity-polkadot".to_string()),
+            authority,
+            name: Some(name.to_string()),
+        }
+    }
+
+    #[test]
+    fn inventory_counts_versions_and_authorities_test() {
+        let chains = inventory(vec![
+            node("Polkadot", "0.8.26", Some(true), "alice"),
+            node("Polkadot", "0.8.25", Some(false), "bob"),
+            node("Polkadot", "0.8.26", None, "dave"),
+            node("Kusama", "0.8.25", Some(true), "carol"),
+        ]);
+        assert_eq!(chains.len(), 2);
+        let polkadot = &chains[1];
+        assert_eq!(polkadot.chain, "Polkadot");
+        assert_eq!(polkadot.nodes, 3);
+        assert_eq!(polkadot.authorities, 1);
+        assert_eq!(polkadot.full_nodes, 2);
+        assert_eq!(polkadot.versions[0].version.as_deref(), Some("0.8.26"));
+        assert_eq!(polkadot.versions[0].nodes, 2);
+        assert_eq!(polkadot.versions[0].authorities, 1);
+        assert_eq!(polkadot.implementations.get("parity-polkadot"), Some(&3));
+        assert_eq!(polkadot.names, vec!["alice", "bob", "dave"]);
+    }
+
+    #[test]
+    fn history_fills_missing_samples_test() {
+        let t0 = NaiveDateTime::from_timestamp(1_600_000_000, 0);
+        let query = ChainHistoryQuery::new(
+            ChainParams {
+                chain: None,
+                bucket_s: Some(60),
+            },
+            Some(t0),
+            Some(t0 + Duration::seconds(120)),
+            None,
+        )
+        .unwrap();
+        let samples = vec![VersionSample {
+            time: t0 + Duration::seconds(60),
+            chain: "Polkadot".to_string(),
+            version: Some("0.8.26".to_string()),
+            implementation: None,
+            nodes: 2,
+            authorities: 1,
+        }];
+        let chains = history(&query.sample_times(), samples);
+        assert_eq!(chains.len(), 1);
+        let nodes: Vec<i64> = chains[0].points.iter().map(|p| p.nodes).collect();
+        assert_eq!(nodes, vec![0, 2, 0]);
+    }
+
+    #[test]
+    fn history_checks_time_range_test() {
+        let t0 = NaiveDateTime::from_timestamp(1_600_000_000, 0);
+        let query = |bucket_s, max_age_s| {
+            ChainHistoryQuery::new(
+                ChainParams {
+                    chain: None,
+                    bucket_s,
+                },
+                None,
+                Some(t0),
+                Some(max_age_s),
+            )
+        };
+        // The default bucket may be longer than a short range
+        assert_eq!(
+            query(None, 60).unwrap().sample_times(),
+            vec![t0 - Duration::seconds(60)]
+        );
+        assert!(query(Some(60), 3600).is_ok());
+        assert!(query(Some(7200), 3600).is_err());
+        assert!(query(Some(i64::MAX), 3600).is_err());
+        assert!(query(None, i64::MAX).is_err());
+    }
+}
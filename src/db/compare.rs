@@ -0,0 +1,406 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate Analytics.
+
+// Substrate Analytics is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate Analytics is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
+
+use actix::prelude::*;
+use diesel::sql_types::*;
+use diesel::{result::QueryResult, sql_query, RunQueryDsl};
+use failure::Error;
+use statrs::distribution::{StudentsT, Univariate};
+use std::collections::BTreeMap;
+
+use super::DbExecutor;
+
+/// Most benchmarks that can be compared at once
+pub const MAX_COMPARED_BENCHMARKS: usize = 16;
+/// Significance level of each test when no `alpha` is given, before correcting for the number of tests
+const DEFAULT_ALPHA: f64 = 0.01;
+/// Smallest absolute Cohen's d reported as a change when no `min_effect` is given
+const DEFAULT_MIN_EFFECT: f64 = 0.2;
+
+/// SQL expression of the `time` of a `tracing.profiling` message in `{logs}.logs`, NULL if it
+/// isn't numeric. It is sent as a string by some node versions.
+pub(super) fn profiling_time(logs: &str) -> String {
+    format!(
+        "CASE WHEN jsonb_typeof({0}.logs->'time') = 'number' \
+         OR {0}.logs->>'time' ~ '^[0-9]+(\\.[0-9]+)?$' \
+         THEN ({0}.logs->>'time')::FLOAT8 END",
+        logs
+    )
+}
+
+/// Distribution of the profiling `time` (ns) of a target and span name during a benchmark
+#[derive(Serialize, Debug, Clone, QueryableByName)]
+pub struct TimingDistribution {
+    #[sql_type = "Integer"]
+    pub benchmark_id: i32,
+    #[sql_type = "Text"]
+    pub target: String,
+    #[sql_type = "Text"]
+    pub name: String,
+    #[sql_type = "BigInt"]
+    pub count: i64,
+    #[sql_type = "Double"]
+    pub mean: f64,
+    #[sql_type = "Nullable<Double>"]
+    pub stddev: Option<f64>,
+    #[sql_type = "Double"]
+    pub min: f64,
+    #[sql_type = "Double"]
+    pub max: f64,
+    #[sql_type = "Double"]
+    pub p50: f64,
+    #[sql_type = "Double"]
+    pub p90: f64,
+    #[sql_type = "Double"]
+    pub p99: f64,
+    /// Timings are compared on a log scale, as they are heavily right skewed
+    #[serde(skip)]
+    #[sql_type = "Double"]
+    log_mean: f64,
+    #[serde(skip)]
+    #[sql_type = "Nullable<Double>"]
+    log_variance: Option<f64>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Verdict {
+    Regression,
+    Improvement,
+    Unchanged,
+}
+
+/// Welch's t-test of the log timings of a benchmark against the baseline
+#[derive(Serialize, Debug, Clone)]
+pub struct TimingComparison {
+    pub benchmark_id: i32,
+    pub target: String,
+    pub name: String,
+    pub baseline_count: i64,
+    pub count: i64,
+    /// Ratio of the geometric mean timings, above 1 is slower than the baseline
+    pub ratio: f64,
+    /// Cohen's d of the log timings
+    pub effect_size: f64,
+    pub p_value: f64,
+    /// `1 - p_value`
+    pub confidence: f64,
+    pub verdict: Verdict,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BenchmarkComparison {
+    pub baseline_id: i32,
+    pub benchmark_ids: Vec<i32>,
+    /// Significance level of each test, `alpha` divided by the number of tests
+    pub corrected_alpha: f64,
+    pub min_effect: f64,
+    pub distributions: Vec<TimingDistribution>,
+    /// Regressions then improvements, largest effect first, then unchanged timings
+    pub comparisons: Vec<TimingComparison>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct CompareParams {
+    /// Comma separated benchmark ids, the first one is the baseline
+    pub ids: Option<String>,
+    pub alpha: Option<f64>,
+    pub min_effect: Option<f64>,
+}
+
+/// Compare the profiling timings of benchmarks with the first one, see `CompareQuery::new`
+pub struct CompareQuery {
+    benchmark_ids: Vec<i32>,
+    alpha: f64,
+    min_effect: f64,
+}
+
+impl CompareQuery {
+    pub fn new(params: CompareParams) -> Result<Self, String> {
+        let mut benchmark_ids: Vec<i32> = Vec::new();
+        for id in params.ids.as_deref().unwrap_or("").split(',') {
+            let id = id
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid benchmark id `{}`", id))?;
+            if !benchmark_ids.contains(&id) {
+                benchmark_ids.push(id);
+            }
+        }
+        if benchmark_ids.len() < 2 || benchmark_ids.len() > MAX_COMPARED_BENCHMARKS {
+            return Err(format!(
+                "`ids` must have between 2 and {} distinct benchmark ids",
+                MAX_COMPARED_BENCHMARKS
+            ));
+        }
+        let alpha = params.alpha.unwrap_or(DEFAULT_ALPHA);
+        if alpha.is_nan() || alpha <= 0.0 || alpha >= 1.0 {
+            return Err("`alpha` must be between 0 and 1".to_string());
+        }
+        let min_effect = params.min_effect.unwrap_or(DEFAULT_MIN_EFFECT);
+        if min_effect.is_nan() || min_effect < 0.0 {
+            return Err("`min_effect` must not be negative".to_string());
+        }
+        Ok(CompareQuery {
+            benchmark_ids,
+            alpha,
+            min_effect,
+        })
+    }
+}
+
+impl Message for CompareQuery {
+    type Result = Result<BenchmarkComparison, Error>;
+}
+
+impl Handler<CompareQuery> for DbExecutor {
+    type Result = Result<BenchmarkComparison, Error>;
+
+    fn handle(&mut self, msg: CompareQuery, _: &mut Self::Context) -> Self::Result {
+        self.compare_benchmarks(msg)
+    }
+}
+
+impl DbExecutor {
+    fn compare_benchmarks(&self, msg: CompareQuery) -> Result<BenchmarkComparison, Error> {
+        match self.with_connection(|conn| {
+            // Benchmarks run from their first to their last event, on the node of their setup
+            let query = sql_query(format!(
+                "WITH bm AS ( \
+                 SELECT b.id, b.setup->'substrate'->>'peerId' AS peer_id, \
+                 min(be.created_at) AS start_time, max(be.created_at) AS end_time \
+                 FROM benchmarks b \
+                 JOIN benchmark_events be ON be.benchmark_id = b.id \
+                 WHERE b.id = ANY($1) \
+                 GROUP BY b.id \
+                 ) \
+                 SELECT benchmark_id, target, name, \
+                 count(*) AS count, \
+                 avg(t) AS mean, \
+                 stddev_samp(t) AS stddev, \
+                 min(t) AS min, \
+                 max(t) AS max, \
+                 percentile_cont(0.5) WITHIN GROUP (ORDER BY t) AS p50, \
+                 percentile_cont(0.9) WITHIN GROUP (ORDER BY t) AS p90, \
+                 percentile_cont(0.99) WITHIN GROUP (ORDER BY t) AS p99, \
+                 avg(ln(t + 1)) AS log_mean, \
+                 var_samp(ln(t + 1)) AS log_variance \
+                 FROM ( \
+                 SELECT bm.id AS benchmark_id, \
+                 sl.logs->>'target' AS target, \
+                 sl.logs->>'name' AS name, \
+                 {} AS t \
+                 FROM bm \
+                 JOIN peer_connections pc ON pc.peer_id = bm.peer_id \
+                 JOIN substrate_logs sl ON sl.peer_connection_id = pc.id \
+                 WHERE sl.created_at BETWEEN bm.start_time AND bm.end_time \
+                 AND sl.logs->>'msg' = 'tracing.profiling' \
+                 ) s \
+                 WHERE t >= 0 AND target IS NOT NULL AND name IS NOT NULL \
+                 GROUP BY benchmark_id, target, name \
+                 ORDER BY target, name, benchmark_id",
+                profiling_time("sl")
+            ))
+            .bind::<Array<Integer>, _>(msg.benchmark_ids.clone());
+            debug!(
+                "compare_benchmarks query: {}",
+                diesel::debug_query::<diesel::pg::Pg, _>(&query)
+            );
+            let result: QueryResult<Vec<TimingDistribution>> = query.get_results(conn);
+            result
+        }) {
+            Ok(Ok(distributions)) => Ok(compare(msg, distributions)),
+            Ok(Err(e)) => Err(e.into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Two-sided p-value and Cohen's d of Welch's t-test on the log timings of `b` against `a`
+fn welch_test(a: &TimingDistribution, b: &TimingDistribution) -> Option<(f64, f64)> {
+    if a.count < 2 || b.count < 2 {
+        return None;
+    }
+    let (n1, n2) = (a.count as f64, b.count as f64);
+    let (v1, v2) = (a.log_variance?, b.log_variance?);
+    let diff = b.log_mean - a.log_mean;
+    let pooled_sd = (((n1 - 1.0) * v1 + (n2 - 1.0) * v2) / (n1 + n2 - 2.0)).sqrt();
+    let (s1, s2) = (v1 / n1, v2 / n2);
+    let se = (s1 + s2).sqrt();
+    if se == 0.0 {
+        // Constant timings in both, they either all changed or none did
+        return Some(if diff == 0.0 {
+            (1.0, 0.0)
+        } else {
+            (0.0, diff.signum() * f64::INFINITY)
+        });
+    }
+    let t = diff / se;
+    let df = (s1 + s2).powi(2) / (s1.powi(2) / (n1 - 1.0) + s2.powi(2) / (n2 - 1.0));
+    let p_value = match StudentsT::new(0.0, 1.0, df) {
+        Ok(dist) => (2.0 * (1.0 - dist.cdf(t.abs()))).clamp(0.0, 1.0),
+        Err(_) => return None,
+    };
+    let effect_size = if pooled_sd > 0.0 {
+        diff / pooled_sd
+    } else {
+        diff.signum() * f64::INFINITY
+    };
+    Some((p_value, effect_size))
+}
+
+fn compare(msg: CompareQuery, distributions: Vec<TimingDistribution>) -> BenchmarkComparison {
+    let baseline_id = msg.benchmark_ids[0];
+    let mut by_key: BTreeMap<(&str, &str), Vec<&TimingDistribution>> = BTreeMap::new();
+    for d in &distributions {
+        by_key
+            .entry((d.target.as_str(), d.name.as_str()))
+            .or_default()
+            .push(d);
+    }
+    let mut tests = Vec::new();
+    for dists in by_key.values() {
+        let baseline = match dists.iter().find(|d| d.benchmark_id == baseline_id) {
+            Some(b) => b,
+            None => continue,
+        };
+        for d in dists.iter().filter(|d| d.benchmark_id != baseline_id) {
+            if let Some((p_value, effect_size)) = welch_test(baseline, d) {
+                tests.push((baseline, d, p_value, effect_size));
+            }
+        }
+    }
+    // Bonferroni correction, there is a test per target, name and benchmark
+    let corrected_alpha = msg.alpha / tests.len().max(1) as f64;
+    let mut comparisons: Vec<TimingComparison> = tests
+        .into_iter()
+        .map(|(baseline, d, p_value, effect_size)| {
+            let verdict = if p_value >= corrected_alpha || effect_size.abs() < msg.min_effect {
+                Verdict::Unchanged
+            } else if effect_size > 0.0 {
+                Verdict::Regression
+            } else {
+                Verdict::Improvement
+            };
+            TimingComparison {
+                benchmark_id: d.benchmark_id,
+                target: d.target.clone(),
+                name: d.name.clone(),
+                baseline_count: baseline.count,
+                count: d.count,
+                ratio: (d.log_mean - baseline.log_mean).exp(),
+                effect_size,
+                p_value,
+                confidence: 1.0 - p_value,
+                verdict,
+            }
+        })
+        .collect();
+    let rank = |v: Verdict| match v {
+        Verdict::Regression => 0,
+        Verdict::Improvement => 1,
+        Verdict::Unchanged => 2,
+    };
+    comparisons.sort_by(|a, b| {
+        rank(a.verdict).cmp(&rank(b.verdict)).then_with(|| {
+            b.effect_size
+                .abs()
+                .partial_cmp(&a.effect_size.abs())
+                .unwrap_or(std::cmp::Ordering::Equal)
+        })
+    });
+    BenchmarkComparison {
+        baseline_id,
+        benchmark_ids: msg.benchmark_ids,
+        corrected_alpha,
+        min_effect: msg.min_effect,
+        distributions,
+        comparisons,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn distribution(benchmark_id: i32, name: &str, log_mean: f64) -> TimingDistribution {
+        TimingDistribution {
+            benchmark_id,
+            target: "frame_system".to_string(),
+            name: name.to_string(),
+            count: 100,
+            mean: log_mean.exp(),
+            stddev: Some(1.0),
+            min: 0.0,
+            max: 0.0,
+            p50: 0.0,
+            p90: 0.0,
+            p99: 0.0,
+            log_mean,
+            log_variance: Some(0.01),
+        }
+    }
+
+    #[test]
+    fn parse_compare_params_test() {
+        let params = |ids: &str| CompareParams {
+            ids: Some(ids.to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            CompareQuery::new(params("3, 1,3")).unwrap().benchmark_ids,
+            vec![3, 1]
+        );
+        assert!(CompareQuery::new(params("1")).is_err());
+        assert!(CompareQuery::new(params("1,x")).is_err());
+    }
+
+    #[test]
+    fn compare_detects_regressions_and_improvements_test() {
+        let msg = CompareQuery::new(CompareParams {
+            ids: Some("1,2".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+        let distributions = vec![
+            distribution(1, "get", 6.5),
+            distribution(2, "get", 6.7),
+            distribution(1, "put", 6.5),
+            distribution(2, "put", 6.3),
+            distribution(1, "same", 6.5),
+            distribution(2, "same", 6.501),
+            distribution(2, "new", 6.5),
+        ];
+        let result = compare(msg, distributions);
+        assert_eq!(result.comparisons.len(), 3);
+        assert!((result.corrected_alpha - DEFAULT_ALPHA / 3.0).abs() < 1e-12);
+        let verdicts: Vec<(&str, Verdict)> = result
+            .comparisons
+            .iter()
+            .map(|c| (c.name.as_str(), c.verdict))
+            .collect();
+        assert_eq!(
+            verdicts,
+            vec![
+                ("get", Verdict::Regression),
+                ("put", Verdict::Improvement),
+                ("same", Verdict::Unchanged),
+            ]
+        );
+        assert!((result.comparisons[0].ratio - 0.2f64.exp()).abs() < 1e-9);
+        assert!(result.comparisons[0].confidence > 0.99);
+    }
+}
@@ -0,0 +1,116 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate Analytics.
+
+// Substrate Analytics is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate Analytics is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
+
+use actix::prelude::*;
+use chrono::{NaiveDateTime, Utc};
+use diesel::sql_types::*;
+use diesel::{result::QueryResult, sql_query, RunQueryDsl};
+use failure::Error;
+use serde_json::Value;
+
+use super::{filters::Filters, DbExecutor};
+
+/// A stored log message with the details of the node it came from
+#[derive(Serialize, Debug, QueryableByName)]
+pub struct ExportRow {
+    #[sql_type = "Integer"]
+    pub id: i32,
+    #[sql_type = "Timestamp"]
+    pub created_at: NaiveDateTime,
+    #[sql_type = "Nullable<Integer>"]
+    pub peer_connection_id: Option<i32>,
+    #[sql_type = "Nullable<Text>"]
+    pub peer_id: Option<String>,
+    #[sql_type = "Nullable<Text>"]
+    pub ip_addr: Option<String>,
+    #[sql_type = "Nullable<Text>"]
+    pub msg: Option<String>,
+    #[sql_type = "Jsonb"]
+    pub logs: Value,
+}
+
+/// Page of logs in `(created_at, id)` order, starting after `after`
+pub struct ExportPage {
+    pub filters: Filters,
+    pub after: Option<(NaiveDateTime, i32)>,
+    pub limit: i32,
+}
+
+impl Message for ExportPage {
+    type Result = Result<Vec<ExportRow>, Error>;
+}
+
+impl Handler<ExportPage> for DbExecutor {
+    type Result = Result<Vec<ExportRow>, Error>;
+
+    fn handle(&mut self, msg: ExportPage, _: &mut Self::Context) -> Self::Result {
+        self.get_export_page(msg)
+    }
+}
+
+impl DbExecutor {
+    fn get_export_page(&self, page: ExportPage) -> Result<Vec<ExportRow>, Error> {
+        let ExportPage {
+            filters,
+            after,
+            limit,
+        } = page;
+        let start_time = filters
+            .start_time
+            .unwrap_or_else(|| NaiveDateTime::from_timestamp(0, 0));
+        // Rows at exactly `start_time` are included on the first page
+        let (after_time, after_id) = after.unwrap_or((start_time, -1));
+        match self.with_connection(|conn| {
+            let query = sql_query(
+                "SELECT sl.id, \
+                 sl.created_at, \
+                 sl.peer_connection_id, \
+                 pc.peer_id, \
+                 pc.ip_addr, \
+                 sl.logs->>'msg' AS msg, \
+                 sl.logs \
+                 FROM substrate_logs sl \
+                 LEFT JOIN peer_connections pc ON sl.peer_connection_id = pc.id \
+                 WHERE sl.created_at >= $1 \
+                 AND sl.created_at < $2 \
+                 AND ($3::TEXT IS NULL OR pc.peer_id = $3) \
+                 AND ($4::TEXT IS NULL OR sl.logs->>'msg' = $4) \
+                 AND ($5::TEXT IS NULL OR sl.logs->>'target' = $5) \
+                 AND (sl.created_at, sl.id) > ($6, $7) \
+                 ORDER BY sl.created_at, sl.id \
+                 LIMIT $8",
+            )
+            .bind::<Timestamp, _>(start_time)
+            .bind::<Timestamp, _>(filters.end_time.unwrap_or_else(|| Utc::now().naive_utc()))
+            .bind::<Nullable<Text>, _>(filters.peer_id)
+            .bind::<Nullable<Text>, _>(filters.msg)
+            .bind::<Nullable<Text>, _>(filters.target)
+            .bind::<Timestamp, _>(after_time)
+            .bind::<Integer, _>(after_id)
+            .bind::<Integer, _>(limit);
+            debug!(
+                "get_export_page query: {}",
+                diesel::debug_query::<diesel::pg::Pg, _>(&query)
+            );
+            let result: QueryResult<Vec<ExportRow>> = query.get_results(conn);
+            result
+        }) {
+            Ok(Ok(v)) => Ok(v),
+            Ok(Err(e)) => Err(e.into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
@@ -14,7 +14,16 @@
 // You should have received a copy of the GNU General Public License
 // along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
 
-use chrono::NaiveDateTime;
+use chrono::{Duration, NaiveDateTime, Utc};
+use serde::de::{self, Deserialize, Deserializer};
+use serde_json::Value;
+use std::fmt;
+use std::str::FromStr;
+
+/// Most predicates accepted in a single `where` filter
+pub const MAX_FIELD_PREDICATES: usize = 8;
+/// Deepest path accepted in a predicate, eg. `state.peerId` has depth 2
+pub const MAX_FIELD_PATH_DEPTH: usize = 8;
 
 //const TIME_FORMAT: &'static str = "2000-01-01T00:00:00";
 
@@ -31,4 +40,377 @@ pub struct Filters {
     pub peer_id: Option<String>,
     pub target: Option<String>,
     pub msg: Option<String>,
+    /// Return the page of results older than this cursor
+    pub before: Option<LogCursor>,
+    /// Return the page of results newer than this cursor
+    pub after: Option<LogCursor>,
+    /// Predicates on fields of the log message, all of which must hold
+    #[serde(default, rename = "where", deserialize_with = "deserialize_predicates")]
+    pub predicates: Vec<FieldPredicate>,
+}
+
+impl Filters {
+    /// Check values that deserialize but can't be used together or make no sense
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(limit) = self.limit {
+            if limit < 1 {
+                return Err("`limit` must be at least 1".into());
+            }
+        }
+        if let Some(max_age_s) = self.max_age_s {
+            if max_age_s < 0 {
+                return Err("`max_age_s` must not be negative".into());
+            }
+            max_age_start(
+                self.end_time.unwrap_or_else(|| Utc::now().naive_utc()),
+                max_age_s,
+            )?;
+        }
+        if let (Some(start_time), Some(end_time)) = (self.start_time, self.end_time) {
+            if start_time > end_time {
+                return Err("`start_time` must not be after `end_time`".into());
+            }
+        }
+        if self.before.is_some() && self.after.is_some() {
+            return Err("Only one of `before` and `after` may be given".into());
+        }
+        Ok(())
+    }
+}
+
+/// The time `max_age_s` before `end_time`, or an error if that is out of range
+pub fn max_age_start(end_time: NaiveDateTime, max_age_s: i64) -> Result<NaiveDateTime, String> {
+    max_age_s
+        .checked_mul(1000)
+        .and_then(|ms| end_time.checked_sub_signed(Duration::milliseconds(ms)))
+        .ok_or_else(|| format!("`max_age_s` of {} is out of range", max_age_s))
+}
+
+/// Position of a log in `(created_at, id)` order, passed to clients as an opaque token
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LogCursor {
+    pub created_at: NaiveDateTime,
+    pub id: i32,
+}
+
+impl fmt::Display for LogCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}_{}",
+            self.created_at.timestamp() * 1_000_000
+                + i64::from(self.created_at.timestamp_subsec_micros()),
+            self.id
+        )
+    }
+}
+
+impl FromStr for LogCursor {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = "Invalid cursor";
+        let mut parts = s.splitn(2, '_');
+        let micros: i64 = parts.next().and_then(|p| p.parse().ok()).ok_or(invalid)?;
+        let id: i32 = parts.next().and_then(|p| p.parse().ok()).ok_or(invalid)?;
+        let created_at = NaiveDateTime::from_timestamp_opt(
+            micros.div_euclid(1_000_000),
+            (micros.rem_euclid(1_000_000) * 1000) as u32,
+        )
+        .ok_or(invalid)?;
+        Ok(LogCursor { created_at, id })
+    }
+}
+
+impl<'de> Deserialize<'de> for LogCursor {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// Condition on the value found at `path` in a log message
+#[derive(Clone, Debug, PartialEq)]
+pub struct FieldPredicate {
+    pub path: Vec<String>,
+    pub op: PredicateOp,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PredicateOp {
+    /// `path=value`, value is parsed as JSON, otherwise taken as a string
+    Eq(Value),
+    /// `path>n`, `path>=n`, `path<n`, `path<=n`, only holds for numeric fields
+    Compare(Comparison, f64),
+    /// `path?`
+    Exists,
+    /// `path@>json`, with the semantics of the postgres `jsonb` `@>` operator
+    Contains(Value),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Comparison {
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+}
+
+impl Comparison {
+    pub fn as_sql(self) -> &'static str {
+        match self {
+            Comparison::Gt => ">",
+            Comparison::Gte => ">=",
+            Comparison::Lt => "<",
+            Comparison::Lte => "<=",
+        }
+    }
+}
+
+impl FieldPredicate {
+    /// Evaluate the predicate in memory, matching the SQL compiled for it in `db::nodes`
+    pub fn matches(&self, log: &Value) -> bool {
+        let field = self
+            .path
+            .iter()
+            .try_fold(log, |value, segment| match value {
+                Value::Object(map) => map.get(segment),
+                Value::Array(items) => segment.parse::<usize>().ok().and_then(|i| items.get(i)),
+                _ => None,
+            });
+        match (&self.op, field) {
+            (_, None) => false,
+            (PredicateOp::Exists, Some(_)) => true,
+            (PredicateOp::Eq(expected), Some(value)) => json_eq(value, expected),
+            (PredicateOp::Contains(expected), Some(value)) => json_contains(value, expected),
+            (PredicateOp::Compare(comparison, bound), Some(value)) => match value.as_f64() {
+                Some(n) => match comparison {
+                    Comparison::Gt => n > *bound,
+                    Comparison::Gte => n >= *bound,
+                    Comparison::Lt => n < *bound,
+                    Comparison::Lte => n <= *bound,
+                },
+                None => false,
+            },
+        }
+    }
+}
+
+fn json_eq(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => x.as_f64() == y.as_f64(),
+        _ => a == b,
+    }
+}
+
+fn json_contains(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Object(a), Value::Object(b)) => b
+            .iter()
+            .all(|(k, bv)| a.get(k).is_some_and(|av| json_contains(av, bv))),
+        (Value::Array(a), Value::Array(b)) => {
+            b.iter().all(|bv| a.iter().any(|av| json_contains(av, bv)))
+        }
+        (Value::Array(_), Value::Object(_)) => false,
+        (Value::Array(a), b) => a.iter().any(|av| json_eq(av, b)),
+        _ => json_eq(a, b),
+    }
+}
+
+impl FromStr for FieldPredicate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let path_len = s
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+            .unwrap_or(s.len());
+        let (path, rest) = s.split_at(path_len);
+        let path: Vec<String> = path.split('.').map(str::to_owned).collect();
+        if path.iter().any(String::is_empty) {
+            return Err(format!("Invalid field path in predicate `{}`", s));
+        }
+        if path.len() > MAX_FIELD_PATH_DEPTH {
+            return Err(format!(
+                "Field path in predicate `{}` is deeper than {}",
+                s, MAX_FIELD_PATH_DEPTH
+            ));
+        }
+        let number = |v: &str| match v.parse::<f64>() {
+            Ok(n) if n.is_finite() => Ok(n),
+            _ => Err(format!("Expected a number in predicate `{}`", s)),
+        };
+        let op = if rest == "?" {
+            PredicateOp::Exists
+        } else if let Some(v) = rest.strip_prefix("@>") {
+            PredicateOp::Contains(
+                serde_json::from_str(v)
+                    .map_err(|_| format!("Expected JSON in predicate `{}`", s))?,
+            )
+        } else if let Some(v) = rest.strip_prefix(">=") {
+            PredicateOp::Compare(Comparison::Gte, number(v)?)
+        } else if let Some(v) = rest.strip_prefix("<=") {
+            PredicateOp::Compare(Comparison::Lte, number(v)?)
+        } else if let Some(v) = rest.strip_prefix('>') {
+            PredicateOp::Compare(Comparison::Gt, number(v)?)
+        } else if let Some(v) = rest.strip_prefix('<') {
+            PredicateOp::Compare(Comparison::Lt, number(v)?)
+        } else if let Some(v) = rest.strip_prefix('=') {
+            PredicateOp::Eq(serde_json::from_str(v).unwrap_or_else(|_| Value::String(v.to_owned())))
+        } else {
+            return Err(format!(
+                "Predicate `{}` must use one of `=`, `>`, `>=`, `<`, `<=`, `?` or `@>`",
+                s
+            ));
+        };
+        Ok(FieldPredicate { path, op })
+    }
+}
+
+/// Parse `;` separated predicates, ignoring separators inside JSON strings, objects and arrays
+pub fn parse_predicates(s: &str) -> Result<Vec<FieldPredicate>, String> {
+    let mut predicates = Vec::new();
+    let (mut depth, mut in_string, mut escaped, mut start) = (0i32, false, false, 0);
+    for (i, c) in s.char_indices().chain(std::iter::once((s.len(), ';'))) {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => (),
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '{' | '[' => depth += 1,
+            '}' | ']' => depth -= 1,
+            ';' if depth == 0 => {
+                if !s[start..i].trim().is_empty() {
+                    predicates.push(s[start..i].parse()?);
+                }
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    if depth != 0 || in_string {
+        return Err(format!(
+            "Unbalanced JSON in predicate `{}`",
+            s[start..].trim()
+        ));
+    }
+    if predicates.len() > MAX_FIELD_PREDICATES {
+        return Err(format!(
+            "No more than {} predicates are allowed",
+            MAX_FIELD_PREDICATES
+        ));
+    }
+    Ok(predicates)
+}
+
+fn deserialize_predicates<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<FieldPredicate>, D::Error> {
+    parse_predicates(&String::deserialize(deserializer)?).map_err(de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_round_trip_test() {
+        let cursor = LogCursor {
+            created_at: NaiveDateTime::parse_from_str(
+                "2020-10-19 10:11:12.345678",
+                "%Y-%m-%d %H:%M:%S%.f",
+            )
+            .unwrap(),
+            id: 42,
+        };
+        assert_eq!(cursor.to_string().parse(), Ok(cursor));
+        assert!("42".parse::<LogCursor>().is_err());
+        assert!("abc_1".parse::<LogCursor>().is_err());
+    }
+
+    #[test]
+    fn validate_filters_test() {
+        assert!(Filters::default().validate().is_ok());
+        let invalid = |filters: Filters| filters.validate().is_err();
+        assert!(invalid(Filters {
+            limit: Some(0),
+            ..Default::default()
+        }));
+        assert!(invalid(Filters {
+            max_age_s: Some(-1),
+            ..Default::default()
+        }));
+        assert!(invalid(Filters {
+            max_age_s: Some(i64::MAX),
+            ..Default::default()
+        }));
+        let cursor = "1_1".parse::<LogCursor>().ok();
+        assert!(invalid(Filters {
+            before: cursor,
+            after: cursor,
+            ..Default::default()
+        }));
+        let time = |s: &str| NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S").ok();
+        assert!(invalid(Filters {
+            start_time: time("2020-10-02T00:00:00"),
+            end_time: time("2020-10-01T00:00:00"),
+            ..Default::default()
+        }));
+    }
+
+    #[test]
+    fn parse_predicates_splits_outside_json_test() {
+        let predicates =
+            parse_predicates(r#"height>=10;best=0x12;state.peerId?;net@>{"a":";"}"#).unwrap();
+        assert_eq!(
+            predicates,
+            vec![
+                FieldPredicate {
+                    path: vec!["height".into()],
+                    op: PredicateOp::Compare(Comparison::Gte, 10.0),
+                },
+                FieldPredicate {
+                    path: vec!["best".into()],
+                    op: PredicateOp::Eq(json!("0x12")),
+                },
+                FieldPredicate {
+                    path: vec!["state".into(), "peerId".into()],
+                    op: PredicateOp::Exists,
+                },
+                FieldPredicate {
+                    path: vec!["net".into()],
+                    op: PredicateOp::Contains(json!({"a": ";"})),
+                },
+            ]
+        );
+        assert!(parse_predicates("height~10").is_err());
+        assert!(parse_predicates("height>=abc").is_err());
+        assert!(parse_predicates("a..b?").is_err());
+        assert!(parse_predicates("x@>{").is_err());
+        assert!(parse_predicates(&["a?"; MAX_FIELD_PREDICATES + 1].join(";")).is_err());
+    }
+
+    #[test]
+    fn predicates_match_like_jsonb_test() {
+        let log = json!({"height": 12, "best": "0x12", "net": {"peers": [1, 2], "name": "x"}});
+        let matches = |s: &str| s.parse::<FieldPredicate>().unwrap().matches(&log);
+        assert!(matches("height=12.0"));
+        assert!(matches("height>11"));
+        assert!(!matches("height<=11"));
+        assert!(!matches("best>1"));
+        assert!(matches("best=0x12"));
+        assert!(matches("net.peers.1=2"));
+        assert!(matches("net.name?"));
+        assert!(!matches("net.other?"));
+        assert!(matches(r#"net@>{"peers":[2]}"#));
+        assert!(matches("net.peers@>1"));
+        assert!(!matches(r#"net@>{"peers":[3]}"#));
+    }
 }
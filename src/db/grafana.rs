@@ -0,0 +1,153 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate Analytics.
+
+// Substrate Analytics is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate Analytics is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
+
+use actix::prelude::*;
+use chrono::NaiveDateTime;
+use diesel::sql_types::*;
+use diesel::{result::QueryResult, sql_query, RunQueryDsl};
+use failure::Error;
+
+use super::{DbExecutor, RECORD_LIMIT};
+
+/// A `msg` type seen recently, with one of its numeric top level fields
+#[derive(Serialize, Debug, QueryableByName)]
+pub struct MetricName {
+    #[sql_type = "Text"]
+    pub msg: String,
+    #[sql_type = "Nullable<Text>"]
+    pub field: Option<String>,
+}
+
+/// `msg` types and their numeric fields in logs since the given time
+pub struct MetricNamesQuery(pub NaiveDateTime);
+
+impl Message for MetricNamesQuery {
+    type Result = Result<Vec<MetricName>, Error>;
+}
+
+impl Handler<MetricNamesQuery> for DbExecutor {
+    type Result = Result<Vec<MetricName>, Error>;
+
+    fn handle(&mut self, msg: MetricNamesQuery, _: &mut Self::Context) -> Self::Result {
+        self.get_metric_names(msg.0)
+    }
+}
+
+#[derive(Serialize, Debug, QueryableByName)]
+pub struct Annotation {
+    #[sql_type = "Timestamp"]
+    pub time: NaiveDateTime,
+    #[sql_type = "Text"]
+    pub title: String,
+    #[sql_type = "Text"]
+    pub text: String,
+    #[sql_type = "Array<Text>"]
+    pub tags: Vec<String>,
+}
+
+/// Benchmark events and/or node connections between `start_time` and `end_time`
+pub struct AnnotationsQuery {
+    pub start_time: NaiveDateTime,
+    pub end_time: NaiveDateTime,
+    pub peer_id: Option<String>,
+    pub benchmarks: bool,
+    pub connects: bool,
+}
+
+impl Message for AnnotationsQuery {
+    type Result = Result<Vec<Annotation>, Error>;
+}
+
+impl Handler<AnnotationsQuery> for DbExecutor {
+    type Result = Result<Vec<Annotation>, Error>;
+
+    fn handle(&mut self, msg: AnnotationsQuery, _: &mut Self::Context) -> Self::Result {
+        self.get_annotations(msg)
+    }
+}
+
+impl DbExecutor {
+    fn get_metric_names(&self, since: NaiveDateTime) -> Result<Vec<MetricName>, Error> {
+        match self.with_connection(|conn| {
+            let query = sql_query(
+                "SELECT DISTINCT sl.logs->>'msg' AS msg, f.key AS field \
+                 FROM substrate_logs sl \
+                 LEFT JOIN LATERAL jsonb_each(\
+                 CASE WHEN jsonb_typeof(sl.logs) = 'object' THEN sl.logs ELSE '{}' END\
+                 ) f ON jsonb_typeof(f.value) = 'number' \
+                 WHERE sl.created_at > $1 \
+                 AND sl.logs->>'msg' IS NOT NULL \
+                 ORDER BY msg, field NULLS FIRST",
+            )
+            .bind::<Timestamp, _>(since);
+            debug!(
+                "get_metric_names query: {}",
+                diesel::debug_query::<diesel::pg::Pg, _>(&query)
+            );
+            let result: QueryResult<Vec<MetricName>> = query.get_results(conn);
+            result
+        }) {
+            Ok(Ok(v)) => Ok(v),
+            Ok(Err(e)) => Err(e.into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn get_annotations(&self, msg: AnnotationsQuery) -> Result<Vec<Annotation>, Error> {
+        match self.with_connection(|conn| {
+            let query = sql_query(
+                "SELECT be.created_at AS time, \
+                 be.name || ' ' || be.phase AS title, \
+                 'benchmark ' || be.benchmark_id AS text, \
+                 ARRAY['benchmark', be.phase]::TEXT[] AS tags \
+                 FROM benchmark_events be \
+                 INNER JOIN benchmarks b ON b.id = be.benchmark_id \
+                 WHERE $4 \
+                 AND be.created_at >= $1 \
+                 AND be.created_at < $2 \
+                 AND ($3::TEXT IS NULL OR b.setup->'substrate'->>'peerId' = $3) \
+                 UNION ALL \
+                 SELECT pc.created_at AS time, \
+                 'connected ' || COALESCE(pc.name, pc.peer_id, pc.ip_addr) AS title, \
+                 concat_ws(' ', pc.peer_id, pc.chain, pc.version, pc.ip_addr) AS text, \
+                 ARRAY['connect']::TEXT[] AS tags \
+                 FROM peer_connections pc \
+                 WHERE $5 \
+                 AND pc.created_at >= $1 \
+                 AND pc.created_at < $2 \
+                 AND ($3::TEXT IS NULL OR pc.peer_id = $3) \
+                 ORDER BY time \
+                 LIMIT $6",
+            )
+            .bind::<Timestamp, _>(msg.start_time)
+            .bind::<Timestamp, _>(msg.end_time)
+            .bind::<Nullable<Text>, _>(msg.peer_id)
+            .bind::<Bool, _>(msg.benchmarks)
+            .bind::<Bool, _>(msg.connects)
+            .bind::<Integer, _>(RECORD_LIMIT);
+            debug!(
+                "get_annotations query: {}",
+                diesel::debug_query::<diesel::pg::Pg, _>(&query)
+            );
+            let result: QueryResult<Vec<Annotation>> = query.get_results(conn);
+            result
+        }) {
+            Ok(Ok(v)) => Ok(v),
+            Ok(Err(e)) => Err(e.into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
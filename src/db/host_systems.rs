@@ -0,0 +1,284 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate Analytics.
+
+// Substrate Analytics is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate Analytics is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
+
+use actix::prelude::*;
+use diesel::prelude::*;
+use diesel::sql_types::*;
+use failure::Error;
+use serde_json::Value;
+
+use super::models::{HostSystem, NewHostSystem};
+use super::DbExecutor;
+
+/// Longest accepted `description`, `os` or `disk_info`
+const MAX_HOST_SYSTEM_TEXT_LEN: usize = 1024;
+
+impl NewHostSystem {
+    pub fn validate(&self) -> Result<(), String> {
+        for (field, value) in &[
+            ("description", &self.description),
+            ("os", &self.os),
+            ("disk_info", &self.disk_info),
+        ] {
+            if value.trim().is_empty() {
+                return Err(format!("`{}` must not be empty", field));
+            }
+            if value.len() > MAX_HOST_SYSTEM_TEXT_LEN {
+                return Err(format!(
+                    "`{}` must be at most {} bytes",
+                    field, MAX_HOST_SYSTEM_TEXT_LEN
+                ));
+            }
+        }
+        for (field, value) in &[
+            ("cpu_qty", self.cpu_qty),
+            ("cpu_clock", self.cpu_clock),
+            ("ram_mb", self.ram_mb),
+        ] {
+            if *value < 1 {
+                return Err(format!("`{}` must be at least 1", field));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Id of the host system a benchmark `setup` refers to with `hostSystemId`, if any
+pub fn setup_host_system_id(setup: &Value) -> Result<Option<i32>, String> {
+    match setup.get("hostSystemId") {
+        None | Some(Value::Null) => Ok(None),
+        Some(id) => id
+            .as_i64()
+            .filter(|id| *id >= i32::MIN as i64 && *id <= i32::MAX as i64)
+            .map(|id| Some(id as i32))
+            .ok_or_else(|| format!("`hostSystemId` must be an integer, got {}", id)),
+    }
+}
+
+pub enum HostSystemsQuery {
+    All,
+    One(i32),
+}
+
+impl Message for HostSystemsQuery {
+    type Result = Result<Vec<HostSystem>, Error>;
+}
+
+impl Handler<HostSystemsQuery> for DbExecutor {
+    type Result = Result<Vec<HostSystem>, Error>;
+
+    fn handle(&mut self, msg: HostSystemsQuery, _: &mut Self::Context) -> Self::Result {
+        self.get_host_systems(msg)
+    }
+}
+
+impl Message for NewHostSystem {
+    type Result = Result<HostSystem, Error>;
+}
+
+impl Handler<NewHostSystem> for DbExecutor {
+    type Result = Result<HostSystem, Error>;
+
+    fn handle(&mut self, msg: NewHostSystem, _: &mut Self::Context) -> Self::Result {
+        self.save_host_system(msg)
+    }
+}
+
+/// Replace all the fields of a host system, returns `None` if it doesn't exist
+pub struct UpdateHostSystem {
+    pub id: i32,
+    pub host_system: NewHostSystem,
+}
+
+impl Message for UpdateHostSystem {
+    type Result = Result<Option<HostSystem>, Error>;
+}
+
+impl Handler<UpdateHostSystem> for DbExecutor {
+    type Result = Result<Option<HostSystem>, Error>;
+
+    fn handle(&mut self, msg: UpdateHostSystem, _: &mut Self::Context) -> Self::Result {
+        self.update_host_system(msg)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum HostSystemDeletion {
+    Deleted(HostSystem),
+    NotFound,
+    /// Benchmarks referring to the host system, which is kept
+    InUse(Vec<i32>),
+}
+
+pub struct DeleteHostSystem(pub i32);
+
+impl Message for DeleteHostSystem {
+    type Result = Result<HostSystemDeletion, Error>;
+}
+
+impl Handler<DeleteHostSystem> for DbExecutor {
+    type Result = Result<HostSystemDeletion, Error>;
+
+    fn handle(&mut self, msg: DeleteHostSystem, _: &mut Self::Context) -> Self::Result {
+        self.delete_host_system(msg.0)
+    }
+}
+
+/// Lock a host system against being deleted until the end of the transaction, `None` if it
+/// doesn't exist
+pub(super) fn share_host_system(
+    conn: &PgConnection,
+    host_id: i32,
+) -> QueryResult<Option<HostSystem>> {
+    use crate::schema::host_systems::dsl::*;
+    host_systems
+        .filter(id.eq(host_id))
+        .for_share()
+        .first::<HostSystem>(conn)
+        .optional()
+}
+
+#[derive(QueryableByName)]
+struct BenchmarkId {
+    #[sql_type = "Integer"]
+    id: i32,
+}
+
+impl DbExecutor {
+    fn get_host_systems(&self, msg: HostSystemsQuery) -> Result<Vec<HostSystem>, Error> {
+        match self.with_connection(|conn| {
+            use crate::schema::host_systems::dsl::*;
+            match msg {
+                HostSystemsQuery::All => host_systems.order(id.asc()).load::<HostSystem>(conn),
+                HostSystemsQuery::One(host_id) => {
+                    host_systems.filter(id.eq(host_id)).load::<HostSystem>(conn)
+                }
+            }
+        }) {
+            Ok(Ok(v)) => Ok(v),
+            Ok(Err(e)) => Err(e.into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn save_host_system(&self, msg: NewHostSystem) -> Result<HostSystem, Error> {
+        match self.with_connection(|conn| {
+            use crate::schema::host_systems;
+            diesel::insert_into(host_systems::table)
+                .values(msg)
+                .get_result::<HostSystem>(conn)
+        }) {
+            Ok(Ok(v)) => Ok(v),
+            Ok(Err(e)) => Err(e.into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn update_host_system(&self, msg: UpdateHostSystem) -> Result<Option<HostSystem>, Error> {
+        match self.with_connection(|conn| {
+            use crate::schema::host_systems::dsl::*;
+            diesel::update(host_systems.filter(id.eq(msg.id)))
+                .set(&msg.host_system)
+                .get_result::<HostSystem>(conn)
+                .optional()
+        }) {
+            Ok(Ok(v)) => Ok(v),
+            Ok(Err(e)) => Err(e.into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn delete_host_system(&self, host_id: i32) -> Result<HostSystemDeletion, Error> {
+        match self.with_connection(|conn| {
+            conn.transaction::<_, diesel::result::Error, _>(|| {
+                use crate::schema::host_systems::dsl::*;
+                // Waits for benchmarks being created with this host system, see `save_benchmark`
+                let locked = host_systems
+                    .filter(id.eq(host_id))
+                    .for_update()
+                    .first::<HostSystem>(conn)
+                    .optional()?;
+                if locked.is_none() {
+                    return Ok(HostSystemDeletion::NotFound);
+                }
+                // Benchmarks refer to host systems in their JSON setup, so there is no foreign key
+                let query = diesel::sql_query(
+                    "SELECT id FROM benchmarks \
+                     WHERE setup->'hostSystemId' = to_jsonb($1) \
+                     ORDER BY id \
+                     FOR UPDATE",
+                )
+                .bind::<Integer, _>(host_id);
+                debug!(
+                    "delete_host_system query: {}",
+                    diesel::debug_query::<diesel::pg::Pg, _>(&query)
+                );
+                let benchmarks: Vec<BenchmarkId> = query.get_results(conn)?;
+                if !benchmarks.is_empty() {
+                    return Ok(HostSystemDeletion::InUse(
+                        benchmarks.into_iter().map(|b| b.id).collect(),
+                    ));
+                }
+                let deleted = diesel::delete(host_systems.filter(id.eq(host_id)))
+                    .get_result::<HostSystem>(conn)
+                    .optional()?;
+                Ok(deleted.map_or(HostSystemDeletion::NotFound, HostSystemDeletion::Deleted))
+            })
+        }) {
+            Ok(Ok(v)) => Ok(v),
+            Ok(Err(e)) => Err(e.into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host_system() -> NewHostSystem {
+        NewHostSystem {
+            description: "Any notes to go here".to_string(),
+            os: "freebsd".to_string(),
+            cpu_qty: 4,
+            cpu_clock: 2600,
+            ram_mb: 8192,
+            disk_info: "NVME".to_string(),
+        }
+    }
+
+    #[test]
+    fn validate_host_system_test() {
+        assert!(host_system().validate().is_ok());
+        let mut invalid = host_system();
+        invalid.os = " ".to_string();
+        assert!(invalid.validate().is_err());
+        let mut invalid = host_system();
+        invalid.ram_mb = 0;
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn parse_setup_host_system_id_test() {
+        assert_eq!(setup_host_system_id(&json!({})), Ok(None));
+        assert_eq!(
+            setup_host_system_id(&json!({"hostSystemId": 2})),
+            Ok(Some(2))
+        );
+        assert!(setup_host_system_id(&json!({"hostSystemId": "2"})).is_err());
+        assert!(setup_host_system_id(&json!({"hostSystemId": 1u64 << 40})).is_err());
+    }
+}
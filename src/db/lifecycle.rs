@@ -0,0 +1,327 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate Analytics.
+
+// Substrate Analytics is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate Analytics is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
+
+use actix::prelude::*;
+use chrono::{NaiveDateTime, Utc};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::*;
+use failure::Error;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use super::compare::profiling_time;
+use super::models::Benchmark;
+use super::pins::close_pins;
+use super::DbExecutor;
+
+/// `name` of the benchmark events that change the state of the benchmark they belong to
+pub const LIFECYCLE_EVENT_NAME: &str = "benchmark";
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BenchmarkState {
+    Running,
+    Finished,
+    Failed,
+    Aborted,
+}
+
+impl BenchmarkState {
+    /// Benchmarks are created running and can then only be stopped once
+    pub fn can_transition_to(self, next: BenchmarkState) -> bool {
+        self == BenchmarkState::Running && next != BenchmarkState::Running
+    }
+
+    /// State a benchmark event moves its benchmark to, if it is a lifecycle event
+    pub fn from_event(name: &str, phase: &str) -> Option<Self> {
+        if name != LIFECYCLE_EVENT_NAME {
+            return None;
+        }
+        match phase {
+            "end" => Some(BenchmarkState::Finished),
+            "fail" => Some(BenchmarkState::Failed),
+            "abort" => Some(BenchmarkState::Aborted),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for BenchmarkState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BenchmarkState::Running => write!(f, "running"),
+            BenchmarkState::Finished => write!(f, "finished"),
+            BenchmarkState::Failed => write!(f, "failed"),
+            BenchmarkState::Aborted => write!(f, "aborted"),
+        }
+    }
+}
+
+impl FromStr for BenchmarkState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "running" => Ok(BenchmarkState::Running),
+            "finished" => Ok(BenchmarkState::Finished),
+            "failed" => Ok(BenchmarkState::Failed),
+            "aborted" => Ok(BenchmarkState::Aborted),
+            _ => Err(format!("Unknown benchmark state `{}`", s)),
+        }
+    }
+}
+
+/// Outcome of a change to a benchmark that depends on its state
+#[derive(Debug)]
+pub enum BenchmarkUpdate<T> {
+    Saved(T),
+    NotFound,
+    /// The change isn't allowed in the current state of the benchmark
+    Conflict(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TargetTimings {
+    pub count: i64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+}
+
+/// Results of a benchmark saved when it stops running
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BenchmarkSummary {
+    /// From the first event of the benchmark to when it stopped
+    pub duration_ms: i64,
+    pub message_count: i64,
+    /// Number of messages logged by the benchmarked node of each type
+    pub message_counts: BTreeMap<String, i64>,
+    /// Profiling `time` (ns) percentiles of each target
+    pub timings: BTreeMap<String, TargetTimings>,
+}
+
+#[derive(QueryableByName)]
+struct MessageCount {
+    #[sql_type = "Text"]
+    msg: String,
+    #[sql_type = "BigInt"]
+    count: i64,
+}
+
+#[derive(QueryableByName)]
+struct TargetTimingsRow {
+    #[sql_type = "Text"]
+    target: String,
+    #[sql_type = "BigInt"]
+    count: i64,
+    #[sql_type = "Double"]
+    p50: f64,
+    #[sql_type = "Double"]
+    p90: f64,
+    #[sql_type = "Double"]
+    p99: f64,
+}
+
+/// Move a benchmark to `state` now, eg. to abort it
+pub struct TransitionBenchmark {
+    pub benchmark_id: i32,
+    pub state: BenchmarkState,
+}
+
+impl Message for TransitionBenchmark {
+    type Result = Result<BenchmarkUpdate<Benchmark>, Error>;
+}
+
+impl Handler<TransitionBenchmark> for DbExecutor {
+    type Result = Result<BenchmarkUpdate<Benchmark>, Error>;
+
+    fn handle(&mut self, msg: TransitionBenchmark, _: &mut Self::Context) -> Self::Result {
+        match self.with_connection(|conn| {
+            conn.transaction::<_, diesel::result::Error, _>(|| {
+                match lock_benchmark(conn, msg.benchmark_id)? {
+                    Some(benchmark) => {
+                        transition(conn, benchmark, msg.state, Utc::now().naive_utc())
+                    }
+                    None => Ok(BenchmarkUpdate::NotFound),
+                }
+            })
+        }) {
+            Ok(Ok(v)) => Ok(v),
+            Ok(Err(e)) => Err(e.into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Select a benchmark, locking it until the end of the transaction
+pub(super) fn lock_benchmark(conn: &PgConnection, bm_id: i32) -> QueryResult<Option<Benchmark>> {
+    use crate::schema::benchmarks::dsl::*;
+    benchmarks
+        .filter(id.eq(bm_id))
+        .for_update()
+        .first::<Benchmark>(conn)
+        .optional()
+}
+
+/// Move a locked benchmark to `state` at time `at`, saving its summary
+pub(super) fn transition(
+    conn: &PgConnection,
+    benchmark: Benchmark,
+    next: BenchmarkState,
+    at: NaiveDateTime,
+) -> QueryResult<BenchmarkUpdate<Benchmark>> {
+    let current = benchmark
+        .state
+        .parse::<BenchmarkState>()
+        .unwrap_or(BenchmarkState::Running);
+    if !current.can_transition_to(next) {
+        return Ok(BenchmarkUpdate::Conflict(format!(
+            "Benchmark {} can't go from {} to {}",
+            benchmark.id, current, next
+        )));
+    }
+    let start = window_start(conn, &benchmark, at)?;
+    let bm_summary = summarise(conn, &benchmark, start, at)?;
+    close_pins(conn, benchmark.id, start, at)?;
+    use crate::schema::benchmarks::dsl::*;
+    diesel::update(benchmarks.filter(id.eq(benchmark.id)))
+        .set((
+            state.eq(next.to_string()),
+            finished_at.eq(at),
+            summary.eq(json!(bm_summary)),
+        ))
+        .get_result::<Benchmark>(conn)
+        .map(BenchmarkUpdate::Saved)
+}
+
+/// Start of a benchmark stopping at `at`, its first event or its creation if it has none
+fn window_start(
+    conn: &PgConnection,
+    benchmark: &Benchmark,
+    at: NaiveDateTime,
+) -> QueryResult<NaiveDateTime> {
+    use crate::schema::benchmark_events::dsl::*;
+    Ok(benchmark_events
+        .filter(benchmark_id.eq(benchmark.id))
+        .select(diesel::dsl::min(created_at))
+        .first::<Option<NaiveDateTime>>(conn)?
+        .unwrap_or(benchmark.created_at)
+        .min(at))
+}
+
+fn summarise(
+    conn: &PgConnection,
+    benchmark: &Benchmark,
+    start: NaiveDateTime,
+    at: NaiveDateTime,
+) -> QueryResult<BenchmarkSummary> {
+    let peer_logs = "FROM substrate_logs sl \
+                     WHERE sl.peer_connection_id IN ( \
+                     SELECT id FROM peer_connections WHERE peer_id = \
+                     (SELECT setup->'substrate'->>'peerId' FROM benchmarks WHERE id = $1) \
+                     ) \
+                     AND sl.created_at BETWEEN $2 AND $3";
+    let query = sql_query(format!(
+        "SELECT sl.logs->>'msg' AS msg, count(*) AS count \
+         {} \
+         AND sl.logs->>'msg' IS NOT NULL \
+         GROUP BY 1",
+        peer_logs
+    ))
+    .bind::<Integer, _>(benchmark.id)
+    .bind::<Timestamp, _>(start)
+    .bind::<Timestamp, _>(at);
+    debug!(
+        "summarise message counts query: {}",
+        diesel::debug_query::<diesel::pg::Pg, _>(&query)
+    );
+    let counts: Vec<MessageCount> = query.get_results(conn)?;
+    let query = sql_query(format!(
+        "SELECT target, count(*) AS count, \
+         percentile_cont(0.5) WITHIN GROUP (ORDER BY t) AS p50, \
+         percentile_cont(0.9) WITHIN GROUP (ORDER BY t) AS p90, \
+         percentile_cont(0.99) WITHIN GROUP (ORDER BY t) AS p99 \
+         FROM ( \
+         SELECT sl.logs->>'target' AS target, {} AS t \
+         {} \
+         AND sl.logs->>'msg' = 'tracing.profiling' \
+         ) s \
+         WHERE t >= 0 AND target IS NOT NULL \
+         GROUP BY target",
+        profiling_time("sl"),
+        peer_logs
+    ))
+    .bind::<Integer, _>(benchmark.id)
+    .bind::<Timestamp, _>(start)
+    .bind::<Timestamp, _>(at);
+    debug!(
+        "summarise timings query: {}",
+        diesel::debug_query::<diesel::pg::Pg, _>(&query)
+    );
+    let timings: Vec<TargetTimingsRow> = query.get_results(conn)?;
+    Ok(BenchmarkSummary {
+        duration_ms: (at - start).num_milliseconds(),
+        message_count: counts.iter().map(|c| c.count).sum(),
+        message_counts: counts.into_iter().map(|c| (c.msg, c.count)).collect(),
+        timings: timings
+            .into_iter()
+            .map(|t| {
+                (
+                    t.target,
+                    TargetTimings {
+                        count: t.count,
+                        p50: t.p50,
+                        p90: t.p90,
+                        p99: t.p99,
+                    },
+                )
+            })
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_running_benchmarks_can_stop_test() {
+        use BenchmarkState::*;
+        assert!(Running.can_transition_to(Finished));
+        assert!(Running.can_transition_to(Aborted));
+        assert!(!Running.can_transition_to(Running));
+        assert!(!Finished.can_transition_to(Failed));
+        assert!(!Aborted.can_transition_to(Running));
+    }
+
+    #[test]
+    fn lifecycle_events_test() {
+        assert_eq!(
+            BenchmarkState::from_event("benchmark", "end"),
+            Some(BenchmarkState::Finished)
+        );
+        assert_eq!(
+            BenchmarkState::from_event("benchmark", "abort"),
+            Some(BenchmarkState::Aborted)
+        );
+        assert_eq!(BenchmarkState::from_event("benchmark", "start"), None);
+        assert_eq!(BenchmarkState::from_event("import", "end"), None);
+        assert_eq!("failed".parse(), Ok(BenchmarkState::Failed));
+    }
+}
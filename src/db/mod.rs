@@ -14,13 +14,27 @@
 // You should have received a copy of the GNU General Public License
 // along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
 
+pub mod aggregate;
 pub mod benchmarks;
+pub mod bulk;
+pub mod chains;
+pub mod compare;
+pub mod export;
 pub mod filters;
+pub mod grafana;
+pub mod host_systems;
+pub mod lifecycle;
 pub mod models;
 pub mod nodes;
+pub mod partitions;
 pub mod peer_data;
+pub mod phases;
+pub mod pins;
 pub mod reputation;
+pub mod sessions;
 pub mod stats;
+pub mod telemetry;
+pub mod tokens;
 
 use actix::prelude::*;
 use diesel;
@@ -30,13 +44,18 @@ use diesel::r2d2::{ConnectionManager, Pool, PoolError};
 use diesel::result::QueryResult;
 use diesel::RunQueryDsl;
 
+use self::bulk::{CopyFailure, CopyPool};
 use self::models::{NewPeerConnection, NewSubstrateLog, PeerConnection};
-use crate::{DATABASE_URL, DB_POOL_SIZE};
+use self::telemetry::{TypedLogs, TYPED_TABLES};
+use crate::web::metrics::Metrics;
+use crate::{DATABASE_URL, DB_POOL_SIZE, INSTANCE_ID};
 
 pub const RECORD_LIMIT: i32 = 10_000;
 
 pub struct DbExecutor {
     pool: Pool<ConnectionManager<PgConnection>>,
+    copy_pool: CopyPool,
+    metrics: Metrics,
 }
 
 impl Actor for DbExecutor {
@@ -56,8 +75,16 @@ impl DbExecutor {
         result
     }
 
-    pub fn new(pool: Pool<ConnectionManager<PgConnection>>) -> Self {
-        DbExecutor { pool }
+    pub fn new(
+        pool: Pool<ConnectionManager<PgConnection>>,
+        copy_pool: CopyPool,
+        metrics: Metrics,
+    ) -> Self {
+        DbExecutor {
+            pool,
+            copy_pool,
+            metrics,
+        }
     }
 }
 
@@ -74,7 +101,7 @@ impl Handler<NewPeerConnection> for DbExecutor {
         use crate::schema::peer_connections::dsl::*;
         let pc: Result<Result<PeerConnection, _>, _> = self.with_connection(|conn| {
             let result: QueryResult<PeerConnection> = diesel::insert_into(peer_connections::table)
-                .values(&msg)
+                .values((&msg, instance_id.eq(INSTANCE_ID.as_str())))
                 .get_result(conn);
             result
         });
@@ -90,6 +117,61 @@ impl Handler<NewPeerConnection> for DbExecutor {
     }
 }
 
+/// Look up an existing `PeerConnection` by id, or the most recent one for `peer_id`
+pub struct FindPeerConnection {
+    pub id: Option<i32>,
+    pub peer_id: Option<String>,
+    pub audit: bool,
+    /// Only consider connections created by `/ingest/` from this origin
+    pub ingest: Option<IngestOrigin>,
+}
+
+/// IP and token an `/ingest/` request was made with
+pub struct IngestOrigin {
+    pub ip_addr: String,
+    pub token_id: Option<i32>,
+}
+
+impl Message for FindPeerConnection {
+    type Result = Result<Option<PeerConnection>, String>;
+}
+
+impl Handler<FindPeerConnection> for DbExecutor {
+    type Result = Result<Option<PeerConnection>, String>;
+
+    fn handle(&mut self, msg: FindPeerConnection, _: &mut Self::Context) -> Self::Result {
+        #[allow(unused_imports)]
+        use crate::schema::peer_connections::dsl::*;
+        let result = self.with_connection(|conn| {
+            let mut query = peer_connections.filter(audit.eq(msg.audit)).into_boxed();
+            if let Some(pc_id) = msg.id {
+                query = query.filter(id.eq(pc_id));
+            }
+            if let Some(pid) = &msg.peer_id {
+                query = query.filter(peer_id.eq(pid));
+            }
+            if let Some(origin) = &msg.ingest {
+                query = query
+                    .filter(http_ingest.eq(true))
+                    .filter(ip_addr.eq(&origin.ip_addr));
+                query = match origin.token_id {
+                    Some(token_id) => query.filter(ingest_token_id.eq(token_id)),
+                    None => query.filter(ingest_token_id.is_null()),
+                };
+            }
+            query
+                .order(created_at.desc())
+                .first::<PeerConnection>(conn)
+                .optional()
+        });
+        match result {
+            Ok(Ok(pc)) => Ok(pc),
+            Ok(Err(e)) => Err(format!("Error finding PeerConnection: {}", e)),
+            Err(e) => Err(format!("Error finding PeerConnection: {}", e)),
+        }
+    }
+}
+
 impl Message for PeerConnection {
     type Result = Result<(), String>;
 }
@@ -125,35 +207,58 @@ impl Handler<PeerConnection> for DbExecutor {
     }
 }
 
+/// Batch of logs to save, the logs that weren't saved are returned as the error if the DB
+/// could not be reached so that they can be retried later
 pub struct LogBatch(pub Vec<NewSubstrateLog>);
 
 impl Message for LogBatch {
-    type Result = Result<(), &'static str>;
+    type Result = Result<(), LogBatch>;
 }
 
 impl Handler<LogBatch> for DbExecutor {
-    type Result = Result<(), &'static str>;
+    type Result = Result<(), LogBatch>;
 
     fn handle(&mut self, msg: LogBatch, _: &mut Self::Context) -> Self::Result {
-        use crate::schema::substrate_logs;
-        #[allow(unused_imports)]
-        use crate::schema::substrate_logs::dsl::*;
-        let _ = self.with_connection(|conn| {
-            match diesel::insert_into(substrate_logs::table)
-                .values(msg.0)
-                .execute(conn)
-            {
-                Err(e) => error!("Error inserting logs: {:?}", e),
-                Ok(n) => debug!("Inserted {} substrate_logs", n),
+        let mut logs = msg.0;
+        let (outcome, error) = match self.copy_logs(&logs) {
+            Ok(outcome) => (outcome, None),
+            Err(CopyFailure { error, outcome }) => (outcome, Some(error)),
+        };
+        let handled = (outcome.written + outcome.rejected) as usize;
+        if handled > 0 || error.is_none() {
+            debug!(
+                "Inserted {} substrate_logs, rejected {}, in {:?}",
+                outcome.written, outcome.rejected, outcome.duration
+            );
+            self.metrics
+                .record_log_batch(outcome.written, outcome.rejected, outcome.duration);
+            let typed_logs = TypedLogs::from_logs(&logs[..handled]);
+            if !typed_logs.is_empty() {
+                let _ = self.with_connection(|conn| match typed_logs.save(conn) {
+                    Err(e) => error!("Error inserting typed logs: {:?}", e),
+                    Ok(n) => debug!("Inserted {} typed logs", n),
+                });
             }
-        });
-        Ok(())
+        }
+        match error {
+            // Only the logs that weren't handled are returned, so none are written twice
+            Some(e) => {
+                let unwritten = logs.split_off(handled);
+                error!("Unable to write {} logs to DB: {}", unwritten.len(), e);
+                Err(LogBatch(unwritten))
+            }
+            None => Ok(()),
+        }
     }
 }
 
 #[derive(Clone)]
 pub struct PurgeLogs {
     pub hours_valid: u32,
+    /// Hours to keep closed sessions after they end, even if all their logs have been purged
+    pub session_retention_h: u32,
+    /// Hours after which pins of benchmarks that never stopped no longer keep logs
+    pub max_pin_h: u32,
 }
 
 impl Message for PurgeLogs {
@@ -165,24 +270,53 @@ impl Handler<PurgeLogs> for DbExecutor {
 
     fn handle(&mut self, msg: PurgeLogs, _: &mut Self::Context) -> Self::Result {
         let _ = self.with_connection(|conn| {
+            info!("Cleaning up database - dropping expired log partitions");
+            match partitions::drop_expired_partitions(conn, msg.hours_valid, msg.max_pin_h) {
+                Err(e) => error!("Error dropping expired log partitions: {:?}", e),
+                Ok(n) => info!("Dropped {} expired log partitions", n),
+            }
+            // Logs outside of any created partition range end up in the default partition
             let query = format!(
-                "DELETE FROM substrate_logs \
-                 USING peer_connections \
-                 WHERE peer_connections.id = peer_connection_id \
-                 AND audit = false \
-                 AND substrate_logs.created_at < now() - {} * interval '1 hour'",
-                msg.hours_valid
+                "DELETE FROM substrate_logs_expiring_default sl \
+                 USING peer_connections pc \
+                 WHERE pc.id = sl.peer_connection_id \
+                 AND sl.created_at < now() - {} * interval '1 hour' \
+                 AND {}",
+                msg.hours_valid,
+                pins::unpinned("sl", "pc", msg.max_pin_h)
             );
             info!("Cleaning up database - deleting old log messages");
             match diesel::sql_query(query).execute(conn) {
                 Err(e) => error!("Error purging expired logs: {:?}", e),
                 Ok(n) => info!("Purged {} records from database", n),
             }
+            for table in TYPED_TABLES.iter() {
+                let query = format!(
+                    "DELETE FROM {table} \
+                     USING peer_connections \
+                     WHERE peer_connections.id = peer_connection_id \
+                     AND audit = false \
+                     AND {table}.created_at < now() - {} * interval '1 hour' \
+                     AND {}",
+                    msg.hours_valid,
+                    pins::unpinned(table, "peer_connections", msg.max_pin_h),
+                    table = table
+                );
+                match diesel::sql_query(query).execute(conn) {
+                    Err(e) => error!("Error purging expired {}: {:?}", table, e),
+                    Ok(n) => info!("Purged {} records from {}", n, table),
+                }
+            }
         });
         let _ = self.with_connection(|conn| {
-            let query = "DELETE FROM peer_connections \
+            // Open sessions and recently closed ones are kept for the session history
+            let query = format!(
+                "DELETE FROM peer_connections \
                  WHERE id NOT IN \
-                 (SELECT DISTINCT peer_connection_id FROM substrate_logs)";
+                 (SELECT DISTINCT peer_connection_id FROM substrate_logs) \
+                 AND disconnected_at < now() - {} * interval '1 hour'",
+                msg.session_retention_h
+            );
             info!("Cleaning up database - deleting unreferenced peer_connections");
             match diesel::sql_query(query).execute(conn) {
                 Err(e) => error!("Error purging expired peer_connections: {:?}", e),
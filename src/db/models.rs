@@ -14,11 +14,14 @@
 // You should have received a copy of the GNU General Public License
 // along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::schema::{benchmark_events, benchmarks, peer_connections, substrate_logs};
+use crate::schema::{
+    afg_finalized, benchmark_events, benchmarks, block_imports, host_systems, ingest_tokens,
+    log_pins, notify_finalized, peer_connections, substrate_logs, system_intervals, txpool_imports,
+};
 use chrono::NaiveDateTime;
 use serde_json::Value;
 
-#[derive(Queryable, Identifiable, PartialEq, Serialize, Debug)]
+#[derive(Queryable, Identifiable, PartialEq, Serialize, Clone, Debug)]
 #[table_name = "benchmark_events"]
 pub struct BenchmarkEvent {
     pub id: i32,
@@ -43,6 +46,11 @@ pub struct Benchmark {
     pub id: i32,
     pub setup: Value,
     pub created_at: NaiveDateTime,
+    /// One of `BenchmarkState`, only `running` benchmarks accept events
+    pub state: String,
+    pub finished_at: Option<NaiveDateTime>,
+    /// `BenchmarkSummary` saved when the benchmark stops running
+    pub summary: Option<Value>,
 }
 
 #[derive(Insertable, Debug, Serialize, Deserialize)]
@@ -51,21 +59,70 @@ pub struct NewBenchmark {
     pub setup: Value,
 }
 
+/// Logs of `peer_id` between `start_time` and `end_time` are kept past `LOG_EXPIRY_H` for a benchmark
+#[derive(Queryable, Identifiable, PartialEq, Serialize, Clone, Debug)]
+#[table_name = "log_pins"]
+pub struct LogPin {
+    pub id: i32,
+    pub benchmark_id: i32,
+    pub peer_id: String,
+    pub start_time: NaiveDateTime,
+    /// `None` while the benchmark is running
+    pub end_time: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "log_pins"]
+pub struct NewLogPin {
+    pub benchmark_id: i32,
+    pub peer_id: String,
+    pub start_time: NaiveDateTime,
+}
+
+/// Machine a benchmark ran on, referenced by `hostSystemId` in the benchmark setup
+#[derive(Queryable, Identifiable, PartialEq, Serialize, Clone, Debug)]
+#[table_name = "host_systems"]
+pub struct HostSystem {
+    pub id: i32,
+    pub description: String,
+    pub os: String,
+    pub cpu_qty: i32,
+    /// MHz
+    pub cpu_clock: i32,
+    pub ram_mb: i32,
+    pub disk_info: String,
+}
+
+#[derive(Insertable, AsChangeset, Debug, Serialize, Deserialize)]
+#[table_name = "host_systems"]
+pub struct NewHostSystem {
+    pub description: String,
+    pub os: String,
+    pub cpu_qty: i32,
+    pub cpu_clock: i32,
+    pub ram_mb: i32,
+    pub disk_info: String,
+}
+
 #[derive(Queryable, QueryableByName, Identifiable, Serialize, PartialEq, Clone, Debug)]
 #[table_name = "substrate_logs"]
+#[primary_key(id, audit, created_at)]
 pub struct SubstrateLog {
     pub id: i32,
     pub created_at: NaiveDateTime,
     pub logs: Value,
     pub peer_connection_id: Option<i32>,
+    pub audit: bool,
 }
 
-#[derive(Insertable, Debug, Serialize, Deserialize)]
+#[derive(Insertable, Clone, Debug, Serialize, Deserialize)]
 #[table_name = "substrate_logs"]
 pub struct NewSubstrateLog {
     pub logs: Value,
     pub peer_connection_id: i32,
     pub created_at: NaiveDateTime,
+    pub audit: bool,
 }
 
 #[derive(Queryable, QueryableByName, Identifiable, Serialize, PartialEq, Clone, Debug)]
@@ -82,6 +139,17 @@ pub struct PeerConnection {
     pub authority: Option<bool>,
     pub startup_time: Option<i64>,
     pub implementation: Option<String>,
+    /// Address of the direct peer, which differs from `ip_addr` when connecting through a proxy
+    pub peer_addr: Option<String>,
+    /// Created by `/ingest/`, which only appends to connections it created itself
+    pub http_ingest: bool,
+    /// Token the connection was created with by `/ingest/`
+    pub ingest_token_id: Option<i32>,
+    /// When the session ended, `None` while it is connected
+    pub disconnected_at: Option<NaiveDateTime>,
+    pub disconnect_reason: Option<String>,
+    /// `INSTANCE_ID` of the server that received the session
+    pub instance_id: Option<String>,
 }
 
 #[derive(Insertable, Debug, Serialize, Deserialize)]
@@ -96,4 +164,90 @@ pub struct NewPeerConnection {
     pub authority: Option<bool>,
     pub startup_time: Option<i64>,
     pub implementation: Option<String>,
+    /// Address of the direct peer, which differs from `ip_addr` when connecting through a proxy
+    pub peer_addr: Option<String>,
+    pub http_ingest: bool,
+    pub ingest_token_id: Option<i32>,
+}
+
+#[derive(Insertable, Debug, Serialize, Deserialize)]
+#[table_name = "block_imports"]
+pub struct NewBlockImport {
+    pub peer_connection_id: i32,
+    pub created_at: NaiveDateTime,
+    pub best: String,
+    pub height: i64,
+    pub origin: Option<String>,
+}
+
+#[derive(Insertable, Debug, Serialize, Deserialize)]
+#[table_name = "afg_finalized"]
+pub struct NewAfgFinalized {
+    pub peer_connection_id: i32,
+    pub created_at: NaiveDateTime,
+    pub finalized_hash: String,
+    pub finalized_number: i64,
+}
+
+#[derive(Insertable, Debug, Serialize, Deserialize)]
+#[table_name = "notify_finalized"]
+pub struct NewNotifyFinalized {
+    pub peer_connection_id: i32,
+    pub created_at: NaiveDateTime,
+    pub best: String,
+    pub height: i64,
+}
+
+#[derive(Insertable, Debug, Serialize, Deserialize)]
+#[table_name = "system_intervals"]
+pub struct NewSystemInterval {
+    pub peer_connection_id: i32,
+    pub created_at: NaiveDateTime,
+    pub peers: Option<i64>,
+    pub best: Option<String>,
+    pub height: Option<i64>,
+    pub finalized_hash: Option<String>,
+    pub finalized_height: Option<i64>,
+    pub txcount: Option<i64>,
+    pub bandwidth_download: Option<i64>,
+    pub bandwidth_upload: Option<i64>,
+    pub cpu: Option<f64>,
+    pub memory: Option<i64>,
+    pub disk_read_per_sec: Option<i64>,
+    pub disk_write_per_sec: Option<i64>,
+    pub used_state_cache_size: Option<i64>,
+    pub used_db_cache_size: Option<i64>,
+}
+
+#[derive(Insertable, Debug, Serialize, Deserialize)]
+#[table_name = "txpool_imports"]
+pub struct NewTxpoolImport {
+    pub peer_connection_id: i32,
+    pub created_at: NaiveDateTime,
+    pub ready: i64,
+    pub future: i64,
+}
+
+#[derive(Queryable, Identifiable, PartialEq, Serialize, Clone, Debug)]
+#[table_name = "ingest_tokens"]
+pub struct IngestToken {
+    pub id: i32,
+    #[serde(skip_serializing)]
+    pub token_hash: String,
+    pub description: Option<String>,
+    pub scope: String,
+    pub chain: Option<String>,
+    pub expires_at: Option<NaiveDateTime>,
+    pub revoked_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "ingest_tokens"]
+pub struct NewIngestToken {
+    pub token_hash: String,
+    pub description: Option<String>,
+    pub scope: String,
+    pub chain: Option<String>,
+    pub expires_at: Option<NaiveDateTime>,
 }
@@ -21,7 +21,10 @@ use diesel::{result::QueryResult, sql_query, RunQueryDsl};
 use failure::Error;
 use serde_json::Value;
 
-use super::{filters::Filters, DbExecutor, RECORD_LIMIT};
+use super::{
+    filters::{FieldPredicate, Filters, LogCursor, PredicateOp},
+    DbExecutor, RECORD_LIMIT,
+};
 use crate::db::models::PeerConnection;
 
 pub struct NodesQuery(pub Filters);
@@ -41,24 +44,14 @@ impl Handler<NodesQuery> for DbExecutor {
 pub struct LogsQuery(pub Filters);
 
 impl Message for LogsQuery {
-    type Result = Result<Vec<Log>, Error>;
+    type Result = Result<LogsPage, Error>;
 }
 
 impl Handler<LogsQuery> for DbExecutor {
-    type Result = Result<Vec<Log>, Error>;
+    type Result = Result<LogsPage, Error>;
 
     fn handle(&mut self, msg: LogsQuery, _: &mut Self::Context) -> Self::Result {
-        let has_msg = msg.0.msg.is_some();
-        let has_target = msg.0.target.is_some();
-        if has_msg {
-            if has_target {
-                self.get_log_msgs_with_target(msg.0)
-            } else {
-                self.get_log_msgs(msg.0)
-            }
-        } else {
-            self.get_all_logs(msg.0)
-        }
+        self.get_log_page(msg.0)
     }
 }
 
@@ -84,6 +77,8 @@ pub struct Node {
 
 #[derive(Serialize, Deserialize, Debug, QueryableByName)]
 pub struct Log {
+    #[sql_type = "Integer"]
+    pub id: i32,
     #[sql_type = "Text"]
     pub ip_addr: String,
     #[sql_type = "Text"]
@@ -96,6 +91,23 @@ pub struct Log {
     pub logs: Value,
 }
 
+impl Log {
+    pub fn cursor(&self) -> LogCursor {
+        LogCursor {
+            created_at: self.created_at,
+            id: self.id,
+        }
+    }
+}
+
+/// Logs, newest first, with cursors for the pages of older (`next`) and newer (`prev`) logs
+#[derive(Debug)]
+pub struct LogsPage {
+    pub logs: Vec<Log>,
+    pub next: Option<LogCursor>,
+    pub prev: Option<LogCursor>,
+}
+
 #[derive(Serialize, Deserialize, Debug, QueryableByName)]
 pub struct Stats {
     #[sql_type = "BigInt"]
@@ -226,7 +238,9 @@ impl DbExecutor {
         match self.with_connection(|conn| {
             let query = "SELECT DISTINCT ON (peer_id) peer_id, \
             id, ip_addr, created_at, audit, name, \
-            chain, version, authority, startup_time, implementation \
+            chain, version, authority, startup_time, implementation, peer_addr, \
+            http_ingest, ingest_token_id, \
+            disconnected_at, disconnect_reason, instance_id \
              FROM peer_connections \
              ORDER BY peer_id, created_at DESC";
             let result: QueryResult<Vec<PeerConnection>> =
@@ -239,9 +253,16 @@ impl DbExecutor {
         }
     }
 
-    fn get_all_logs(&self, filters: Filters) -> Result<Vec<Log>, Error> {
+    fn get_log_page(&self, filters: Filters) -> Result<LogsPage, Error> {
+        let limit = filters.limit.unwrap_or(RECORD_LIMIT);
+        // Paging to newer logs walks forwards from the cursor, then the page is reversed
+        let (before, after) = (filters.before, filters.after);
+        let (cursor, comparison, order) = match (before, after) {
+            (_, Some(after)) => (Some(after), ">", "ASC"),
+            (before, None) => (before, "<", "DESC"),
+        };
         match self.with_connection(|conn| {
-            let query = sql_query(
+            let query = sql_query(format!(
                 "SELECT sl.id, \
                  ip_addr, \
                  peer_id, \
@@ -254,10 +275,18 @@ impl DbExecutor {
                  WHERE peer_id = $1 \
                  AND sl.created_at > $2 \
                  AND sl.created_at < $3 \
-                 ORDER BY created_at DESC \
-                 LIMIT $4",
-            )
-            .bind::<Text, _>(filters.peer_id.unwrap_or(String::new()))
+                 AND ($4::TEXT IS NULL OR logs->>'msg' = $4) \
+                 AND ($5::TEXT IS NULL OR logs->>'target' = $5) \
+                 AND ($6::TIMESTAMP IS NULL OR (sl.created_at, sl.id) {} ($6, $7)) \
+                 AND {} \
+                 ORDER BY sl.created_at {}, sl.id {} \
+                 LIMIT $9",
+                comparison,
+                field_predicates_sql(8),
+                order,
+                order
+            ))
+            .bind::<Text, _>(filters.peer_id.unwrap_or_default())
             .bind::<Timestamp, _>(
                 filters
                     .start_time
@@ -268,109 +297,76 @@ impl DbExecutor {
                     .end_time
                     .unwrap_or_else(|| NaiveDateTime::from_timestamp(Utc::now().timestamp(), 0)),
             )
-            .bind::<Integer, _>(filters.limit.unwrap_or(RECORD_LIMIT));
+            .bind::<Nullable<Text>, _>(filters.msg)
+            .bind::<Nullable<Text>, _>(filters.target)
+            .bind::<Nullable<Timestamp>, _>(cursor.map(|c| c.created_at))
+            .bind::<Integer, _>(cursor.map_or(0, |c| c.id))
+            .bind::<Jsonb, _>(predicates_json(&filters.predicates))
+            .bind::<Integer, _>(limit);
             debug!(
-                "get_all_logs query: {}",
+                "get_log_page query: {}",
                 diesel::debug_query::<diesel::pg::Pg, _>(&query)
             );
             let result: QueryResult<Vec<Log>> = query.get_results(conn);
             result
         }) {
-            Ok(Ok(v)) => Ok(v),
+            Ok(Ok(mut logs)) => {
+                let full = logs.len() as i32 >= limit;
+                let (next, prev) = if after.is_some() {
+                    logs.reverse();
+                    (
+                        logs.last().map(Log::cursor),
+                        logs.first().filter(|_| full).map(Log::cursor),
+                    )
+                } else {
+                    (
+                        logs.last().filter(|_| full).map(Log::cursor),
+                        logs.first().filter(|_| before.is_some()).map(Log::cursor),
+                    )
+                };
+                Ok(LogsPage { logs, next, prev })
+            }
             Ok(Err(e)) => Err(e.into()),
             Err(e) => Err(e.into()),
         }
     }
+}
 
-    fn get_log_msgs(&self, filters: Filters) -> Result<Vec<Log>, Error> {
-        match self.with_connection(|conn| {
-            let query = sql_query(
-                "SELECT sl.id, \
-                 ip_addr, \
-                 peer_id, \
-                 logs->>'msg' AS msg, \
-                 logs, \
-                 sl.created_at, \
-                 peer_connection_id \
-                 FROM substrate_logs sl \
-                 LEFT JOIN peer_connections pc ON sl.peer_connection_id = pc.id \
-                 WHERE peer_id = $1 \
-                 AND sl.created_at > $2 \
-                 AND sl.created_at < $3 \
-                 AND logs->>'msg' = $4
-                 ORDER BY created_at DESC \
-                 LIMIT $5",
-            )
-            .bind::<Text, _>(filters.peer_id.unwrap_or(String::new()))
-            .bind::<Timestamp, _>(
-                filters
-                    .start_time
-                    .unwrap_or_else(|| NaiveDateTime::from_timestamp(0, 0)),
-            )
-            .bind::<Timestamp, _>(
-                filters
-                    .end_time
-                    .unwrap_or_else(|| NaiveDateTime::from_timestamp(Utc::now().timestamp(), 0)),
-            )
-            .bind::<Text, _>(filters.msg.unwrap_or(String::new()))
-            .bind::<Integer, _>(filters.limit.unwrap_or(RECORD_LIMIT));
-            debug!(
-                "get_log_msgs query: {}",
-                diesel::debug_query::<diesel::pg::Pg, _>(&query)
-            );
-            let result: QueryResult<Vec<Log>> = query.get_results(conn);
-            result
-        }) {
-            Ok(Ok(v)) => Ok(v),
-            Ok(Err(e)) => Err(e.into()),
-            Err(e) => Err(e.into()),
-        }
-    }
+/// Condition holding for logs that match every predicate in the `jsonb` array bound as
+/// `$param`, see `predicates_json`. Passing them as one parameter keeps the number of binds
+/// fixed, as `sql_query` can't bind a variable number of them
+pub(super) fn field_predicates_sql(param: usize) -> String {
+    // Non-numeric fields don't match comparisons rather than failing the cast
+    format!(
+        "NOT EXISTS (SELECT 1 FROM jsonb_array_elements(${}) AS p(pred) \
+         CROSS JOIN LATERAL (SELECT logs #> ARRAY(SELECT jsonb_array_elements_text(pred->'path')) \
+         AS field) f \
+         WHERE NOT COALESCE(CASE pred->>'op' \
+         WHEN '?' THEN field IS NOT NULL \
+         WHEN '=' THEN field = (pred->'value') \
+         WHEN '@>' THEN field @> (pred->'value') \
+         ELSE CASE WHEN jsonb_typeof(field) = 'number' THEN CASE pred->>'op' \
+         WHEN '>' THEN (field #>> '{{}}')::FLOAT8 > (pred->>'value')::FLOAT8 \
+         WHEN '>=' THEN (field #>> '{{}}')::FLOAT8 >= (pred->>'value')::FLOAT8 \
+         WHEN '<' THEN (field #>> '{{}}')::FLOAT8 < (pred->>'value')::FLOAT8 \
+         WHEN '<=' THEN (field #>> '{{}}')::FLOAT8 <= (pred->>'value')::FLOAT8 \
+         END END END, FALSE))",
+        param
+    )
+}
 
-    fn get_log_msgs_with_target(&self, filters: Filters) -> Result<Vec<Log>, Error> {
-        match self.with_connection(|conn| {
-            let query = sql_query(
-                "SELECT sl.id, \
-                 ip_addr, \
-                 peer_id, \
-                 logs->>'msg' AS msg, \
-                 logs, \
-                 sl.created_at, \
-                 peer_connection_id \
-                 FROM substrate_logs sl \
-                 LEFT JOIN peer_connections pc ON sl.peer_connection_id = pc.id \
-                 WHERE peer_id = $1 \
-                 AND sl.created_at > $2 \
-                 AND sl.created_at < $3 \
-                 AND logs->>'msg' = $4
-                 AND logs->>'target' = $5
-                 ORDER BY created_at DESC \
-                 LIMIT $6",
-            )
-            .bind::<Text, _>(filters.peer_id.unwrap_or(String::new()))
-            .bind::<Timestamp, _>(
-                filters
-                    .start_time
-                    .unwrap_or_else(|| NaiveDateTime::from_timestamp(0, 0)),
-            )
-            .bind::<Timestamp, _>(
-                filters
-                    .end_time
-                    .unwrap_or_else(|| NaiveDateTime::from_timestamp(Utc::now().timestamp(), 0)),
-            )
-            .bind::<Text, _>(filters.msg.unwrap_or(String::new()))
-            .bind::<Text, _>(filters.target.unwrap_or(String::new()))
-            .bind::<Integer, _>(filters.limit.unwrap_or(RECORD_LIMIT));
-            debug!(
-                "Query: get_log_msgs_with_target: {}",
-                diesel::debug_query::<diesel::pg::Pg, _>(&query)
-            );
-            let result: QueryResult<Vec<Log>> = query.get_results(conn);
-            result
-        }) {
-            Ok(Ok(v)) => Ok(v),
-            Ok(Err(e)) => Err(e.into()),
-            Err(e) => Err(e.into()),
-        }
-    }
+/// Encode predicates for binding to `field_predicates_sql`
+pub(super) fn predicates_json(predicates: &[FieldPredicate]) -> Value {
+    predicates
+        .iter()
+        .map(|predicate| {
+            let (op, value) = match &predicate.op {
+                PredicateOp::Exists => ("?", Value::Null),
+                PredicateOp::Eq(v) => ("=", v.clone()),
+                PredicateOp::Contains(v) => ("@>", v.clone()),
+                PredicateOp::Compare(comparison, n) => (comparison.as_sql(), json!(n)),
+            };
+            json!({ "path": predicate.path, "op": op, "value": value })
+        })
+        .collect()
 }
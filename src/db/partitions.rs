@@ -0,0 +1,326 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate Analytics.
+
+// Substrate Analytics is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate Analytics is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
+
+use actix::prelude::*;
+use chrono::{Duration, NaiveDateTime, Timelike, Utc};
+use diesel::pg::PgConnection;
+use diesel::sql_types::*;
+use diesel::{result::QueryResult, sql_query, Connection, RunQueryDsl};
+use std::str::FromStr;
+
+use super::pins::{get_pins, unpinned};
+use super::DbExecutor;
+
+/// Parent of the time range partitions, holds all logs from non-audit connections
+const EXPIRING_TABLE: &str = "substrate_logs_expiring";
+/// Catches logs outside of the created time ranges, eg. with timestamps far in the future
+const DEFAULT_PARTITION: &str = "substrate_logs_expiring_default";
+const PARTITION_PREFIX: &str = "substrate_logs_";
+const PARTITION_TIME_FORMAT: &str = "%Y%m%d%H";
+
+/// Width of the time range covered by each partition of `substrate_logs_expiring`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PartitionInterval {
+    Hourly,
+    Daily,
+}
+
+impl PartitionInterval {
+    pub fn duration(self) -> Duration {
+        match self {
+            PartitionInterval::Hourly => Duration::hours(1),
+            PartitionInterval::Daily => Duration::days(1),
+        }
+    }
+
+    /// Start of the interval containing `ts`
+    pub fn truncate(self, ts: NaiveDateTime) -> NaiveDateTime {
+        match self {
+            PartitionInterval::Hourly => ts.date().and_hms(ts.hour(), 0, 0),
+            PartitionInterval::Daily => ts.date().and_hms(0, 0, 0),
+        }
+    }
+}
+
+impl FromStr for PartitionInterval {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "hourly" => Ok(PartitionInterval::Hourly),
+            "daily" => Ok(PartitionInterval::Daily),
+            _ => Err("Partition interval must be either `hourly` or `daily`"),
+        }
+    }
+}
+
+/// Partition of `substrate_logs_expiring` covering `start` (inclusive) to `end` (exclusive)
+///
+/// The range is encoded in the table name so that existing partitions can be found
+/// without parsing the partition bounds from the catalog
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogPartition {
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+}
+
+impl LogPartition {
+    pub fn name(&self) -> String {
+        format!(
+            "{}{}_{}",
+            PARTITION_PREFIX,
+            self.start.format(PARTITION_TIME_FORMAT),
+            self.end.format(PARTITION_TIME_FORMAT)
+        )
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        let mut parts = name.strip_prefix(PARTITION_PREFIX)?.split('_');
+        let start = parse_partition_time(parts.next()?)?;
+        let end = parse_partition_time(parts.next()?)?;
+        if parts.next().is_some() || end <= start {
+            return None;
+        }
+        Some(LogPartition { start, end })
+    }
+
+    fn overlaps(&self, other: &LogPartition) -> bool {
+        self.start < other.end && other.start < self.end
+    }
+}
+
+fn parse_partition_time(s: &str) -> Option<NaiveDateTime> {
+    if s.len() != 10 {
+        return None;
+    }
+    NaiveDateTime::parse_from_str(&format!("{}00", s), "%Y%m%d%H%M").ok()
+}
+
+/// Partitions that should exist to hold logs from the current interval up to `ahead` intervals
+/// in the future
+pub fn upcoming_partitions(
+    now: NaiveDateTime,
+    interval: PartitionInterval,
+    ahead: u32,
+) -> Vec<LogPartition> {
+    let mut start = interval.truncate(now);
+    let mut partitions = Vec::new();
+    for _ in 0..=ahead {
+        let end = start + interval.duration();
+        partitions.push(LogPartition { start, end });
+        start = end;
+    }
+    partitions
+}
+
+/// Message to create partitions of `substrate_logs_expiring` ahead of time
+#[derive(Clone)]
+pub struct ManagePartitions {
+    pub interval: PartitionInterval,
+    /// Number of future intervals to create partitions for
+    pub ahead: u32,
+}
+
+impl Message for ManagePartitions {
+    type Result = Result<(), &'static str>;
+}
+
+impl Handler<ManagePartitions> for DbExecutor {
+    type Result = Result<(), &'static str>;
+
+    fn handle(&mut self, msg: ManagePartitions, _: &mut Self::Context) -> Self::Result {
+        let _ =
+            self.with_connection(
+                |conn| match create_partitions(conn, msg.interval, msg.ahead) {
+                    Err(e) => error!("Error creating log partitions: {:?}", e),
+                    Ok(n) => debug!("Created {} log partitions", n),
+                },
+            );
+        Ok(())
+    }
+}
+
+#[derive(QueryableByName)]
+struct PartitionName {
+    #[sql_type = "Text"]
+    name: String,
+}
+
+/// Existing partitions of `substrate_logs_expiring`, ignoring the default partition
+pub fn get_partitions(conn: &PgConnection) -> QueryResult<Vec<LogPartition>> {
+    let query = sql_query(
+        "SELECT c.relname::TEXT AS name \
+         FROM pg_inherits i \
+         INNER JOIN pg_class c ON c.oid = i.inhrelid \
+         INNER JOIN pg_class p ON p.oid = i.inhparent \
+         WHERE p.relname = $1",
+    )
+    .bind::<Text, _>(EXPIRING_TABLE);
+    let names: Vec<PartitionName> = query.get_results(conn)?;
+    Ok(names
+        .iter()
+        .filter_map(|n| LogPartition::from_name(&n.name))
+        .collect())
+}
+
+fn create_partitions(
+    conn: &PgConnection,
+    interval: PartitionInterval,
+    ahead: u32,
+) -> QueryResult<usize> {
+    let existing = get_partitions(conn)?;
+    let mut created = 0;
+    for partition in upcoming_partitions(Utc::now().naive_utc(), interval, ahead) {
+        // Skip anything already covered, eg. if the interval has been changed since the
+        // partition was created
+        if existing.iter().any(|p| p.overlaps(&partition)) {
+            continue;
+        }
+        match create_partition(conn, &partition) {
+            Ok(moved) => {
+                info!(
+                    "Created log partition: {}, moved {} logs from the default partition",
+                    partition.name(),
+                    moved
+                );
+                created += 1;
+            }
+            Err(e) => error!("Unable to create partition {}: {:?}", partition.name(), e),
+        }
+    }
+    Ok(created)
+}
+
+/// Create `partition` as a standalone table, move any logs in its range out of the default
+/// partition and attach it. Attaching (or `CREATE TABLE ... PARTITION OF`) fails while the
+/// default partition holds rows in the range, which nodes with skewed clocks can send.
+/// Returns the number of logs moved.
+fn create_partition(conn: &PgConnection, partition: &LogPartition) -> QueryResult<usize> {
+    let name = partition.name();
+    conn.transaction(|| {
+        // Nothing may be added to the range in the default partition until it's attached
+        sql_query(format!(
+            "LOCK TABLE {} IN EXCLUSIVE MODE",
+            DEFAULT_PARTITION
+        ))
+        .execute(conn)?;
+        sql_query(format!(
+            "CREATE TABLE {} (LIKE {} INCLUDING DEFAULTS)",
+            name, EXPIRING_TABLE
+        ))
+        .execute(conn)?;
+        let moved = sql_query(format!(
+            "WITH moved AS (\
+             DELETE FROM {} WHERE created_at >= $1 AND created_at < $2 RETURNING *) \
+             INSERT INTO {} SELECT * FROM moved",
+            DEFAULT_PARTITION, name
+        ))
+        .bind::<Timestamp, _>(partition.start)
+        .bind::<Timestamp, _>(partition.end)
+        .execute(conn)?;
+        sql_query(format!(
+            "ALTER TABLE {} ATTACH PARTITION {} FOR VALUES FROM ('{}') TO ('{}')",
+            EXPIRING_TABLE, name, partition.start, partition.end
+        ))
+        .execute(conn)?;
+        Ok(moved)
+    })
+}
+
+/// Detach and drop every partition whose whole range is older than `hours_valid`. Partitions
+/// with pinned logs are kept, only their logs that aren't pinned are deleted.
+pub fn drop_expired_partitions(
+    conn: &PgConnection,
+    hours_valid: u32,
+    max_pin_h: u32,
+) -> QueryResult<usize> {
+    let expiry = Utc::now().naive_utc() - Duration::hours(hours_valid.into());
+    let max_open = Duration::hours(max_pin_h.into());
+    let pins = get_pins(conn)?;
+    let mut dropped = 0;
+    for partition in get_partitions(conn)?
+        .into_iter()
+        .filter(|p| p.end <= expiry)
+    {
+        let name = partition.name();
+        if pins
+            .iter()
+            .any(|pin| pin.overlaps(partition.start, partition.end, max_open))
+        {
+            let deleted = sql_query(format!(
+                "DELETE FROM {} sl \
+                 USING peer_connections pc \
+                 WHERE pc.id = sl.peer_connection_id \
+                 AND {}",
+                name,
+                unpinned("sl", "pc", max_pin_h)
+            ))
+            .execute(conn)?;
+            info!(
+                "Kept expired log partition with pinned logs: {}, deleted {} unpinned logs",
+                name, deleted
+            );
+            continue;
+        }
+        sql_query(format!(
+            "ALTER TABLE {} DETACH PARTITION {}",
+            EXPIRING_TABLE, name
+        ))
+        .execute(conn)?;
+        sql_query(format!("DROP TABLE {}", name)).execute(conn)?;
+        info!("Dropped expired log partition: {}", name);
+        dropped += 1;
+    }
+    Ok(dropped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ts(s: &str) -> NaiveDateTime {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn partition_name_round_trip_test() {
+        let partition = LogPartition {
+            start: ts("2020-09-28T13:00:00"),
+            end: ts("2020-09-28T14:00:00"),
+        };
+        assert_eq!(partition.name(), "substrate_logs_2020092813_2020092814");
+        assert_eq!(LogPartition::from_name(&partition.name()), Some(partition));
+        assert_eq!(
+            LogPartition::from_name("substrate_logs_expiring_default"),
+            None
+        );
+        assert_eq!(LogPartition::from_name("substrate_logs_audit"), None);
+    }
+
+    #[test]
+    fn upcoming_partitions_test() {
+        let now = ts("2020-09-28T13:25:00");
+        let hourly = upcoming_partitions(now, PartitionInterval::Hourly, 2);
+        assert_eq!(hourly.len(), 3);
+        assert_eq!(hourly[0].start, ts("2020-09-28T13:00:00"));
+        assert_eq!(hourly[2].end, ts("2020-09-28T16:00:00"));
+        let daily = upcoming_partitions(now, PartitionInterval::Daily, 0);
+        assert_eq!(daily.len(), 1);
+        assert_eq!(daily[0].start, ts("2020-09-28T00:00:00"));
+        assert!(daily[0].overlaps(&hourly[1]));
+        assert!(!hourly[0].overlaps(&hourly[1]));
+    }
+}
@@ -0,0 +1,294 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate Analytics.
+
+// Substrate Analytics is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate Analytics is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
+
+use actix::prelude::*;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::*;
+use failure::Error;
+use std::collections::HashMap;
+
+use super::models::{Benchmark, BenchmarkEvent};
+use super::DbExecutor;
+
+/// `phase` of the event opening a phase of a benchmark
+pub const PHASE_START: &str = "start";
+/// `phase` of the event closing the latest open phase with the same `name`
+pub const PHASE_END: &str = "end";
+
+/// Time between a `start` and an `end` event with the same name, `end` is `None` while it runs
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Phase {
+    pub name: String,
+    pub start: NaiveDateTime,
+    pub end: Option<NaiveDateTime>,
+    pub duration_ms: Option<i64>,
+}
+
+/// Pair the `start` and `end` events of each name, in order of their creation, nested phases
+/// with the same name are closed innermost first. Also returns the events that aren't part
+/// of a phase: `end` events without a `start` and events with any other `phase`.
+pub fn pair_phases(events: &[BenchmarkEvent]) -> (Vec<Phase>, Vec<&BenchmarkEvent>) {
+    let mut sorted: Vec<&BenchmarkEvent> = events.iter().collect();
+    sorted.sort_by_key(|e| (e.created_at, e.id));
+    let mut phases: Vec<Phase> = Vec::new();
+    // Indices into `phases` of the phases still open, by name
+    let mut open: HashMap<&str, Vec<usize>> = HashMap::new();
+    let mut unpaired = Vec::new();
+    for event in sorted {
+        if event.phase == PHASE_START {
+            open.entry(event.name.as_str())
+                .or_default()
+                .push(phases.len());
+            phases.push(Phase {
+                name: event.name.clone(),
+                start: event.created_at,
+                end: None,
+                duration_ms: None,
+            });
+        } else if event.phase == PHASE_END {
+            match open.get_mut(event.name.as_str()).and_then(|o| o.pop()) {
+                Some(i) => {
+                    let phase = &mut phases[i];
+                    phase.end = Some(event.created_at);
+                    phase.duration_ms = Some((event.created_at - phase.start).num_milliseconds());
+                }
+                None => unpaired.push(event),
+            }
+        } else {
+            unpaired.push(event);
+        }
+    }
+    (phases, unpaired)
+}
+
+/// Telemetry of the benchmarked node during a phase
+#[derive(Serialize, Debug, Clone, QueryableByName)]
+pub struct PhaseTelemetry {
+    #[sql_type = "BigInt"]
+    pub blocks_imported: i64,
+    #[sql_type = "Nullable<BigInt>"]
+    pub min_height: Option<i64>,
+    #[sql_type = "Nullable<BigInt>"]
+    pub max_height: Option<i64>,
+    /// Blocks imported per second
+    #[sql_type = "Nullable<Double>"]
+    pub block_import_rate: Option<f64>,
+    /// Blocks between the best and the finalized block, from `system.interval` messages
+    #[sql_type = "Nullable<Double>"]
+    pub finality_lag_mean: Option<f64>,
+    #[sql_type = "Nullable<BigInt>"]
+    pub finality_lag_max: Option<i64>,
+    #[sql_type = "BigInt"]
+    pub txpool_imports: i64,
+    /// Transaction pool imports per second
+    #[sql_type = "Nullable<Double>"]
+    pub txpool_import_rate: Option<f64>,
+    #[sql_type = "Nullable<Double>"]
+    pub txpool_ready_mean: Option<f64>,
+    #[sql_type = "Nullable<BigInt>"]
+    pub txpool_ready_max: Option<i64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PhaseReport {
+    #[serde(flatten)]
+    pub phase: Phase,
+    /// `None` for phases that didn't end
+    pub telemetry: Option<PhaseTelemetry>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BenchmarkReport {
+    pub benchmark: Benchmark,
+    /// All the events of the benchmark, oldest first
+    pub timeline: Vec<BenchmarkEvent>,
+    pub phases: Vec<PhaseReport>,
+    /// Events that aren't the start or the end of a phase
+    pub unpaired: Vec<BenchmarkEvent>,
+}
+
+/// Report of a benchmark, `None` if it doesn't exist
+pub struct BenchmarkReportQuery(pub i32);
+
+impl Message for BenchmarkReportQuery {
+    type Result = Result<Option<BenchmarkReport>, Error>;
+}
+
+impl Handler<BenchmarkReportQuery> for DbExecutor {
+    type Result = Result<Option<BenchmarkReport>, Error>;
+
+    fn handle(&mut self, msg: BenchmarkReportQuery, _: &mut Self::Context) -> Self::Result {
+        self.get_benchmark_report(msg.0)
+    }
+}
+
+impl DbExecutor {
+    fn get_benchmark_report(&self, bm_id: i32) -> Result<Option<BenchmarkReport>, Error> {
+        match self.with_connection(|conn| {
+            conn.transaction::<_, diesel::result::Error, _>(|| {
+                let benchmark = {
+                    use crate::schema::benchmarks::dsl::*;
+                    match benchmarks
+                        .filter(id.eq(bm_id))
+                        .first::<Benchmark>(conn)
+                        .optional()?
+                    {
+                        Some(b) => b,
+                        None => return Ok(None),
+                    }
+                };
+                let timeline = {
+                    use crate::schema::benchmark_events::dsl::*;
+                    benchmark_events
+                        .filter(benchmark_id.eq(bm_id))
+                        .order((created_at.asc(), id.asc()))
+                        .load::<BenchmarkEvent>(conn)?
+                };
+                let (phases, unpaired) = pair_phases(&timeline);
+                let unpaired: Vec<BenchmarkEvent> = unpaired.into_iter().cloned().collect();
+                let ended: Vec<&Phase> = phases.iter().filter(|p| p.end.is_some()).collect();
+                let query = sql_query(
+                    "WITH pc AS ( \
+                     SELECT id FROM peer_connections WHERE peer_id = \
+                     (SELECT setup->'substrate'->>'peerId' FROM benchmarks WHERE id = $1) \
+                     ) \
+                     SELECT bi.blocks_imported, bi.min_height, bi.max_height, \
+                     NULL::FLOAT8 AS block_import_rate, \
+                     fl.finality_lag_mean, fl.finality_lag_max, \
+                     tx.txpool_imports, \
+                     NULL::FLOAT8 AS txpool_import_rate, \
+                     tx.txpool_ready_mean, tx.txpool_ready_max \
+                     FROM unnest($2, $3) WITH ORDINALITY AS p(start_time, end_time, idx) \
+                     CROSS JOIN LATERAL ( \
+                     SELECT count(*) AS blocks_imported, \
+                     min(height) AS min_height, max(height) AS max_height \
+                     FROM block_imports \
+                     WHERE peer_connection_id IN (SELECT id FROM pc) \
+                     AND created_at >= p.start_time AND created_at < p.end_time \
+                     ) bi \
+                     CROSS JOIN LATERAL ( \
+                     SELECT avg(height - finalized_height)::FLOAT8 AS finality_lag_mean, \
+                     max(height - finalized_height) AS finality_lag_max \
+                     FROM system_intervals \
+                     WHERE peer_connection_id IN (SELECT id FROM pc) \
+                     AND created_at >= p.start_time AND created_at < p.end_time \
+                     ) fl \
+                     CROSS JOIN LATERAL ( \
+                     SELECT count(*) AS txpool_imports, \
+                     avg(ready)::FLOAT8 AS txpool_ready_mean, max(ready) AS txpool_ready_max \
+                     FROM txpool_imports \
+                     WHERE peer_connection_id IN (SELECT id FROM pc) \
+                     AND created_at >= p.start_time AND created_at < p.end_time \
+                     ) tx \
+                     ORDER BY p.idx",
+                )
+                .bind::<Integer, _>(bm_id)
+                .bind::<Array<Timestamp>, _>(ended.iter().map(|p| p.start).collect::<Vec<_>>())
+                .bind::<Array<Timestamp>, _>(
+                    ended.iter().filter_map(|p| p.end).collect::<Vec<_>>(),
+                );
+                debug!(
+                    "get_benchmark_report query: {}",
+                    diesel::debug_query::<diesel::pg::Pg, _>(&query)
+                );
+                let telemetry: Vec<PhaseTelemetry> = query.get_results(conn)?;
+                Ok(Some((benchmark, timeline, phases, unpaired, telemetry)))
+            })
+        }) {
+            Ok(Ok(Some((benchmark, timeline, phases, unpaired, telemetry)))) => {
+                Ok(Some(BenchmarkReport {
+                    benchmark,
+                    timeline,
+                    phases: phase_reports(phases, telemetry),
+                    unpaired,
+                }))
+            }
+            Ok(Ok(None)) => Ok(None),
+            Ok(Err(e)) => Err(e.into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Attach the telemetry of the ended phases, in order, computing the rates from their duration
+fn phase_reports(phases: Vec<Phase>, telemetry: Vec<PhaseTelemetry>) -> Vec<PhaseReport> {
+    let mut telemetry = telemetry.into_iter();
+    phases
+        .into_iter()
+        .map(|phase| {
+            let telemetry = match phase.duration_ms {
+                Some(duration_ms) => telemetry.next().map(|mut t| {
+                    if duration_ms > 0 {
+                        let duration_s = duration_ms as f64 / 1000.0;
+                        t.block_import_rate = Some(t.blocks_imported as f64 / duration_s);
+                        t.txpool_import_rate = Some(t.txpool_imports as f64 / duration_s);
+                    }
+                    t
+                }),
+                None => None,
+            };
+            PhaseReport { phase, telemetry }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(id: i32, name: &str, phase: &str, at_s: i64) -> BenchmarkEvent {
+        BenchmarkEvent {
+            id,
+            benchmark_id: 1,
+            name: name.to_string(),
+            phase: phase.to_string(),
+            created_at: NaiveDateTime::from_timestamp(1_600_000_000 + at_s, 0),
+        }
+    }
+
+    #[test]
+    fn pair_phases_by_name_test() {
+        let events = vec![
+            event(1, "sync", "start", 0),
+            event(2, "import", "start", 10),
+            event(3, "import", "start", 20),
+            event(4, "import", "end", 30),
+            event(5, "import", "end", 50),
+            event(6, "sync", "checkpoint", 55),
+            event(7, "sync", "end", 60),
+            event(8, "txs", "end", 70),
+            event(9, "txs", "start", 80),
+        ];
+        let (phases, unpaired) = pair_phases(&events);
+        let durations: Vec<(&str, Option<i64>)> = phases
+            .iter()
+            .map(|p| (p.name.as_str(), p.duration_ms))
+            .collect();
+        assert_eq!(
+            durations,
+            vec![
+                ("sync", Some(60_000)),
+                ("import", Some(40_000)),
+                ("import", Some(10_000)),
+                ("txs", None),
+            ]
+        );
+        let unpaired: Vec<i32> = unpaired.iter().map(|e| e.id).collect();
+        assert_eq!(unpaired, vec![6, 8]);
+    }
+}
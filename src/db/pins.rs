@@ -0,0 +1,132 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate Analytics.
+
+// Substrate Analytics is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate Analytics is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
+
+use chrono::{Duration, NaiveDateTime};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+
+use super::models::{Benchmark, LogPin, NewLogPin};
+
+/// SQL condition, true if the row of `{table}` logged by the node of `{peer_connections}` isn't
+/// in the time range of any pin, so it may be purged. Pins that were never closed end
+/// `max_pin_h` after they start.
+pub(super) fn unpinned(table: &str, peer_connections: &str, max_pin_h: u32) -> String {
+    format!(
+        "NOT EXISTS (SELECT 1 FROM log_pins lp \
+         WHERE lp.peer_id = {pc}.peer_id \
+         AND {t}.created_at >= lp.start_time \
+         AND {t}.created_at <= COALESCE(lp.end_time, lp.start_time + {h} * interval '1 hour'))",
+        t = table,
+        pc = peer_connections,
+        h = max_pin_h
+    )
+}
+
+/// Pin the logs of the node a new benchmark runs on, if its setup has a `peerId`, from its
+/// creation until it stops
+pub(super) fn pin_benchmark_logs(
+    conn: &PgConnection,
+    benchmark: &Benchmark,
+) -> QueryResult<Option<LogPin>> {
+    let peer_id = match benchmark.setup["substrate"]["peerId"].as_str() {
+        Some(peer_id) => peer_id.to_string(),
+        None => return Ok(None),
+    };
+    use crate::schema::log_pins;
+    diesel::insert_into(log_pins::table)
+        .values(NewLogPin {
+            benchmark_id: benchmark.id,
+            peer_id,
+            start_time: benchmark.created_at,
+        })
+        .get_result::<LogPin>(conn)
+        .map(Some)
+}
+
+/// Set the end of the pins of a benchmark that stopped, extending them back to `start` if it is
+/// earlier, eg. when the first event was recorded before the benchmark
+pub(super) fn close_pins(
+    conn: &PgConnection,
+    bm_id: i32,
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+) -> QueryResult<usize> {
+    use crate::schema::log_pins::dsl::*;
+    let pins = log_pins
+        .filter(benchmark_id.eq(bm_id))
+        .load::<LogPin>(conn)?;
+    for pin in &pins {
+        diesel::update(log_pins.filter(id.eq(pin.id)))
+            .set((start_time.eq(pin.start_time.min(start)), end_time.eq(end)))
+            .execute(conn)?;
+    }
+    Ok(pins.len())
+}
+
+/// Remove the pins of a benchmark, its logs then expire as usual
+pub(super) fn unpin_benchmark_logs(conn: &PgConnection, bm_id: i32) -> QueryResult<usize> {
+    use crate::schema::log_pins::dsl::*;
+    diesel::delete(log_pins.filter(benchmark_id.eq(bm_id))).execute(conn)
+}
+
+/// All pins, of any node
+pub(super) fn get_pins(conn: &PgConnection) -> QueryResult<Vec<LogPin>> {
+    use crate::schema::log_pins::dsl::*;
+    log_pins.order(start_time.asc()).load::<LogPin>(conn)
+}
+
+impl LogPin {
+    /// End of the pinned range, `max_open` after the start if the pin was never closed, eg. when
+    /// its benchmark was left running
+    pub fn end(&self, max_open: Duration) -> NaiveDateTime {
+        self.end_time.unwrap_or(self.start_time + max_open)
+    }
+
+    /// Whether some of the logs between `start` (inclusive) and `end` (exclusive) are pinned
+    pub fn overlaps(&self, start: NaiveDateTime, end: NaiveDateTime, max_open: Duration) -> bool {
+        self.start_time < end && start <= self.end(max_open)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ts(s: &str) -> NaiveDateTime {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn pin_overlaps_range_test() {
+        let mut pin = LogPin {
+            id: 1,
+            benchmark_id: 1,
+            peer_id: "Qm".to_string(),
+            start_time: ts("2020-10-01T10:30:00"),
+            end_time: Some(ts("2020-10-01T11:00:00")),
+            created_at: ts("2020-10-01T10:30:00"),
+        };
+        let hour = |h: u32| ts(&format!("2020-10-01T{:02}:00:00", h));
+        let max_open = Duration::hours(2);
+        assert!(!pin.overlaps(hour(9), hour(10), max_open));
+        assert!(pin.overlaps(hour(10), hour(11), max_open));
+        assert!(pin.overlaps(hour(11), hour(12), max_open));
+        assert!(!pin.overlaps(hour(12), hour(13), max_open));
+        pin.end_time = None;
+        assert!(pin.overlaps(hour(12), hour(13), max_open));
+        assert!(!pin.overlaps(hour(13), hour(14), max_open));
+    }
+}
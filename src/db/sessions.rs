@@ -0,0 +1,313 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate Analytics.
+
+// Substrate Analytics is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate Analytics is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
+
+use actix::prelude::*;
+use chrono::{Duration, NaiveDateTime};
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::*;
+use failure::Error;
+use std::collections::BTreeMap;
+
+use super::DbExecutor;
+
+/// Record the end of the sessions of a WS connection, one per logical node
+pub struct RecordDisconnect {
+    pub peer_connection_ids: Vec<i32>,
+    pub disconnected_at: NaiveDateTime,
+    pub reason: String,
+}
+
+impl Message for RecordDisconnect {
+    type Result = Result<(), String>;
+}
+
+impl Handler<RecordDisconnect> for DbExecutor {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: RecordDisconnect, _: &mut Self::Context) -> Self::Result {
+        use crate::schema::peer_connections::dsl::*;
+        match self.with_connection(|conn| {
+            diesel::update(peer_connections.filter(id.eq_any(&msg.peer_connection_ids)))
+                .set((
+                    disconnected_at.eq(msg.disconnected_at),
+                    disconnect_reason.eq(&msg.reason),
+                ))
+                .execute(conn)
+        }) {
+            Ok(Ok(_)) => Ok(()),
+            Ok(Err(e)) => Err(format!("Error recording disconnect: {}", e)),
+            Err(e) => Err(format!("Error recording disconnect: {}", e)),
+        }
+    }
+}
+
+/// Close sessions left open by a previous run of `instance_id`, at the time of their last log
+/// message. Sessions created from `started_at` belong to this run and are left alone, as are
+/// those of other instances sharing the DB.
+pub struct CloseOpenSessions {
+    pub started_at: NaiveDateTime,
+    pub instance_id: String,
+}
+
+impl Message for CloseOpenSessions {
+    type Result = Result<usize, String>;
+}
+
+impl Handler<CloseOpenSessions> for DbExecutor {
+    type Result = Result<usize, String>;
+
+    fn handle(&mut self, msg: CloseOpenSessions, _: &mut Self::Context) -> Self::Result {
+        match self.with_connection(|conn| {
+            sql_query(
+                "UPDATE peer_connections pc \
+                 SET disconnect_reason = 'server restart', \
+                 disconnected_at = GREATEST(pc.created_at, \
+                 (SELECT max(sl.created_at) FROM substrate_logs sl WHERE sl.peer_connection_id = pc.id)) \
+                 WHERE pc.disconnected_at IS NULL \
+                 AND pc.created_at < $1 \
+                 AND (pc.instance_id = $2 OR pc.instance_id IS NULL)",
+            )
+            .bind::<Timestamp, _>(msg.started_at)
+            .bind::<Text, _>(msg.instance_id)
+            .execute(conn)
+        }) {
+            Ok(Ok(n)) => {
+                info!("Closed {} sessions left open by a previous run", n);
+                Ok(n)
+            }
+            Ok(Err(e)) => Err(format!("Error closing open sessions: {}", e)),
+            Err(e) => Err(format!("Error closing open sessions: {}", e)),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, QueryableByName)]
+pub struct Session {
+    #[sql_type = "Integer"]
+    pub id: i32,
+    #[sql_type = "Text"]
+    pub ip_addr: String,
+    #[sql_type = "Nullable<Text>"]
+    pub peer_addr: Option<String>,
+    #[sql_type = "Nullable<Text>"]
+    pub name: Option<String>,
+    #[sql_type = "Nullable<Text>"]
+    pub chain: Option<String>,
+    #[sql_type = "Nullable<Text>"]
+    pub version: Option<String>,
+    #[sql_type = "Timestamp"]
+    pub connected_at: NaiveDateTime,
+    #[sql_type = "Nullable<Timestamp>"]
+    pub disconnected_at: Option<NaiveDateTime>,
+    #[sql_type = "Nullable<Text>"]
+    pub disconnect_reason: Option<String>,
+}
+
+/// Sessions of a node overlapping a time window, with a summary of its availability
+#[derive(Serialize, Debug)]
+pub struct SessionHistory {
+    pub peer_id: String,
+    pub start_time: NaiveDateTime,
+    pub end_time: NaiveDateTime,
+    pub sessions: Vec<Session>,
+    /// Seconds during the window with at least one session connected
+    pub uptime_s: i64,
+    pub uptime_ratio: f64,
+    /// Sessions started during the window, other than the first ever session of the node
+    pub reconnects: usize,
+    /// Number of sessions ended during the window by each reason
+    pub disconnects: BTreeMap<String, usize>,
+    pub ips: Vec<String>,
+    pub versions: Vec<String>,
+}
+
+pub struct SessionHistoryQuery {
+    pub peer_id: String,
+    pub start_time: NaiveDateTime,
+    pub end_time: NaiveDateTime,
+    /// Open sessions are counted as connected until this time
+    pub now: NaiveDateTime,
+}
+
+impl Message for SessionHistoryQuery {
+    type Result = Result<SessionHistory, Error>;
+}
+
+impl Handler<SessionHistoryQuery> for DbExecutor {
+    type Result = Result<SessionHistory, Error>;
+
+    fn handle(&mut self, msg: SessionHistoryQuery, _: &mut Self::Context) -> Self::Result {
+        self.get_session_history(msg)
+    }
+}
+
+impl DbExecutor {
+    fn get_session_history(&self, msg: SessionHistoryQuery) -> Result<SessionHistory, Error> {
+        match self.with_connection(|conn| {
+            conn.transaction::<_, diesel::result::Error, _>(|| {
+                let query = sql_query(
+                    "SELECT id, ip_addr, peer_addr, name, chain, version, \
+                 created_at AS connected_at, disconnected_at, disconnect_reason \
+                 FROM peer_connections \
+                 WHERE peer_id = $1 \
+                 AND created_at < $3 \
+                 AND (disconnected_at IS NULL OR disconnected_at > $2) \
+                 ORDER BY created_at",
+                )
+                .bind::<Text, _>(msg.peer_id.clone())
+                .bind::<Timestamp, _>(msg.start_time)
+                .bind::<Timestamp, _>(msg.end_time);
+                debug!(
+                    "get_session_history query: {}",
+                    diesel::debug_query::<diesel::pg::Pg, _>(&query)
+                );
+                let sessions: Vec<Session> = query.get_results(conn)?;
+                let first_seen = {
+                    use crate::schema::peer_connections::dsl::*;
+                    peer_connections
+                        .filter(peer_id.eq(&msg.peer_id))
+                        .select(diesel::dsl::min(created_at))
+                        .first::<Option<NaiveDateTime>>(conn)?
+                };
+                Ok((sessions, first_seen))
+            })
+        }) {
+            Ok(Ok((sessions, first_seen))) => Ok(summarise(msg, sessions, first_seen)),
+            Ok(Err(e)) => Err(e.into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+fn summarise(
+    msg: SessionHistoryQuery,
+    sessions: Vec<Session>,
+    first_seen: Option<NaiveDateTime>,
+) -> SessionHistory {
+    let (start, end) = (msg.start_time, msg.end_time.min(msg.now));
+    // Sessions may overlap, eg. a node connected to several telemetry URLs, so merge them
+    let mut intervals: Vec<(NaiveDateTime, NaiveDateTime)> = sessions
+        .iter()
+        .map(|s| {
+            (
+                s.connected_at.max(start),
+                s.disconnected_at.unwrap_or(msg.now).min(end),
+            )
+        })
+        .filter(|(from, to)| from < to)
+        .collect();
+    intervals.sort();
+    let mut uptime = Duration::zero();
+    let mut current: Option<(NaiveDateTime, NaiveDateTime)> = None;
+    for (from, to) in intervals {
+        current = match current {
+            Some((cur_from, cur_to)) if from <= cur_to => Some((cur_from, cur_to.max(to))),
+            Some((cur_from, cur_to)) => {
+                uptime = uptime + (cur_to - cur_from);
+                Some((from, to))
+            }
+            None => Some((from, to)),
+        };
+    }
+    if let Some((cur_from, cur_to)) = current {
+        uptime = uptime + (cur_to - cur_from);
+    }
+    let window = (end - start).num_seconds();
+    let in_window = |t: NaiveDateTime| t >= msg.start_time && t < msg.end_time;
+    let reconnects = sessions
+        .iter()
+        .filter(|s| in_window(s.connected_at) && Some(s.connected_at) != first_seen)
+        .count();
+    let mut disconnects = BTreeMap::new();
+    for s in sessions
+        .iter()
+        .filter(|s| s.disconnected_at.is_some_and(in_window))
+    {
+        let reason = s.disconnect_reason.clone().unwrap_or_default();
+        *disconnects.entry(reason).or_insert(0) += 1;
+    }
+    let mut ips: Vec<String> = sessions.iter().map(|s| s.ip_addr.clone()).collect();
+    ips.sort();
+    ips.dedup();
+    let mut versions: Vec<String> = sessions.iter().filter_map(|s| s.version.clone()).collect();
+    versions.sort();
+    versions.dedup();
+    SessionHistory {
+        peer_id: msg.peer_id,
+        start_time: msg.start_time,
+        end_time: msg.end_time,
+        uptime_s: uptime.num_seconds(),
+        uptime_ratio: if window > 0 {
+            uptime.num_seconds() as f64 / window as f64
+        } else {
+            0.0
+        },
+        reconnects,
+        disconnects,
+        ips,
+        versions,
+        sessions,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(
+        t0: NaiveDateTime,
+        from: i64,
+        to: Option<i64>,
+        version: &str,
+        reason: &str,
+    ) -> Session {
+        Session {
+            id: from as i32,
+            ip_addr: "1.2.3.4".to_string(),
+            peer_addr: None,
+            name: None,
+            chain: None,
+            version: Some(version.to_string()),
+            connected_at: t0 + Duration::seconds(from),
+            disconnected_at: to.map(|to| t0 + Duration::seconds(to)),
+            disconnect_reason: to.map(|_| reason.to_string()),
+        }
+    }
+
+    #[test]
+    fn summarise_merges_overlapping_sessions_test() {
+        let t0 = NaiveDateTime::from_timestamp(1_600_000_000, 0);
+        let sessions = vec![
+            session(t0, 0, Some(30), "0.8.25", "heartbeat timeout"),
+            session(t0, 20, Some(50), "0.8.25", "heartbeat timeout"),
+            session(t0, 70, None, "0.8.26", ""),
+        ];
+        let msg = SessionHistoryQuery {
+            peer_id: "Qm".to_string(),
+            start_time: t0,
+            end_time: t0 + Duration::seconds(100),
+            now: t0 + Duration::seconds(90),
+        };
+        let history = summarise(msg, sessions, Some(t0));
+        assert_eq!(history.uptime_s, 70);
+        assert!((history.uptime_ratio - 70.0 / 90.0).abs() < 1e-9);
+        assert_eq!(history.reconnects, 2);
+        assert_eq!(history.disconnects.get("heartbeat timeout"), Some(&2));
+        assert_eq!(history.versions, vec!["0.8.25", "0.8.26"]);
+        assert_eq!(history.ips, vec!["1.2.3.4"]);
+    }
+}
@@ -0,0 +1,251 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate Analytics.
+
+// Substrate Analytics is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate Analytics is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
+
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::result::QueryResult;
+use serde_json::Value;
+
+use super::models::{
+    NewAfgFinalized, NewBlockImport, NewNotifyFinalized, NewSubstrateLog, NewSystemInterval,
+    NewTxpoolImport,
+};
+
+/// Names of the typed tables, all of which have `peer_connection_id` and `created_at` columns
+pub const TYPED_TABLES: [&str; 5] = [
+    "block_imports",
+    "afg_finalized",
+    "notify_finalized",
+    "system_intervals",
+    "txpool_imports",
+];
+
+/// Typed rows extracted from a batch of `NewSubstrateLog`s
+///
+/// Every message is stored as JSONB in `substrate_logs`, the messages recognised here are
+/// additionally written to a dedicated table with numeric columns so that they can be
+/// queried without scanning and casting JSONB
+#[derive(Default, Debug)]
+pub struct TypedLogs {
+    pub block_imports: Vec<NewBlockImport>,
+    pub afg_finalized: Vec<NewAfgFinalized>,
+    pub notify_finalized: Vec<NewNotifyFinalized>,
+    pub system_intervals: Vec<NewSystemInterval>,
+    pub txpool_imports: Vec<NewTxpoolImport>,
+}
+
+impl TypedLogs {
+    pub fn from_logs(logs: &[NewSubstrateLog]) -> Self {
+        let mut typed_logs = TypedLogs::default();
+        for log in logs {
+            typed_logs.push(log);
+        }
+        typed_logs
+    }
+
+    /// Add the typed representation of `log`, if it is one of the recognised messages
+    /// and has the required fields
+    pub fn push(&mut self, log: &NewSubstrateLog) {
+        let peer_connection_id = log.peer_connection_id;
+        let created_at = log.created_at;
+        let l = &log.logs;
+        match l["msg"].as_str() {
+            Some("block.import") => {
+                if let (Some(best), Some(height)) = (as_string(&l["best"]), as_i64(&l["height"])) {
+                    self.block_imports.push(NewBlockImport {
+                        peer_connection_id,
+                        created_at,
+                        best,
+                        height,
+                        origin: as_string(&l["origin"]),
+                    });
+                }
+            }
+            Some("afg.finalized") => {
+                if let (Some(finalized_hash), Some(finalized_number)) = (
+                    as_string(&l["finalized_hash"]),
+                    as_i64(&l["finalized_number"]),
+                ) {
+                    self.afg_finalized.push(NewAfgFinalized {
+                        peer_connection_id,
+                        created_at,
+                        finalized_hash,
+                        finalized_number,
+                    });
+                }
+            }
+            Some("notify.finalized") => {
+                if let (Some(best), Some(height)) = (as_string(&l["best"]), as_i64(&l["height"])) {
+                    self.notify_finalized.push(NewNotifyFinalized {
+                        peer_connection_id,
+                        created_at,
+                        best,
+                        height,
+                    });
+                }
+            }
+            Some("system.interval") => {
+                self.system_intervals.push(NewSystemInterval {
+                    peer_connection_id,
+                    created_at,
+                    peers: as_i64(&l["peers"]),
+                    best: as_string(&l["best"]),
+                    height: as_i64(&l["height"]),
+                    finalized_hash: as_string(&l["finalized_hash"]),
+                    finalized_height: as_i64(&l["finalized_height"]),
+                    txcount: as_i64(&l["txcount"]),
+                    bandwidth_download: as_i64(&l["bandwidth_download"]),
+                    bandwidth_upload: as_i64(&l["bandwidth_upload"]),
+                    cpu: as_f64(&l["cpu"]),
+                    memory: as_i64(&l["memory"]),
+                    disk_read_per_sec: as_i64(&l["disk_read_per_sec"]),
+                    disk_write_per_sec: as_i64(&l["disk_write_per_sec"]),
+                    used_state_cache_size: as_i64(&l["used_state_cache_size"]),
+                    used_db_cache_size: as_i64(&l["used_db_cache_size"]),
+                });
+            }
+            Some("txpool.import") => {
+                if let (Some(ready), Some(future)) = (as_i64(&l["ready"]), as_i64(&l["future"])) {
+                    self.txpool_imports.push(NewTxpoolImport {
+                        peer_connection_id,
+                        created_at,
+                        ready,
+                        future,
+                    });
+                }
+            }
+            _ => (),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.block_imports.len()
+            + self.afg_finalized.len()
+            + self.notify_finalized.len()
+            + self.system_intervals.len()
+            + self.txpool_imports.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Insert all rows in a single transaction, returning the number of rows inserted
+    pub fn save(&self, conn: &PgConnection) -> QueryResult<usize> {
+        use crate::schema::{
+            afg_finalized, block_imports, notify_finalized, system_intervals, txpool_imports,
+        };
+        conn.transaction(|| {
+            let mut n = 0;
+            if !self.block_imports.is_empty() {
+                n += diesel::insert_into(block_imports::table)
+                    .values(&self.block_imports)
+                    .execute(conn)?;
+            }
+            if !self.afg_finalized.is_empty() {
+                n += diesel::insert_into(afg_finalized::table)
+                    .values(&self.afg_finalized)
+                    .execute(conn)?;
+            }
+            if !self.notify_finalized.is_empty() {
+                n += diesel::insert_into(notify_finalized::table)
+                    .values(&self.notify_finalized)
+                    .execute(conn)?;
+            }
+            if !self.system_intervals.is_empty() {
+                n += diesel::insert_into(system_intervals::table)
+                    .values(&self.system_intervals)
+                    .execute(conn)?;
+            }
+            if !self.txpool_imports.is_empty() {
+                n += diesel::insert_into(txpool_imports::table)
+                    .values(&self.txpool_imports)
+                    .execute(conn)?;
+            }
+            Ok(n)
+        })
+    }
+}
+
+// Substrate reports some numbers as JSON strings and others as JSON numbers
+fn as_i64(v: &Value) -> Option<i64> {
+    v.as_i64()
+        .or_else(|| v.as_str().and_then(|s| s.parse().ok()))
+}
+
+fn as_f64(v: &Value) -> Option<f64> {
+    v.as_f64()
+        .or_else(|| v.as_str().and_then(|s| s.parse().ok()))
+}
+
+fn as_string(v: &Value) -> Option<String> {
+    v.as_str().map(|s| s.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDateTime;
+
+    fn new_log(logs: Value) -> NewSubstrateLog {
+        NewSubstrateLog {
+            logs,
+            peer_connection_id: 1,
+            created_at: NaiveDateTime::from_timestamp(0, 0),
+            audit: false,
+        }
+    }
+
+    #[test]
+    fn extracts_known_messages_test() {
+        let logs = vec![
+            new_log(json!({
+                "best": "0x91e8",
+                "height": 1853456,
+                "msg": "block.import",
+                "origin": "NetworkBroadcast",
+            })),
+            new_log(json!({
+                "finalized_hash": "0x32d4",
+                "finalized_number": "1853236",
+                "msg": "afg.finalized",
+            })),
+            new_log(json!({"best": "0xe197", "height": "1853457", "msg": "notify.finalized"})),
+            new_log(json!({"peers": 39, "cpu": 11.29, "msg": "system.interval"})),
+            new_log(json!({"future": 0, "ready": 1, "msg": "txpool.import"})),
+            new_log(json!({"msg": "afg.received_prevote"})),
+        ];
+        let typed_logs = TypedLogs::from_logs(&logs);
+        assert_eq!(typed_logs.len(), 5);
+        assert_eq!(typed_logs.block_imports[0].height, 1_853_456);
+        assert_eq!(typed_logs.afg_finalized[0].finalized_number, 1_853_236);
+        assert_eq!(typed_logs.notify_finalized[0].height, 1_853_457);
+        assert_eq!(typed_logs.system_intervals[0].peers, Some(39));
+        assert_eq!(typed_logs.system_intervals[0].height, None);
+        assert_eq!(typed_logs.txpool_imports[0].ready, 1);
+    }
+
+    #[test]
+    fn skips_messages_missing_required_fields_test() {
+        let logs = vec![
+            new_log(json!({"height": 10, "msg": "block.import"})),
+            new_log(
+                json!({"finalized_number": "not a number", "finalized_hash": "0x00", "msg": "afg.finalized"}),
+            ),
+        ];
+        assert!(TypedLogs::from_logs(&logs).is_empty());
+    }
+}
@@ -0,0 +1,236 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate Analytics.
+
+// Substrate Analytics is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate Analytics is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
+
+use actix::prelude::*;
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use failure::Error;
+use rand::Rng;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+
+use super::models::{IngestToken, NewIngestToken};
+use super::DbExecutor;
+
+/// What a token allows a node to do, `Audit` tokens can also be used for normal ingest
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenScope {
+    Normal,
+    Audit,
+}
+
+impl TokenScope {
+    pub fn allows(self, audit: bool) -> bool {
+        !audit || self == TokenScope::Audit
+    }
+}
+
+impl fmt::Display for TokenScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenScope::Normal => write!(f, "normal"),
+            TokenScope::Audit => write!(f, "audit"),
+        }
+    }
+}
+
+impl FromStr for TokenScope {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "normal" => Ok(TokenScope::Normal),
+            "audit" => Ok(TokenScope::Audit),
+            _ => Err("`scope` must be either `normal` or `audit`"),
+        }
+    }
+}
+
+impl IngestToken {
+    pub fn scope(&self) -> TokenScope {
+        self.scope.parse().unwrap_or(TokenScope::Normal)
+    }
+}
+
+/// Only the hash of a token is stored, the token itself is returned once when it is issued
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+fn generate_token() -> String {
+    let bytes: [u8; 32] = rand::thread_rng().gen();
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Request to issue a new token
+#[derive(Deserialize, Debug)]
+pub struct IssueToken {
+    pub description: Option<String>,
+    pub scope: TokenScope,
+    /// Restrict the token to nodes reporting this chain
+    pub chain: Option<String>,
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+impl Message for IssueToken {
+    type Result = Result<Value, Error>;
+}
+
+impl Handler<IssueToken> for DbExecutor {
+    type Result = Result<Value, Error>;
+
+    fn handle(&mut self, msg: IssueToken, _: &mut Self::Context) -> Self::Result {
+        self.issue_token(msg)
+    }
+}
+
+pub struct RevokeToken(pub i32);
+
+impl Message for RevokeToken {
+    type Result = Result<Value, Error>;
+}
+
+impl Handler<RevokeToken> for DbExecutor {
+    type Result = Result<Value, Error>;
+
+    fn handle(&mut self, msg: RevokeToken, _: &mut Self::Context) -> Self::Result {
+        self.revoke_token(msg.0)
+    }
+}
+
+pub struct TokensQuery;
+
+impl Message for TokensQuery {
+    type Result = Result<Value, Error>;
+}
+
+impl Handler<TokensQuery> for DbExecutor {
+    type Result = Result<Value, Error>;
+
+    fn handle(&mut self, _msg: TokensQuery, _: &mut Self::Context) -> Self::Result {
+        self.get_tokens()
+    }
+}
+
+/// Look up a token presented by a node, returns `None` if it is unknown, revoked or expired
+pub struct ValidateToken(pub String);
+
+impl Message for ValidateToken {
+    type Result = Result<Option<IngestToken>, Error>;
+}
+
+impl Handler<ValidateToken> for DbExecutor {
+    type Result = Result<Option<IngestToken>, Error>;
+
+    fn handle(&mut self, msg: ValidateToken, _: &mut Self::Context) -> Self::Result {
+        self.validate_token(&msg.0)
+    }
+}
+
+impl DbExecutor {
+    fn issue_token(&self, msg: IssueToken) -> Result<Value, Error> {
+        let token = generate_token();
+        let new_token = NewIngestToken {
+            token_hash: hash_token(&token),
+            description: msg.description,
+            scope: msg.scope.to_string(),
+            chain: msg.chain,
+            expires_at: msg.expires_at,
+        };
+        match self.with_connection(|conn| {
+            use crate::schema::ingest_tokens;
+            diesel::insert_into(ingest_tokens::table)
+                .values(new_token)
+                .get_result::<IngestToken>(conn)
+        }) {
+            Ok(Ok(v)) => {
+                let mut result = json!(v);
+                result["token"] = json!(token);
+                Ok(result)
+            }
+            Ok(Err(e)) => Err(e.into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn revoke_token(&self, token_id: i32) -> Result<Value, Error> {
+        match self.with_connection(|conn| {
+            use crate::schema::ingest_tokens::dsl::*;
+            diesel::update(ingest_tokens.filter(id.eq(token_id)))
+                .set(revoked_at.eq(Utc::now().naive_utc()))
+                .get_result::<IngestToken>(conn)
+                .optional()
+        }) {
+            Ok(Ok(v)) => Ok(json!(v)),
+            Ok(Err(e)) => Err(e.into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn get_tokens(&self) -> Result<Value, Error> {
+        match self.with_connection(|conn| {
+            use crate::schema::ingest_tokens::dsl::*;
+            ingest_tokens
+                .order(created_at.desc())
+                .load::<IngestToken>(conn)
+        }) {
+            Ok(Ok(v)) => Ok(json!(v)),
+            Ok(Err(e)) => Err(e.into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn validate_token(&self, token: &str) -> Result<Option<IngestToken>, Error> {
+        let now = Utc::now().naive_utc();
+        match self.with_connection(|conn| {
+            use crate::schema::ingest_tokens::dsl::*;
+            ingest_tokens
+                .filter(token_hash.eq(hash_token(token)))
+                .filter(revoked_at.is_null())
+                .filter(expires_at.is_null().or(expires_at.gt(now)))
+                .first::<IngestToken>(conn)
+                .optional()
+        }) {
+            Ok(Ok(v)) => Ok(v),
+            Ok(Err(e)) => Err(e.into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scope_allows_audit_only_for_audit_tokens_test() {
+        assert!(TokenScope::Normal.allows(false));
+        assert!(!TokenScope::Normal.allows(true));
+        assert!(TokenScope::Audit.allows(true));
+        assert!(TokenScope::Audit.allows(false));
+    }
+
+    #[test]
+    fn generated_tokens_hash_consistently_test() {
+        let token = generate_token();
+        assert_eq!(token.len(), 64);
+        assert_eq!(hash_token(&token), hash_token(&token));
+        assert_ne!(hash_token(&token), hash_token(&generate_token()));
+    }
+}
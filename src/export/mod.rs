@@ -0,0 +1,405 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate Analytics.
+
+// Substrate Analytics is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate Analytics is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::db::export::{ExportPage, ExportRow};
+use crate::db::{filters::Filters, DbExecutor};
+use actix::prelude::*;
+use chrono::{NaiveDateTime, Utc};
+use parquet::basic::{ConvertedType, Repetition, Type as PhysicalType};
+use parquet::column::writer::ColumnWriter;
+use parquet::data_type::ByteArray;
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::{FileWriter, SerializedFileWriter};
+use parquet::schema::types::Type;
+use serde_json::Value;
+use std::fs::File;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Rows fetched from the DB per page, and written per Parquet row group
+pub const EXPORT_PAGE_SIZE: i32 = 10_000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    NdJson,
+    Csv,
+    Parquet,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ndjson" => Ok(ExportFormat::NdJson),
+            "csv" => Ok(ExportFormat::Csv),
+            "parquet" => Ok(ExportFormat::Parquet),
+            _ => Err(format!("Unknown export format: {}", s)),
+        }
+    }
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::NdJson => "application/x-ndjson",
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Parquet => "application/vnd.apache.parquet",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::NdJson => "ndjson",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Parquet => "parquet",
+        }
+    }
+}
+
+/// Walks through the logs matching `filters` a page at a time
+pub struct ExportCursor {
+    filters: Filters,
+    after: Option<(NaiveDateTime, i32)>,
+    /// Rows left to export if `filters.limit` is set
+    remaining: Option<i64>,
+    done: bool,
+}
+
+impl ExportCursor {
+    pub fn new(mut filters: Filters) -> Self {
+        // Fixed once so that logs saved during the export don't move the end of later pages
+        filters
+            .end_time
+            .get_or_insert_with(|| Utc::now().naive_utc());
+        ExportCursor {
+            remaining: filters.limit.map(i64::from),
+            filters,
+            after: None,
+            done: false,
+        }
+    }
+
+    /// The next page of rows, or `None` once every row has been returned
+    pub async fn next(&mut self, db: &Addr<DbExecutor>) -> Result<Option<Vec<ExportRow>>, String> {
+        let limit = match self.remaining {
+            Some(remaining) => remaining.min(i64::from(EXPORT_PAGE_SIZE)) as i32,
+            None => EXPORT_PAGE_SIZE,
+        };
+        if self.done || limit <= 0 {
+            return Ok(None);
+        }
+        let rows = db
+            .send(ExportPage {
+                filters: self.filters.clone(),
+                after: self.after,
+                limit,
+            })
+            .await
+            .map_err(|e| e.to_string())?
+            .map_err(|e| e.to_string())?;
+        self.done = rows.len() < limit as usize;
+        if let Some(last) = rows.last() {
+            self.after = Some((last.created_at, last.id));
+        }
+        if let Some(remaining) = &mut self.remaining {
+            *remaining -= rows.len() as i64;
+        }
+        Ok(Some(rows))
+    }
+}
+
+const COLUMNS: [&str; 6] = [
+    "id",
+    "created_at",
+    "peer_connection_id",
+    "peer_id",
+    "ip_addr",
+    "msg",
+];
+
+/// Fields of the log message to flatten into their own columns, as dotted paths eg. `state.peerId`
+#[derive(Clone, Debug, Default)]
+pub struct Fields(Vec<String>);
+
+impl FromStr for Fields {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Fields(
+            s.split(',')
+                .map(str::trim)
+                .filter(|f| !f.is_empty())
+                .map(String::from)
+                .collect(),
+        ))
+    }
+}
+
+impl Fields {
+    /// Value of each field in `logs` as a string, `None` if absent
+    fn values<'a>(&'a self, logs: &'a Value) -> impl Iterator<Item = Option<String>> + 'a {
+        self.0.iter().map(move |path| {
+            let value = path.split('.').try_fold(logs, |v, key| v.get(key))?;
+            match value {
+                Value::Null => None,
+                Value::String(s) => Some(s.clone()),
+                v => Some(v.to_string()),
+            }
+        })
+    }
+
+    fn column_names(&self) -> impl Iterator<Item = String> + '_ {
+        self.0.iter().map(|path| path.replace('.', "_"))
+    }
+}
+
+pub fn ndjson_chunk(rows: &[ExportRow]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for row in rows {
+        if serde_json::to_writer(&mut out, row).is_ok() {
+            out.push(b'\n');
+        }
+    }
+    out
+}
+
+pub fn csv_header(fields: &Fields) -> Vec<u8> {
+    let columns: Vec<String> = COLUMNS
+        .iter()
+        .map(|c| c.to_string())
+        .chain(fields.column_names())
+        .map(|c| csv_escape(&c))
+        .collect();
+    format!("{}\n", columns.join(",")).into_bytes()
+}
+
+/// The log message itself is not included in CSV, only the selected `fields`
+pub fn csv_chunk(rows: &[ExportRow], fields: &Fields) -> Vec<u8> {
+    let mut out = String::new();
+    for row in rows {
+        let columns: Vec<String> = vec![
+            Some(row.id.to_string()),
+            Some(row.created_at.to_string()),
+            row.peer_connection_id.map(|id| id.to_string()),
+            row.peer_id.clone(),
+            row.ip_addr.clone(),
+            row.msg.clone(),
+        ]
+        .into_iter()
+        .chain(fields.values(&row.logs))
+        .map(|v| v.map(|v| csv_escape(&v)).unwrap_or_default())
+        .collect();
+        out.push_str(&columns.join(","));
+        out.push('\n');
+    }
+    out.into_bytes()
+}
+
+fn csv_escape(s: &str) -> String {
+    if s.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// Writes pages of rows to a Parquet file, one row group per page
+pub struct ParquetExport {
+    writer: SerializedFileWriter<File>,
+    fields: Fields,
+}
+
+impl ParquetExport {
+    pub fn new(file: File, fields: Fields) -> Result<Self, ParquetError> {
+        let mut columns = vec![
+            column("id", PhysicalType::INT32, None, Repetition::REQUIRED)?,
+            column(
+                "created_at",
+                PhysicalType::INT64,
+                Some(ConvertedType::TIMESTAMP_MICROS),
+                Repetition::REQUIRED,
+            )?,
+            column(
+                "peer_connection_id",
+                PhysicalType::INT32,
+                None,
+                Repetition::OPTIONAL,
+            )?,
+        ];
+        for name in COLUMNS[3..]
+            .iter()
+            .map(|c| c.to_string())
+            .chain(fields.column_names())
+        {
+            columns.push(utf8_column(&name)?);
+        }
+        columns.push(column(
+            "logs",
+            PhysicalType::BYTE_ARRAY,
+            Some(ConvertedType::JSON),
+            Repetition::REQUIRED,
+        )?);
+        let schema = Type::group_type_builder("substrate_logs")
+            .with_fields(&mut columns)
+            .build()?;
+        let props = WriterProperties::builder().build();
+        Ok(ParquetExport {
+            writer: SerializedFileWriter::new(file, Arc::new(schema), Arc::new(props))?,
+            fields,
+        })
+    }
+
+    pub fn write(&mut self, rows: &[ExportRow]) -> Result<(), ParquetError> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let field_values: Vec<Vec<Option<String>>> = rows
+            .iter()
+            .map(|r| self.fields.values(&r.logs).collect())
+            .collect();
+        let mut string_columns: Vec<Vec<Option<String>>> = vec![
+            rows.iter().map(|r| r.peer_id.clone()).collect(),
+            rows.iter().map(|r| r.ip_addr.clone()).collect(),
+            rows.iter().map(|r| r.msg.clone()).collect(),
+        ];
+        for i in 0..self.fields.0.len() {
+            string_columns.push(field_values.iter().map(|v| v[i].clone()).collect());
+        }
+        string_columns.push(rows.iter().map(|r| Some(r.logs.to_string())).collect());
+        let mut string_columns = string_columns.into_iter();
+
+        let mut row_group = self.writer.next_row_group()?;
+        let mut index = 0;
+        while let Some(mut column_writer) = row_group.next_column()? {
+            match (index, &mut column_writer) {
+                (0, ColumnWriter::Int32ColumnWriter(w)) => {
+                    let ids: Vec<i32> = rows.iter().map(|r| r.id).collect();
+                    w.write_batch(&ids, None, None)?;
+                }
+                (1, ColumnWriter::Int64ColumnWriter(w)) => {
+                    let micros: Vec<i64> = rows
+                        .iter()
+                        .map(|r| {
+                            r.created_at.timestamp() * 1_000_000
+                                + i64::from(r.created_at.timestamp_subsec_micros())
+                        })
+                        .collect();
+                    w.write_batch(&micros, None, None)?;
+                }
+                (2, ColumnWriter::Int32ColumnWriter(w)) => {
+                    let (values, levels) = optional(rows.iter().map(|r| r.peer_connection_id));
+                    w.write_batch(&values, Some(&levels), None)?;
+                }
+                (_, ColumnWriter::ByteArrayColumnWriter(w)) => {
+                    let column = string_columns.next().unwrap_or_default();
+                    let (values, levels) = optional(
+                        column
+                            .into_iter()
+                            .map(|v| v.map(|s| ByteArray::from(s.into_bytes()))),
+                    );
+                    w.write_batch(&values, Some(&levels), None)?;
+                }
+                _ => return Err(ParquetError::General("Unexpected column type".to_string())),
+            }
+            row_group.close_column(column_writer)?;
+            index += 1;
+        }
+        self.writer.close_row_group(row_group)
+    }
+
+    pub fn close(mut self) -> Result<(), ParquetError> {
+        self.writer.close().map(|_| ())
+    }
+}
+
+fn column(
+    name: &str,
+    physical_type: PhysicalType,
+    converted_type: Option<ConvertedType>,
+    repetition: Repetition,
+) -> Result<Arc<Type>, ParquetError> {
+    let mut builder = Type::primitive_type_builder(name, physical_type).with_repetition(repetition);
+    if let Some(converted_type) = converted_type {
+        builder = builder.with_converted_type(converted_type);
+    }
+    Ok(Arc::new(builder.build()?))
+}
+
+fn utf8_column(name: &str) -> Result<Arc<Type>, ParquetError> {
+    column(
+        name,
+        PhysicalType::BYTE_ARRAY,
+        Some(ConvertedType::UTF8),
+        Repetition::OPTIONAL,
+    )
+}
+
+/// Non-null values and the definition level of each row, for writing optional columns
+fn optional<T>(values: impl Iterator<Item = Option<T>>) -> (Vec<T>, Vec<i16>) {
+    let mut present = Vec::new();
+    let mut levels = Vec::new();
+    for value in values {
+        match value {
+            Some(v) => {
+                present.push(v);
+                levels.push(1);
+            }
+            None => levels.push(0),
+        }
+    }
+    (present, levels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDateTime;
+
+    fn row() -> ExportRow {
+        ExportRow {
+            id: 1,
+            created_at: NaiveDateTime::from_timestamp(1_600_000_000, 0),
+            peer_connection_id: Some(2),
+            peer_id: Some("Qm1".to_string()),
+            ip_addr: None,
+            msg: Some("system.interval".to_string()),
+            logs: json!({"msg": "system.interval", "peers": 3, "net": {"name": "a,b"}}),
+        }
+    }
+
+    #[test]
+    fn csv_flattens_fields_test() {
+        let fields: Fields = "peers, net.name, missing".parse().unwrap();
+        assert_eq!(
+            String::from_utf8(csv_header(&fields)).unwrap(),
+            "id,created_at,peer_connection_id,peer_id,ip_addr,msg,peers,net_name,missing\n"
+        );
+        assert_eq!(
+            String::from_utf8(csv_chunk(&[row()], &fields)).unwrap(),
+            "1,2020-09-13 12:26:40,2,Qm1,,system.interval,3,\"a,b\",\n"
+        );
+    }
+
+    #[test]
+    fn ndjson_one_row_per_line_test() {
+        let out = String::from_utf8(ndjson_chunk(&[row(), row()])).unwrap();
+        assert_eq!(out.lines().count(), 2);
+        let first: Value = serde_json::from_str(out.lines().next().unwrap()).unwrap();
+        assert_eq!(first["logs"]["peers"], 3);
+    }
+}
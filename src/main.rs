@@ -27,22 +27,33 @@ extern crate serde_derive;
 extern crate serde_json;
 
 pub mod cache;
+mod cli;
 pub mod db;
+pub mod export;
 pub mod schema;
+pub mod spool;
 pub mod util;
 mod web;
 
 use cache::Cache;
+use spool::Spool;
+use web::client_ip::TrustedProxies;
+use web::metrics::Metrics;
+use web::rate_limit::{IpRateLimits, Limits, OverLimitAction};
+use web::shutdown::{Connections, CountConnections, DisconnectAll, ShutdownFlag};
 
 use dotenv::dotenv;
+use std::collections::BTreeMap;
 use std::env;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use crate::db::models::NewSubstrateLog;
+use crate::db::partitions::{ManagePartitions, PartitionInterval};
+use crate::db::sessions::CloseOpenSessions;
 //use crate::db::peer_data::UpdateCache;
 use crate::db::*;
 use actix::prelude::*;
-use actix_web::{middleware, App, HttpServer};
+use actix_web::{dev::Server, middleware, App, HttpServer};
 
 lazy_static! {
     /// *Must be set* Database URL
@@ -65,17 +76,37 @@ lazy_static! {
     /// Number of hours to keep logs in DB before deleting them
     /// Does not affect logs received on the `/archive` route
     pub static ref LOG_EXPIRY_H: u32 = parse_env("LOG_EXPIRY_H").unwrap_or(3);
+    /// Number of hours to keep closed sessions, after their logs have expired
+    pub static ref SESSION_RETENTION_H: u32 = parse_env("SESSION_RETENTION_H").unwrap_or(24);
+    /// Number of hours a benchmark that never stops keeps the logs of its node from expiring
+    pub static ref MAX_PIN_H: u32 = parse_env("MAX_PIN_H").unwrap_or(168);
+    /// Identifies this server in `peer_connections`, must be unique and stable across restarts
+    /// when several servers share the DB
+    pub static ref INSTANCE_ID: String = parse_env("INSTANCE_ID").unwrap_or("default".to_string());
+    /// Time range covered by each partition of non-audit logs, either `hourly` or `daily`
+    pub static ref LOG_PARTITION_INTERVAL: PartitionInterval =
+        parse_env("LOG_PARTITION_INTERVAL").unwrap_or(PartitionInterval::Hourly);
+    /// Number of future log partitions to create ahead of time
+    pub static ref LOG_PARTITIONS_AHEAD: u32 = parse_env("LOG_PARTITIONS_AHEAD").unwrap_or(2);
     /// Max number of pending connections to hold in backlog before returnin server error
     pub static ref MAX_PENDING_CONNECTIONS: i32 = parse_env("MAX_PENDING_CONNECTIONS").unwrap_or(1024);
     /// Max payload size for WS message
     /// Default to accept payload size of 512 MiB because default 65KiB is not enough
     pub static ref WS_MAX_PAYLOAD: usize = parse_env("WS_MAX_PAYLOAD").unwrap_or(524_288);
+    /// Max number of logical nodes sending telemetry envelopes over a single WS connection
+    pub static ref WS_MAX_NODES_PER_CONNECTION: usize = parse_env("WS_MAX_NODES_PER_CONNECTION").unwrap_or(64);
+    /// Max size of a request body sent to the `/ingest` route
+    pub static ref HTTP_INGEST_MAX_BYTES: usize = parse_env("HTTP_INGEST_MAX_BYTES").unwrap_or(16_777_216);
     /// Number of threads to start for DbExecutor
     pub static ref NUM_THREADS: usize = parse_env("NUM_THREADS").unwrap_or(num_cpus::get() * 3);
     /// Connections to establish for DB pool
     pub static ref DB_POOL_SIZE: u32 = parse_env("DB_POOL_SIZE").unwrap_or(*NUM_THREADS as u32);
+    /// Connections to establish for the pool used to `COPY` log batches, separate from `DB_POOL_SIZE`
+    pub static ref DB_COPY_POOL_SIZE: u32 = parse_env("DB_COPY_POOL_SIZE").unwrap_or(4);
     /// Max batch size before saving logs to DB
     pub static ref DB_BATCH_SIZE: usize = parse_env("DB_BATCH_SIZE").unwrap_or(1024);
+    /// Number of times to retry a failed `COPY` of a log batch before isolating bad rows
+    pub static ref DB_COPY_RETRIES: u32 = parse_env("DB_COPY_RETRIES").unwrap_or(2);
     /// Max amount of time to wait before saving logs to DB
     pub static ref DB_SAVE_LATENCY_MS: Duration = Duration::from_millis(parse_env("DB_SAVE_LATENCY_MS").unwrap_or(100));
     /// How long to wait for a response from the DB - warnings logged at this time and update aborted at 4* this value
@@ -86,6 +117,35 @@ lazy_static! {
     pub static ref CACHE_EXPIRY_S: u64 = parse_env("CACHE_EXPIRY_S").unwrap_or(10_800);
     /// How long to keep an unused cache in memory until we drop it
     pub static ref CACHE_TIMEOUT_S: u64 = parse_env("CACHE_TIMEOUT_S").unwrap_or(60);
+    /// Directory to spool logs to while the DB is unavailable
+    pub static ref SPOOL_PATH: String = parse_env("SPOOL_PATH").unwrap_or("./spool".to_string());
+    /// Max size of the spool, oldest logs are dropped beyond this
+    pub static ref SPOOL_MAX_MB: u64 = parse_env("SPOOL_MAX_MB").unwrap_or(1024);
+    /// Max age of spooled logs, older logs are dropped
+    pub static ref SPOOL_MAX_AGE_H: u64 = parse_env("SPOOL_MAX_AGE_H").unwrap_or(24);
+    /// Max time to wait for buffered logs to be saved when shutting down
+    pub static ref SHUTDOWN_TIMEOUT_S: Duration = Duration::from_secs(parse_env("SHUTDOWN_TIMEOUT_S").unwrap_or(30));
+    /// Comma separated CIDRs of reverse proxies whose forwarding headers are used to find a node's IP
+    pub static ref TRUSTED_PROXIES: TrustedProxies = parse_env("TRUSTED_PROXIES").unwrap_or_default();
+    /// Require nodes to present an ingest token when connecting
+    pub static ref INGEST_AUTH: bool = parse_env("INGEST_AUTH").unwrap_or(false);
+    /// Token required to manage ingest tokens, the admin routes are disabled if unset
+    pub static ref ADMIN_TOKEN: Option<String> = parse_env("ADMIN_TOKEN").ok();
+    /// Max WS messages per second from a single node connection, 0 for unlimited
+    pub static ref WS_CONN_MSGS_PER_S: u64 = parse_env("WS_CONN_MSGS_PER_S").unwrap_or(0);
+    /// Max WS bytes per second from a single node connection, 0 for unlimited
+    pub static ref WS_CONN_BYTES_PER_S: u64 = parse_env("WS_CONN_BYTES_PER_S").unwrap_or(0);
+    /// Max WS messages per second from all node connections from the same IP, 0 for unlimited
+    pub static ref WS_IP_MSGS_PER_S: u64 = parse_env("WS_IP_MSGS_PER_S").unwrap_or(0);
+    /// Max WS bytes per second from all node connections from the same IP, 0 for unlimited
+    pub static ref WS_IP_BYTES_PER_S: u64 = parse_env("WS_IP_BYTES_PER_S").unwrap_or(0);
+    /// How many seconds worth of messages a node may send in a burst before being limited
+    pub static ref RATE_LIMIT_BURST_S: Duration = Duration::from_secs(parse_env("RATE_LIMIT_BURST_S").unwrap_or(5));
+    /// What to do with over-limit frames, `drop` or `sample:N` to keep one in every N
+    pub static ref RATE_LIMIT_ACTION: OverLimitAction = parse_env("RATE_LIMIT_ACTION").unwrap_or(OverLimitAction::Drop);
+    /// Number of over-limit frames within `RATE_LIMIT_OFFENCE_WINDOW_S` before a node is disconnected, 0 to never disconnect
+    pub static ref RATE_LIMIT_MAX_OFFENCES: u64 = parse_env("RATE_LIMIT_MAX_OFFENCES").unwrap_or(1000);
+    pub static ref RATE_LIMIT_OFFENCE_WINDOW_S: Duration = Duration::from_secs(parse_env("RATE_LIMIT_OFFENCE_WINDOW_S").unwrap_or(60));
     /// Location of `static` directory
     pub static ref ASSETS_PATH: String = parse_env("ASSETS_PATH").unwrap_or("./static".to_string());
 }
@@ -93,6 +153,121 @@ lazy_static! {
 struct LogBuffer {
     logs: Vec<NewSubstrateLog>,
     db_arbiter: Recipient<LogBatch>,
+    metrics: Metrics,
+    spool: Option<Spool>,
+    /// Copies of the batches sent to the DB arbiter that have not been answered yet, so they can
+    /// be spooled if shutdown times out before they're saved
+    in_flight: BTreeMap<u64, Vec<NewSubstrateLog>>,
+    next_batch: u64,
+    /// Sequence number of the spool segment currently being replayed
+    replaying: Option<u64>,
+    /// Set when the DB fails to save a batch, cleared once a batch or replay is saved
+    db_unavailable: bool,
+}
+
+impl LogBuffer {
+    fn new(db_arbiter: Recipient<LogBatch>, spool: Option<Spool>, metrics: Metrics) -> Self {
+        LogBuffer {
+            logs: Vec::new(),
+            db_arbiter,
+            metrics,
+            spool,
+            in_flight: BTreeMap::new(),
+            next_batch: 0,
+            replaying: None,
+            db_unavailable: false,
+        }
+    }
+
+    // Beyond this the DB is not keeping up, so we spool rather than buffer in memory
+    fn max_in_flight() -> usize {
+        *NUM_THREADS * 2
+    }
+
+    fn send_batch(&mut self, lb: LogBatch, ctx: &mut Context<Self>) {
+        let id = self.next_batch;
+        self.next_batch += 1;
+        self.in_flight.insert(id, lb.0.clone());
+        let fut = self
+            .db_arbiter
+            .send(lb)
+            .into_actor(self)
+            .map(move |res, act, _ctx| {
+                // Already spooled by `SpoolInFlight`
+                if act.in_flight.remove(&id).is_none() {
+                    return;
+                }
+                match res {
+                    Ok(Ok(())) => act.db_unavailable = false,
+                    Ok(Err(lb)) => {
+                        if act.spool.is_some() {
+                            act.db_unavailable = true;
+                        }
+                        act.spool_batch(lb);
+                    }
+                    Err(e) => error!("Failed to send LogBatch to DB arbiter - {:?}", e),
+                }
+            });
+        ctx.spawn(fut);
+    }
+
+    fn spool_batch(&mut self, lb: LogBatch) {
+        let n = lb.0.len();
+        match self.spool.as_mut() {
+            Some(spool) => match spool.append(&lb.0) {
+                Ok(()) => self.metrics.inc_spool_rows_written(n as u64),
+                Err(e) => error!("Failed to spool {} logs - {:?}", n, e),
+            },
+            None => error!("Dropping {} logs - unable to save to DB", n),
+        }
+    }
+
+    /// Send the oldest spooled segment to the DB arbiter, one segment at a time
+    fn replay_spool(&mut self, ctx: &mut Context<Self>) {
+        if self.replaying.is_some() || self.in_flight.len() >= Self::max_in_flight() {
+            return;
+        }
+        let spool = match self.spool.as_mut() {
+            Some(spool) => spool,
+            None => return,
+        };
+        self.metrics
+            .inc_spool_segments_dropped(spool.enforce_limits());
+        let (seq, logs) = match spool.oldest() {
+            Ok(Some(segment)) => segment,
+            Ok(None) => return,
+            Err(e) => {
+                error!("Unable to read spooled logs - {:?}", e);
+                return;
+            }
+        };
+        if logs.is_empty() {
+            spool.remove(seq);
+            return;
+        }
+        let n = logs.len();
+        self.replaying = Some(seq);
+        let fut =
+            self.db_arbiter
+                .send(LogBatch(logs))
+                .into_actor(self)
+                .map(move |res, act, _ctx| {
+                    act.replaying = None;
+                    match res {
+                        Ok(Ok(())) => {
+                            info!("Replayed {} spooled logs", n);
+                            act.db_unavailable = false;
+                            act.metrics.inc_spool_rows_replayed(n as u64);
+                            if let Some(spool) = act.spool.as_mut() {
+                                spool.remove(seq);
+                            }
+                        }
+                        Ok(Err(_)) => debug!("DB still unavailable, keeping spooled logs"),
+                        Err(e) => error!("Failed to send spooled LogBatch to DB arbiter - {:?}", e),
+                    }
+                });
+        ctx.spawn(fut);
+    }
 }
 
 impl Actor for LogBuffer {
@@ -116,6 +291,22 @@ impl Handler<NewSubstrateLog> for LogBuffer {
     }
 }
 
+/// All the logs from an ingest batch, sent as one message so a large batch isn't limited by the mailbox capacity
+struct NewSubstrateLogs(Vec<NewSubstrateLog>);
+
+impl Message for NewSubstrateLogs {
+    type Result = Result<(), &'static str>;
+}
+
+impl Handler<NewSubstrateLogs> for LogBuffer {
+    type Result = Result<(), &'static str>;
+
+    fn handle(&mut self, msg: NewSubstrateLogs, _: &mut Self::Context) -> Self::Result {
+        self.logs.extend(msg.0);
+        Ok(())
+    }
+}
+
 #[derive(Clone)]
 struct SaveLogs;
 
@@ -126,38 +317,135 @@ impl Message for SaveLogs {
 impl Handler<SaveLogs> for LogBuffer {
     type Result = Result<(), &'static str>;
 
-    fn handle(&mut self, _msg: SaveLogs, _: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, _msg: SaveLogs, ctx: &mut Self::Context) -> Self::Result {
         while !self.logs.is_empty() {
             let lb = LogBatch(
                 self.logs
                     .split_off(self.logs.len().saturating_sub(*DB_BATCH_SIZE)),
             );
-            self.db_arbiter
-                .try_send(lb)
-                .unwrap_or_else(|e| error!("Failed to send LogBatch to DB arbiter - {:?}", e));
+            // While the DB is unavailable batches go to the spool behind the ones already there,
+            // if there are none (eg. the spool couldn't be written) the batch is sent to find out
+            // whether the DB is back
+            let spooled = self.spool.as_ref().is_some_and(|s| s.bytes() > 0);
+            if (self.db_unavailable && spooled) || self.in_flight.len() >= Self::max_in_flight() {
+                self.spool_batch(lb);
+            } else {
+                self.send_batch(lb, ctx);
+            }
+        }
+        self.replay_spool(ctx);
+        if let Some(spool) = &self.spool {
+            self.metrics
+                .set_spool_depth(spool.segments(), spool.bytes());
         }
         Ok(())
     }
 }
 
+/// Number of batches sent to the DB arbiter that have not been saved yet
+struct PendingBatches;
+
+impl Message for PendingBatches {
+    type Result = usize;
+}
+
+impl Handler<PendingBatches> for LogBuffer {
+    type Result = usize;
+
+    fn handle(&mut self, _msg: PendingBatches, _: &mut Self::Context) -> Self::Result {
+        self.in_flight.len()
+    }
+}
+
+/// Spool the batches that haven't been saved yet, returning how many logs were spooled
+///
+/// If the DB does save a batch afterwards, its logs will be saved again when the spool
+/// is replayed, which is preferred to losing them
+struct SpoolInFlight;
+
+impl Message for SpoolInFlight {
+    type Result = usize;
+}
+
+impl Handler<SpoolInFlight> for LogBuffer {
+    type Result = usize;
+
+    fn handle(&mut self, _msg: SpoolInFlight, _: &mut Self::Context) -> Self::Result {
+        let batches = std::mem::take(&mut self.in_flight);
+        let mut n = 0;
+        for (_, logs) in batches {
+            n += logs.len();
+            self.spool_batch(LogBatch(logs));
+        }
+        n
+    }
+}
+
 #[actix_rt::main]
 async fn main() -> std::io::Result<()> {
     dotenv().ok();
     env_logger::init();
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Some(result) = cli::run(&args).await {
+        return result;
+    }
+    // Ignoring a malformed list would take the proxies' IPs to be those of the nodes
+    if let Ok(Err(e)) = env::var("TRUSTED_PROXIES").map(|v| v.parse::<TrustedProxies>()) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Invalid TRUSTED_PROXIES: {}", e),
+        ));
+    }
     log_statics();
     info!("Starting substrate-analytics");
     info!("Creating database pool");
     let pool = create_pool();
+    let copy_pool = db::bulk::create_copy_pool()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let metrics = Metrics::default();
     info!("Starting DbArbiter with {} threads", *NUM_THREADS);
 
-    let db_arbiter = SyncArbiter::start(*NUM_THREADS, move || db::DbExecutor::new(pool.clone()));
+    let db_metrics = metrics.clone();
+    let db_arbiter = SyncArbiter::start(*NUM_THREADS, move || {
+        db::DbExecutor::new(pool.clone(), copy_pool.clone(), db_metrics.clone())
+    });
     info!("DbExecutor started");
 
     let cache = Cache::new(db_arbiter.clone()).start();
 
-    let log_buffer = LogBuffer {
-        logs: Vec::new(),
-        db_arbiter: db_arbiter.clone().recipient(),
+    let spool = match Spool::open(
+        &*SPOOL_PATH,
+        *SPOOL_MAX_MB * 1024 * 1024,
+        Duration::from_secs(*SPOOL_MAX_AGE_H * 3600),
+    ) {
+        Ok(spool) => Some(spool),
+        Err(e) => {
+            error!(
+                "Unable to open spool at {}, spooling disabled - {:?}",
+                *SPOOL_PATH, e
+            );
+            None
+        }
+    };
+
+    let log_buffer = LogBuffer::new(db_arbiter.clone().recipient(), spool, metrics.clone()).start();
+
+    let manage_partitions = ManagePartitions {
+        interval: *LOG_PARTITION_INTERVAL,
+        ahead: *LOG_PARTITIONS_AHEAD,
+    };
+    // Make sure partitions for the current interval exist before any logs arrive
+    db_arbiter.do_send(manage_partitions.clone());
+    // Sessions left open by a previous run can no longer be closed by their socket
+    db_arbiter.do_send(CloseOpenSessions {
+        started_at: chrono::Utc::now().naive_utc(),
+        instance_id: INSTANCE_ID.to_string(),
+    });
+
+    util::PeriodicAction {
+        interval: *PURGE_INTERVAL_S,
+        message: manage_partitions,
+        recipient: db_arbiter.clone().recipient(),
     }
     .start();
 
@@ -165,6 +453,8 @@ async fn main() -> std::io::Result<()> {
         interval: *PURGE_INTERVAL_S,
         message: PurgeLogs {
             hours_valid: *LOG_EXPIRY_H,
+            session_retention_h: *SESSION_RETENTION_H,
+            max_pin_h: *MAX_PIN_H,
         },
         recipient: db_arbiter.clone().recipient(),
     }
@@ -177,31 +467,134 @@ async fn main() -> std::io::Result<()> {
     }
     .start();
 
-    let metrics = web::metrics::Metrics::default();
+    let connections = Connections::default().start();
+    let shutdown_flag = ShutdownFlag::default();
+    let ip_rate_limits = IpRateLimits::new(Limits {
+        msgs_per_s: *WS_IP_MSGS_PER_S,
+        bytes_per_s: *WS_IP_BYTES_PER_S,
+        burst: *RATE_LIMIT_BURST_S,
+    });
+
     let address = format!("0.0.0.0:{}", &*PORT);
     info!("Starting server on: {}", &address);
-    HttpServer::new(move || {
+    let app_log_buffer = log_buffer.clone();
+    let app_connections = connections.clone();
+    let app_shutdown_flag = shutdown_flag.clone();
+    let server = HttpServer::new(move || {
         App::new()
             .data(db_arbiter.clone())
             .data(metrics.clone())
-            .data(log_buffer.clone())
+            .data(app_log_buffer.clone())
             .data(cache.clone())
-            .data(actix_web::web::JsonConfig::default().limit(4096))
+            .data(app_connections.clone())
+            .data(app_shutdown_flag.clone())
+            .data(ip_rate_limits.clone())
+            .data(web::error::json_config().limit(4096))
             .wrap(middleware::NormalizePath)
             .wrap(middleware::Logger::default())
-            .configure(web::nodes::configure)
-            .configure(web::reputation::configure)
-            .configure(web::stats::configure)
-            .configure(web::metrics::configure)
-            .configure(web::benchmarks::configure)
+            .configure(web::configure_api)
             .configure(web::dashboard::configure)
             .configure(web::feed::configure)
+            .configure(web::openapi::configure)
             .configure(web::root::configure)
     })
     .backlog(*MAX_PENDING_CONNECTIONS)
+    .shutdown_timeout(SHUTDOWN_TIMEOUT_S.as_secs())
+    .disable_signals()
     .bind(&address)?
-    .run()
-    .await
+    .run();
+
+    actix_rt::spawn(shutdown_on_signal(
+        server.clone(),
+        shutdown_flag,
+        connections,
+        log_buffer,
+    ));
+
+    server.await
+}
+
+async fn shutdown_on_signal(
+    server: Server,
+    shutdown_flag: ShutdownFlag,
+    connections: Addr<Connections>,
+    log_buffer: Addr<LogBuffer>,
+) {
+    use actix_rt::signal::unix::{signal, SignalKind};
+    use futures::future::{select, FutureExt};
+
+    let mut sigterm = signal(SignalKind::terminate()).expect("Unable to listen for SIGTERM");
+    select(
+        Box::pin(sigterm.recv()),
+        Box::pin(actix_rt::signal::ctrl_c().map(|_| ())),
+    )
+    .await;
+    info!("Shutting down");
+    let deadline = Instant::now() + *SHUTDOWN_TIMEOUT_S;
+
+    // Stop accepting connections and ingest requests, and ask the WS connections to close
+    server.pause().await;
+    shutdown_flag.set();
+    match connections
+        .send(DisconnectAll {
+            reason: "Server shutting down".to_string(),
+        })
+        .await
+    {
+        Ok(n) => info!("Sent close to {} WS connections", n),
+        Err(e) => error!("Unable to close WS connections - {:?}", e),
+    }
+
+    // Wait for the WS actors to stop and ingest requests to finish, so no more logs arrive
+    loop {
+        let open = match connections.send(CountConnections).await {
+            Ok(n) => n,
+            Err(e) => {
+                error!("Unable to query WS connections - {:?}", e);
+                0
+            }
+        };
+        let requests = shutdown_flag.active_requests();
+        if open == 0 && requests == 0 {
+            break;
+        }
+        if Instant::now() >= deadline {
+            warn!(
+                "Shutdown timeout reached with {} WS connections and {} ingest requests open",
+                open, requests
+            );
+            break;
+        }
+        actix_rt::time::delay_for(Duration::from_millis(50)).await;
+    }
+
+    // Save whatever is still buffered and wait for the DB to finish with it
+    if let Err(e) = log_buffer.send(SaveLogs).await {
+        error!("Unable to flush LogBuffer - {:?}", e);
+    }
+    loop {
+        match log_buffer.send(PendingBatches).await {
+            Ok(0) => {
+                info!("All buffered logs saved");
+                break;
+            }
+            Ok(n) if Instant::now() >= deadline => {
+                warn!("Shutdown timeout reached with {} log batches unsaved", n);
+                match log_buffer.send(SpoolInFlight).await {
+                    Ok(n) => info!("Spooled {} unsaved logs", n),
+                    Err(e) => error!("Unable to spool unsaved logs - {:?}", e),
+                }
+                break;
+            }
+            Ok(_) => actix_rt::time::delay_for(Duration::from_millis(50)).await,
+            Err(e) => {
+                error!("Unable to query LogBuffer - {:?}", e);
+                break;
+            }
+        }
+    }
+
+    server.stop(true).await;
 }
 
 // Private
@@ -214,15 +607,45 @@ fn log_statics() {
     info!("CLIENT_TIMEOUT_S = {:?}", *CLIENT_TIMEOUT_S);
     info!("MAX_PENDING_CONNECTIONS = {:?}", *MAX_PENDING_CONNECTIONS);
     info!("WS_MAX_PAYLOAD = {:?} bytes", *WS_MAX_PAYLOAD);
+    info!(
+        "WS_MAX_NODES_PER_CONNECTION = {:?}",
+        *WS_MAX_NODES_PER_CONNECTION
+    );
+    info!("HTTP_INGEST_MAX_BYTES = {:?}", *HTTP_INGEST_MAX_BYTES);
     info!("DB_POOL_SIZE = {:?}", *DB_POOL_SIZE);
+    info!("DB_COPY_POOL_SIZE = {:?}", *DB_COPY_POOL_SIZE);
     info!("DB_BATCH_SIZE = {:?}", *DB_BATCH_SIZE);
+    info!("DB_COPY_RETRIES = {:?}", *DB_COPY_RETRIES);
     info!("DB_SAVE_LATENCY_MS = {:?}", *DB_SAVE_LATENCY_MS);
     info!("PURGE_INTERVAL_S = {:?}", *PURGE_INTERVAL_S);
     info!("LOG_EXPIRY_H = {:?}", *LOG_EXPIRY_H);
+    info!("SESSION_RETENTION_H = {:?}", *SESSION_RETENTION_H);
+    info!("MAX_PIN_H = {:?}", *MAX_PIN_H);
+    info!("INSTANCE_ID = {:?}", *INSTANCE_ID);
+    info!("LOG_PARTITION_INTERVAL = {:?}", *LOG_PARTITION_INTERVAL);
+    info!("LOG_PARTITIONS_AHEAD = {:?}", *LOG_PARTITIONS_AHEAD);
     info!("CACHE_UPDATE_TIMEOUT_S = {:?}", *CACHE_UPDATE_TIMEOUT_S);
     info!("CACHE_UPDATE_INTERVAL_MS = {:?}", *CACHE_UPDATE_INTERVAL_MS);
     info!("CACHE_EXPIRY_S = {:?}", *CACHE_EXPIRY_S);
     info!("CACHE_TIMEOUT_S = {:?}", *CACHE_TIMEOUT_S);
+    info!("SHUTDOWN_TIMEOUT_S = {:?}", *SHUTDOWN_TIMEOUT_S);
+    info!("SPOOL_PATH = {:?}", *SPOOL_PATH);
+    info!("SPOOL_MAX_MB = {:?}", *SPOOL_MAX_MB);
+    info!("SPOOL_MAX_AGE_H = {:?}", *SPOOL_MAX_AGE_H);
+    info!("TRUSTED_PROXIES = {:?}", *TRUSTED_PROXIES);
+    info!("INGEST_AUTH = {:?}", *INGEST_AUTH);
+    info!("WS_CONN_MSGS_PER_S = {:?}", *WS_CONN_MSGS_PER_S);
+    info!("WS_CONN_BYTES_PER_S = {:?}", *WS_CONN_BYTES_PER_S);
+    info!("WS_IP_MSGS_PER_S = {:?}", *WS_IP_MSGS_PER_S);
+    info!("WS_IP_BYTES_PER_S = {:?}", *WS_IP_BYTES_PER_S);
+    info!("RATE_LIMIT_BURST_S = {:?}", *RATE_LIMIT_BURST_S);
+    info!("RATE_LIMIT_ACTION = {:?}", *RATE_LIMIT_ACTION);
+    info!("RATE_LIMIT_MAX_OFFENCES = {:?}", *RATE_LIMIT_MAX_OFFENCES);
+    info!(
+        "RATE_LIMIT_OFFENCE_WINDOW_S = {:?}",
+        *RATE_LIMIT_OFFENCE_WINDOW_S
+    );
+    info!("ADMIN_TOKEN is set: {:?}", ADMIN_TOKEN.is_some());
 }
 
 fn parse_env<T>(var: &'static str) -> Result<T, ()>
@@ -1,3 +1,13 @@
+table! {
+    afg_finalized (id) {
+        id -> Int4,
+        peer_connection_id -> Int4,
+        created_at -> Timestamp,
+        finalized_hash -> Varchar,
+        finalized_number -> Int8,
+    }
+}
+
 table! {
     benchmark_events (id) {
         id -> Int4,
@@ -13,6 +23,20 @@ table! {
         id -> Int4,
         setup -> Jsonb,
         created_at -> Timestamp,
+        state -> Varchar,
+        finished_at -> Nullable<Timestamp>,
+        summary -> Nullable<Jsonb>,
+    }
+}
+
+table! {
+    block_imports (id) {
+        id -> Int4,
+        peer_connection_id -> Int4,
+        created_at -> Timestamp,
+        best -> Varchar,
+        height -> Int8,
+        origin -> Nullable<Varchar>,
     }
 }
 
@@ -28,6 +52,40 @@ table! {
     }
 }
 
+table! {
+    ingest_tokens (id) {
+        id -> Int4,
+        token_hash -> Varchar,
+        description -> Nullable<Varchar>,
+        scope -> Varchar,
+        chain -> Nullable<Varchar>,
+        expires_at -> Nullable<Timestamp>,
+        revoked_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    log_pins (id) {
+        id -> Int4,
+        benchmark_id -> Int4,
+        peer_id -> Varchar,
+        start_time -> Timestamp,
+        end_time -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    notify_finalized (id) {
+        id -> Int4,
+        peer_connection_id -> Int4,
+        created_at -> Timestamp,
+        best -> Varchar,
+        height -> Int8,
+    }
+}
+
 table! {
     peer_connections (id) {
         id -> Int4,
@@ -41,25 +99,77 @@ table! {
         authority -> Nullable<Bool>,
         startup_time -> Nullable<Int8>,
         implementation -> Nullable<Varchar>,
+        peer_addr -> Nullable<Varchar>,
+        http_ingest -> Bool,
+        ingest_token_id -> Nullable<Int4>,
+        disconnected_at -> Nullable<Timestamp>,
+        disconnect_reason -> Nullable<Varchar>,
+        instance_id -> Nullable<Varchar>,
     }
 }
 
 table! {
-    substrate_logs (id) {
+    substrate_logs (id, audit, created_at) {
         id -> Int4,
         created_at -> Timestamp,
         logs -> Jsonb,
         peer_connection_id -> Int4,
+        audit -> Bool,
+    }
+}
+
+table! {
+    system_intervals (id) {
+        id -> Int4,
+        peer_connection_id -> Int4,
+        created_at -> Timestamp,
+        peers -> Nullable<Int8>,
+        best -> Nullable<Varchar>,
+        height -> Nullable<Int8>,
+        finalized_hash -> Nullable<Varchar>,
+        finalized_height -> Nullable<Int8>,
+        txcount -> Nullable<Int8>,
+        bandwidth_download -> Nullable<Int8>,
+        bandwidth_upload -> Nullable<Int8>,
+        cpu -> Nullable<Float8>,
+        memory -> Nullable<Int8>,
+        disk_read_per_sec -> Nullable<Int8>,
+        disk_write_per_sec -> Nullable<Int8>,
+        used_state_cache_size -> Nullable<Int8>,
+        used_db_cache_size -> Nullable<Int8>,
+    }
+}
+
+table! {
+    txpool_imports (id) {
+        id -> Int4,
+        peer_connection_id -> Int4,
+        created_at -> Timestamp,
+        ready -> Int8,
+        future -> Int8,
     }
 }
 
+joinable!(afg_finalized -> peer_connections (peer_connection_id));
 joinable!(benchmark_events -> benchmarks (benchmark_id));
+joinable!(block_imports -> peer_connections (peer_connection_id));
+joinable!(log_pins -> benchmarks (benchmark_id));
+joinable!(notify_finalized -> peer_connections (peer_connection_id));
 joinable!(substrate_logs -> peer_connections (peer_connection_id));
+joinable!(system_intervals -> peer_connections (peer_connection_id));
+joinable!(txpool_imports -> peer_connections (peer_connection_id));
 
 allow_tables_to_appear_in_same_query!(
+    afg_finalized,
     benchmark_events,
     benchmarks,
+    block_imports,
     host_systems,
+    ingest_tokens,
+    log_pins,
+    notify_finalized,
     peer_connections,
     substrate_logs,
+    system_intervals,
+    txpool_imports,
 );
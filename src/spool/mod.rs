@@ -0,0 +1,274 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate Analytics.
+
+// Substrate Analytics is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate Analytics is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::db::models::NewSubstrateLog;
+
+/// Segments are closed and a new one started once they exceed this size
+const SEGMENT_MAX_BYTES: u64 = 4 * 1024 * 1024;
+const SEGMENT_EXTENSION: &str = "ndjson";
+
+#[derive(Debug)]
+struct Segment {
+    seq: u64,
+    path: PathBuf,
+    bytes: u64,
+    modified: SystemTime,
+}
+
+/// On-disk spool for logs that could not be delivered to the DB
+///
+/// Logs are appended as NDJSON to numbered segment files in `path`, and read back
+/// oldest segment first. Segments that survive a restart are replayed as well.
+/// The oldest segments are dropped when the spool exceeds `max_bytes`, or when
+/// they have not been written to for longer than `max_age`.
+pub struct Spool {
+    path: PathBuf,
+    max_bytes: u64,
+    max_age: Duration,
+    /// Closed segments, oldest first
+    segments: VecDeque<Segment>,
+    /// Segment currently being appended to
+    current: Option<(Segment, File)>,
+    next_seq: u64,
+}
+
+impl Spool {
+    pub fn open<P: AsRef<Path>>(path: P, max_bytes: u64, max_age: Duration) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        fs::create_dir_all(&path)?;
+        let mut segments = Vec::new();
+        for entry in fs::read_dir(&path)? {
+            let entry = entry?;
+            let entry_path = entry.path();
+            if entry_path.extension().and_then(|e| e.to_str()) != Some(SEGMENT_EXTENSION) {
+                continue;
+            }
+            let seq = match entry_path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .and_then(|s| s.parse::<u64>().ok())
+            {
+                Some(seq) => seq,
+                None => continue,
+            };
+            let metadata = entry.metadata()?;
+            segments.push(Segment {
+                seq,
+                path: entry_path,
+                bytes: metadata.len(),
+                modified: metadata.modified()?,
+            });
+        }
+        segments.sort_by_key(|s| s.seq);
+        let next_seq = segments.last().map(|s| s.seq + 1).unwrap_or(0);
+        if !segments.is_empty() {
+            info!(
+                "Found {} spooled log segments to replay in {:?}",
+                segments.len(),
+                path
+            );
+        }
+        Ok(Spool {
+            path,
+            max_bytes,
+            max_age,
+            segments: segments.into(),
+            current: None,
+            next_seq,
+        })
+    }
+
+    /// Append logs to the current segment, making sure they are on disk before returning
+    pub fn append(&mut self, logs: &[NewSubstrateLog]) -> io::Result<()> {
+        if self.current.is_none() {
+            let seq = self.next_seq;
+            self.next_seq += 1;
+            let path = self.path.join(format!("{:020}.{}", seq, SEGMENT_EXTENSION));
+            let file = OpenOptions::new().create(true).append(true).open(&path)?;
+            let segment = Segment {
+                seq,
+                path,
+                bytes: 0,
+                modified: SystemTime::now(),
+            };
+            self.current = Some((segment, file));
+        }
+        let (segment, file) = self.current.as_mut().expect("Created above if None");
+        let mut buf = Vec::new();
+        for log in logs {
+            serde_json::to_writer(&mut buf, log)?;
+            buf.push(b'\n');
+        }
+        file.write_all(&buf)?;
+        file.sync_data()?;
+        segment.bytes += buf.len() as u64;
+        segment.modified = SystemTime::now();
+        if segment.bytes >= SEGMENT_MAX_BYTES {
+            self.close_current();
+        }
+        Ok(())
+    }
+
+    fn close_current(&mut self) {
+        if let Some((segment, _file)) = self.current.take() {
+            self.segments.push_back(segment);
+        }
+    }
+
+    /// Read the oldest segment, closing the current segment if there is nothing else to read.
+    /// The segment remains in the spool until `remove` is called with the returned sequence number.
+    pub fn oldest(&mut self) -> io::Result<Option<(u64, Vec<NewSubstrateLog>)>> {
+        if self.segments.is_empty() {
+            self.close_current();
+        }
+        let segment = match self.segments.front() {
+            Some(s) => s,
+            None => return Ok(None),
+        };
+        let mut logs = Vec::new();
+        for (n, line) in BufReader::new(File::open(&segment.path)?)
+            .lines()
+            .enumerate()
+        {
+            match serde_json::from_str(&line?) {
+                Ok(log) => logs.push(log),
+                Err(e) => warn!(
+                    "Skipping unreadable line {} in spool segment {:?}: {}",
+                    n + 1,
+                    segment.path,
+                    e
+                ),
+            }
+        }
+        Ok(Some((segment.seq, logs)))
+    }
+
+    /// Remove a segment once its logs have been delivered
+    pub fn remove(&mut self, seq: u64) {
+        if let Some(idx) = self.segments.iter().position(|s| s.seq == seq) {
+            if let Some(segment) = self.segments.remove(idx) {
+                if let Err(e) = fs::remove_file(&segment.path) {
+                    error!("Unable to remove spool segment {:?}: {}", segment.path, e);
+                }
+            }
+        }
+    }
+
+    /// Drop the oldest closed segments until the spool is within its size and age limits,
+    /// returning the number of segments dropped
+    pub fn enforce_limits(&mut self) -> u64 {
+        let now = SystemTime::now();
+        let mut dropped = 0;
+        while let Some(segment) = self.segments.front() {
+            let expired = now
+                .duration_since(segment.modified)
+                .map(|age| age > self.max_age)
+                .unwrap_or(false);
+            if !expired && self.bytes() <= self.max_bytes {
+                break;
+            }
+            let seq = segment.seq;
+            warn!(
+                "Dropping spool segment {:?} ({} bytes) - spool limits exceeded",
+                segment.path, segment.bytes
+            );
+            self.remove(seq);
+            dropped += 1;
+        }
+        dropped
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty() && self.current.is_none()
+    }
+
+    /// Number of segments, including the one being appended to
+    pub fn segments(&self) -> u64 {
+        self.segments.len() as u64 + self.current.is_some() as u64
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.segments.iter().map(|s| s.bytes).sum::<u64>()
+            + self.current.as_ref().map(|(s, _)| s.bytes).unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDateTime;
+
+    fn new_log(n: i32) -> NewSubstrateLog {
+        NewSubstrateLog {
+            logs: json!({ "msg": "system.interval", "n": n }),
+            peer_connection_id: n,
+            created_at: NaiveDateTime::from_timestamp(1_600_000_000, 0),
+            audit: false,
+        }
+    }
+
+    fn test_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "substrate-analytics-spool-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&path);
+        path
+    }
+
+    #[test]
+    fn spool_replays_in_order_test() {
+        let path = test_path("order");
+        let mut spool = Spool::open(&path, u64::MAX, Duration::from_secs(3600)).unwrap();
+        spool.append(&[new_log(1), new_log(2)]).unwrap();
+        spool.close_current();
+        spool.append(&[new_log(3)]).unwrap();
+        assert_eq!(spool.segments(), 2);
+        // Segments written before a restart are picked up again
+        drop(spool);
+        let mut spool = Spool::open(&path, u64::MAX, Duration::from_secs(3600)).unwrap();
+        let (seq, logs) = spool.oldest().unwrap().unwrap();
+        assert_eq!(logs.len(), 2);
+        assert_eq!(logs[0].peer_connection_id, 1);
+        spool.remove(seq);
+        let (seq, logs) = spool.oldest().unwrap().unwrap();
+        assert_eq!(logs[0].peer_connection_id, 3);
+        spool.remove(seq);
+        assert!(spool.oldest().unwrap().is_none());
+        assert!(spool.is_empty());
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn spool_enforces_size_limit_test() {
+        let path = test_path("limit");
+        let mut spool = Spool::open(&path, 1, Duration::from_secs(3600)).unwrap();
+        spool.append(&[new_log(1)]).unwrap();
+        spool.close_current();
+        spool.append(&[new_log(2)]).unwrap();
+        spool.close_current();
+        assert_eq!(spool.enforce_limits(), 2);
+        assert!(spool.is_empty());
+        fs::remove_dir_all(&path).unwrap();
+    }
+}
@@ -0,0 +1,57 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate Analytics.
+
+// Substrate Analytics is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate Analytics is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
+
+use super::error::ApiError;
+use super::get_filters;
+use super::metrics::Metrics;
+use super::openapi::ApiScope;
+use crate::db::aggregate::{AggregateParams, AggregateQuery, Aggregation};
+use crate::db::filters::Filters;
+use crate::db::DbExecutor;
+use actix::prelude::*;
+use actix_web::http::Method;
+use actix_web::{HttpRequest, HttpResponse};
+
+pub fn scope() -> ApiScope {
+    ApiScope::new("/aggregate/").route("", Method::GET, aggregate)
+}
+
+/// Aggregation described by the `AggregateParams` in `query`, of logs matching `filters`
+pub(crate) fn aggregate_query(
+    query: &str,
+    filters: Filters,
+    default_bucket_s: Option<i64>,
+) -> Result<AggregateQuery, ApiError> {
+    let mut params = actix_web::web::Query::<AggregateParams>::from_query(query)
+        .map_err(ApiError::invalid_params)?
+        .into_inner();
+    params.bucket_s = params.bucket_s.or(default_bucket_s);
+    let aggregation = Aggregation::from_params(params).map_err(ApiError::invalid_params)?;
+    AggregateQuery::new(filters, aggregation).map_err(ApiError::invalid_params)
+}
+
+async fn aggregate(
+    req: HttpRequest,
+    db: actix_web::web::Data<Addr<DbExecutor>>,
+    metrics: actix_web::web::Data<Metrics>,
+) -> Result<HttpResponse, ApiError> {
+    metrics.inc_req_count();
+    let query = aggregate_query(req.query_string(), get_filters(&req)?, None)?;
+    match db.send(query).await? {
+        Ok(r) => Ok(HttpResponse::Ok().json(json!(r))),
+        Err(e) => Err(ApiError::database("aggregate query", e)),
+    }
+}
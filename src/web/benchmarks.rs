@@ -14,41 +14,44 @@
 // You should have received a copy of the GNU General Public License
 // along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
 
+use super::error::{path_param, ApiError};
 use super::get_filters;
 use super::metrics::Metrics;
+use super::openapi::ApiScope;
+use crate::db::compare::{CompareParams, CompareQuery};
+use crate::db::host_systems::setup_host_system_id;
+use crate::db::lifecycle::{BenchmarkState, BenchmarkUpdate, TransitionBenchmark};
+use crate::db::phases::BenchmarkReportQuery;
 use crate::db::{benchmarks::*, models::*, DbExecutor};
 use actix::prelude::*;
+use actix_web::http::Method;
 use actix_web::{HttpRequest, HttpResponse};
 
-pub fn configure(cfg: &mut actix_web::web::ServiceConfig) {
-    cfg.service(
-        actix_web::web::scope("/benchmarks/")
-            .route("/example/", actix_web::web::get().to(example))
-            .route("/events/", actix_web::web::post().to(new_event))
-            .route(
-                "/{benchmark_id}/targets/",
-                actix_web::web::get().to(targets),
-            )
-            .route("/{benchmark_id}/events/", actix_web::web::get().to(events))
-            .route("", actix_web::web::get().to(all))
-            .route("", actix_web::web::post().to(new)),
-    );
+pub fn scope() -> ApiScope {
+    ApiScope::new("/benchmarks/")
+        .route("/example/", Method::GET, example)
+        .route("/events/", Method::POST, new_event)
+        .route("/compare/", Method::GET, compare)
+        .route("/{benchmark_id}/targets/", Method::GET, targets)
+        .route("/{benchmark_id}/events/", Method::GET, events)
+        .route("/{benchmark_id}/report/", Method::GET, report)
+        .route("/{benchmark_id}/state/", Method::POST, change_state)
+        .route("/{benchmark_id}/", Method::DELETE, delete)
+        .route("", Method::GET, all)
+        .route("", Method::POST, new)
 }
 
 async fn all(
     req: HttpRequest,
     db: actix_web::web::Data<Addr<DbExecutor>>,
     metrics: actix_web::web::Data<Metrics>,
-) -> Result<HttpResponse, actix_web::Error> {
+) -> Result<HttpResponse, ApiError> {
     metrics.inc_req_count();
-    let filters = get_filters(&req);
+    let filters = get_filters(&req)?;
     let res = db.send(Query::All(filters)).await?;
     match res {
         Ok(r) => Ok(HttpResponse::Ok().json(r)),
-        Err(e) => {
-            error!("Could not complete benchmarks query: {:?}", e);
-            Ok(HttpResponse::InternalServerError().json(json!("Error while processing query")))
-        }
+        Err(e) => Err(ApiError::database("benchmarks query", e)),
     }
 }
 
@@ -56,22 +59,13 @@ async fn targets(
     req: HttpRequest,
     db: actix_web::web::Data<Addr<DbExecutor>>,
     metrics: actix_web::web::Data<Metrics>,
-) -> Result<HttpResponse, actix_web::Error> {
+) -> Result<HttpResponse, ApiError> {
     metrics.inc_req_count();
-    let benchmark_id: i32 = req
-        .match_info()
-        .get("benchmark_id")
-        .expect("benchmark_id should be available because the route matched")
-        .to_string()
-        .parse()
-        .unwrap_or(0);
+    let benchmark_id: i32 = path_param(&req, "benchmark_id")?;
     let res = db.send(Query::Targets(benchmark_id)).await?;
     match res {
         Ok(r) => Ok(HttpResponse::Ok().json(r)),
-        Err(e) => {
-            error!("Could not complete benchmarks query: {:?}", e);
-            Ok(HttpResponse::InternalServerError().json(json!("Error while processing query")))
-        }
+        Err(e) => Err(ApiError::database("benchmarks query", e)),
     }
 }
 
@@ -79,22 +73,57 @@ async fn events(
     req: HttpRequest,
     db: actix_web::web::Data<Addr<DbExecutor>>,
     metrics: actix_web::web::Data<Metrics>,
-) -> Result<HttpResponse, actix_web::Error> {
+) -> Result<HttpResponse, ApiError> {
     metrics.inc_req_count();
-    let benchmark_id: i32 = req
-        .match_info()
-        .get("benchmark_id")
-        .expect("benchmark_id should be available because the route matched")
-        .to_string()
-        .parse()
-        .unwrap_or(0);
+    let benchmark_id: i32 = path_param(&req, "benchmark_id")?;
     let res = db.send(Query::Events(benchmark_id)).await?;
     match res {
         Ok(r) => Ok(HttpResponse::Ok().json(r)),
-        Err(e) => {
-            error!("Could not complete benchmarks query: {:?}", e);
-            Ok(HttpResponse::InternalServerError().json(json!("Error while processing query")))
-        }
+        Err(e) => Err(ApiError::database("benchmarks query", e)),
+    }
+}
+
+async fn delete(
+    req: HttpRequest,
+    db: actix_web::web::Data<Addr<DbExecutor>>,
+    metrics: actix_web::web::Data<Metrics>,
+) -> Result<HttpResponse, ApiError> {
+    metrics.inc_req_count();
+    let benchmark_id: i32 = path_param(&req, "benchmark_id")?;
+    match db.send(DeleteBenchmark(benchmark_id)).await? {
+        Ok(Some(r)) => Ok(HttpResponse::Ok().json(r)),
+        Ok(None) => Err(ApiError::not_found("Benchmark not found")),
+        Err(e) => Err(ApiError::database("benchmark delete", e)),
+    }
+}
+
+async fn report(
+    req: HttpRequest,
+    db: actix_web::web::Data<Addr<DbExecutor>>,
+    metrics: actix_web::web::Data<Metrics>,
+) -> Result<HttpResponse, ApiError> {
+    metrics.inc_req_count();
+    let benchmark_id: i32 = path_param(&req, "benchmark_id")?;
+    match db.send(BenchmarkReportQuery(benchmark_id)).await? {
+        Ok(Some(r)) => Ok(HttpResponse::Ok().json(r)),
+        Ok(None) => Err(ApiError::not_found("Benchmark not found")),
+        Err(e) => Err(ApiError::database("benchmark report query", e)),
+    }
+}
+
+async fn compare(
+    req: HttpRequest,
+    db: actix_web::web::Data<Addr<DbExecutor>>,
+    metrics: actix_web::web::Data<Metrics>,
+) -> Result<HttpResponse, ApiError> {
+    metrics.inc_req_count();
+    let params = actix_web::web::Query::<CompareParams>::from_query(req.query_string())
+        .map_err(ApiError::invalid_params)?
+        .into_inner();
+    let query = CompareQuery::new(params).map_err(ApiError::invalid_params)?;
+    match db.send(query).await? {
+        Ok(r) => Ok(HttpResponse::Ok().json(r)),
+        Err(e) => Err(ApiError::database("benchmark comparison query", e)),
     }
 }
 
@@ -127,15 +156,17 @@ async fn new(
     item: actix_web::web::Json<NewBenchmark>,
     db: actix_web::web::Data<Addr<DbExecutor>>,
     metrics: actix_web::web::Data<Metrics>,
-) -> Result<HttpResponse, actix_web::Error> {
+) -> Result<HttpResponse, ApiError> {
     metrics.inc_req_count();
-    let res = db.send(item.into_inner()).await?;
+    let benchmark = item.into_inner();
+    setup_host_system_id(&benchmark.setup).map_err(ApiError::invalid_body)?;
+    let res = db.send(benchmark).await?;
     match res {
-        Ok(r) => Ok(HttpResponse::Ok().json(r)),
-        Err(e) => {
-            error!("Could not create New Benchmark: {:?}", e);
-            Ok(HttpResponse::InternalServerError().json(json!({"error": e.to_string()})))
-        }
+        Ok(BenchmarkCreation::Created(r)) => Ok(HttpResponse::Ok().json(r)),
+        Ok(BenchmarkCreation::UnknownHostSystem(host_system_id)) => Err(ApiError::invalid_body(
+            format!("host system {} doesn't exist", host_system_id),
+        )),
+        Err(e) => Err(ApiError::database("new benchmark insert", e)),
     }
 }
 
@@ -143,19 +174,43 @@ async fn new_event(
     item: actix_web::web::Json<NewBenchmarkEvent>,
     db: actix_web::web::Data<Addr<DbExecutor>>,
     metrics: actix_web::web::Data<Metrics>,
-) -> Result<HttpResponse, actix_web::Error> {
+) -> Result<HttpResponse, ApiError> {
     metrics.inc_req_count();
     let res = db.send(item.into_inner()).await?;
     match res {
-        Ok(r) => Ok(HttpResponse::Ok().json(r)),
-        Err(e) => {
-            error!("Could not create New Benchmark: {:?}", e);
-            Ok(HttpResponse::InternalServerError().json(json!({"error": e.to_string()})))
-        }
+        Ok(BenchmarkUpdate::Saved(r)) => Ok(HttpResponse::Ok().json(r)),
+        Ok(BenchmarkUpdate::NotFound) => Err(ApiError::not_found("Benchmark not found")),
+        Ok(BenchmarkUpdate::Conflict(message)) => Err(ApiError::conflict(message)),
+        Err(e) => Err(ApiError::database("new benchmark event insert", e)),
+    }
+}
+
+#[derive(Deserialize)]
+struct StateChange {
+    state: BenchmarkState,
+}
+
+async fn change_state(
+    req: HttpRequest,
+    item: actix_web::web::Json<StateChange>,
+    db: actix_web::web::Data<Addr<DbExecutor>>,
+    metrics: actix_web::web::Data<Metrics>,
+) -> Result<HttpResponse, ApiError> {
+    metrics.inc_req_count();
+    let benchmark_id: i32 = path_param(&req, "benchmark_id")?;
+    let msg = TransitionBenchmark {
+        benchmark_id,
+        state: item.into_inner().state,
+    };
+    match db.send(msg).await? {
+        Ok(BenchmarkUpdate::Saved(r)) => Ok(HttpResponse::Ok().json(r)),
+        Ok(BenchmarkUpdate::NotFound) => Err(ApiError::not_found("Benchmark not found")),
+        Ok(BenchmarkUpdate::Conflict(message)) => Err(ApiError::conflict(message)),
+        Err(e) => Err(ApiError::database("benchmark state update", e)),
     }
 }
 
-async fn example(metrics: actix_web::web::Data<Metrics>) -> Result<HttpResponse, actix_web::Error> {
+async fn example(metrics: actix_web::web::Data<Metrics>) -> Result<HttpResponse, ApiError> {
     metrics.inc_req_count();
     Ok(actix_web::web::HttpResponse::Ok().finish())
 }
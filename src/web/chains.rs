@@ -0,0 +1,75 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate Analytics.
+
+// Substrate Analytics is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate Analytics is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
+
+use super::error::ApiError;
+use super::get_filters;
+use super::metrics::Metrics;
+use super::openapi::ApiScope;
+use crate::db::chains::{ChainHistoryQuery, ChainParams, ChainsQuery};
+use crate::db::DbExecutor;
+use actix::prelude::*;
+use actix_web::http::Method;
+use actix_web::{HttpRequest, HttpResponse};
+
+pub fn scope() -> ApiScope {
+    ApiScope::new("/chains/")
+        .route("/history/", Method::GET, history)
+        .route("", Method::GET, chains)
+}
+
+fn chain_params(req: &HttpRequest) -> Result<ChainParams, ApiError> {
+    actix_web::web::Query::<ChainParams>::from_query(req.query_string())
+        .map(|q| q.into_inner())
+        .map_err(ApiError::invalid_params)
+}
+
+async fn chains(
+    req: HttpRequest,
+    db: actix_web::web::Data<Addr<DbExecutor>>,
+    metrics: actix_web::web::Data<Metrics>,
+) -> Result<HttpResponse, ApiError> {
+    metrics.inc_req_count();
+    let params = chain_params(&req)?;
+    match db
+        .send(ChainsQuery {
+            chain: params.chain,
+        })
+        .await?
+    {
+        Ok(r) => Ok(HttpResponse::Ok().json(json!(r))),
+        Err(e) => Err(ApiError::database("chains query", e)),
+    }
+}
+
+async fn history(
+    req: HttpRequest,
+    db: actix_web::web::Data<Addr<DbExecutor>>,
+    metrics: actix_web::web::Data<Metrics>,
+) -> Result<HttpResponse, ApiError> {
+    metrics.inc_req_count();
+    let filters = get_filters(&req)?;
+    let query = ChainHistoryQuery::new(
+        chain_params(&req)?,
+        filters.start_time,
+        filters.end_time,
+        filters.max_age_s,
+    )
+    .map_err(ApiError::invalid_params)?;
+    match db.send(query).await? {
+        Ok(r) => Ok(HttpResponse::Ok().json(json!(r))),
+        Err(e) => Err(ApiError::database("chain history query", e)),
+    }
+}
@@ -0,0 +1,194 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate Analytics.
+
+// Substrate Analytics is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate Analytics is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
+
+use actix_web::http::header::HeaderMap;
+use actix_web::HttpRequest;
+use ipnetwork::IpNetwork;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+/// Proxies whose forwarding headers we believe, eg. `10.0.0.0/8,192.168.1.10`
+#[derive(Clone, Debug, Default)]
+pub struct TrustedProxies(Vec<IpNetwork>);
+
+impl TrustedProxies {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.0.iter().any(|net| net.contains(ip))
+    }
+}
+
+impl FromStr for TrustedProxies {
+    type Err = ipnetwork::IpNetworkError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<IpNetwork>, _>>()
+            .map(TrustedProxies)
+    }
+}
+
+/// Address of a connecting node
+#[derive(Clone, Debug)]
+pub struct ClientAddr {
+    /// The node itself, taken from forwarding headers if the request came through a trusted proxy
+    pub ip: String,
+    /// The direct peer of the connection
+    pub peer_addr: Option<String>,
+}
+
+pub fn client_addr(req: &HttpRequest, trusted: &TrustedProxies) -> ClientAddr {
+    let peer = req.head().peer_addr.map(|a| a.ip());
+    ClientAddr {
+        ip: resolve(peer, req.headers(), trusted)
+            .map(|ip| ip.to_string())
+            .unwrap_or_else(|| "Unable to decode remote IP".to_string()),
+        peer_addr: req.head().peer_addr.map(|a| a.to_string()),
+    }
+}
+
+fn resolve(peer: Option<IpAddr>, headers: &HeaderMap, trusted: &TrustedProxies) -> Option<IpAddr> {
+    let peer = peer?;
+    if !trusted.contains(peer) {
+        return Some(peer);
+    }
+    // Each proxy appends the address it received the request from, so walk the chain from
+    // the right and take the first address that isn't one of our proxies
+    let chain = forwarded_chain(headers);
+    if !chain.is_empty() {
+        return chain
+            .iter()
+            .rev()
+            .find(|ip| !trusted.contains(**ip))
+            .or_else(|| chain.first())
+            .copied();
+    }
+    header_str(headers, "x-real-ip")
+        .and_then(parse_ip)
+        .or(Some(peer))
+}
+
+/// Addresses from `Forwarded` or, if absent, `X-Forwarded-For`, in the order they were added
+fn forwarded_chain(headers: &HeaderMap) -> Vec<IpAddr> {
+    let forwarded: Vec<IpAddr> = headers
+        .get_all("forwarded")
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .flat_map(|element| element.split(';'))
+        .filter_map(|pair| {
+            let mut kv = pair.splitn(2, '=');
+            match (kv.next(), kv.next()) {
+                (Some(k), Some(v)) if k.trim().eq_ignore_ascii_case("for") => parse_ip(v),
+                _ => None,
+            }
+        })
+        .collect();
+    if !forwarded.is_empty() {
+        return forwarded;
+    }
+    headers
+        .get_all("x-forwarded-for")
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(parse_ip)
+        .collect()
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+/// Accepts `1.2.3.4`, `1.2.3.4:80`, `"[2001:db8::1]:80"` and `2001:db8::1`
+fn parse_ip(s: &str) -> Option<IpAddr> {
+    let s = s.trim().trim_matches('"');
+    if let Ok(ip) = s.parse::<IpAddr>() {
+        return Some(ip);
+    }
+    if let Ok(addr) = s.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    s.strip_prefix('[')
+        .and_then(|s| s.split(']').next())
+        .and_then(|s| s.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::{HeaderName, HeaderValue};
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (k, v) in pairs {
+            map.append(HeaderName::from_static(k), HeaderValue::from_static(v));
+        }
+        map
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn ignores_headers_from_untrusted_peer_test() {
+        let trusted: TrustedProxies = "10.0.0.0/8".parse().unwrap();
+        let h = headers(&[("x-forwarded-for", "1.1.1.1")]);
+        assert_eq!(
+            resolve(Some(ip("2.2.2.2")), &h, &trusted),
+            Some(ip("2.2.2.2"))
+        );
+    }
+
+    #[test]
+    fn takes_rightmost_untrusted_forwarded_for_test() {
+        let trusted: TrustedProxies = "10.0.0.0/8, 192.168.0.1".parse().unwrap();
+        let h = headers(&[("x-forwarded-for", "6.6.6.6, 1.1.1.1, 192.168.0.1")]);
+        assert_eq!(
+            resolve(Some(ip("10.0.0.2")), &h, &trusted),
+            Some(ip("1.1.1.1"))
+        );
+    }
+
+    #[test]
+    fn prefers_forwarded_header_test() {
+        let trusted: TrustedProxies = "10.0.0.1".parse().unwrap();
+        let h = headers(&[
+            ("forwarded", "for=\"[2001:db8::1]:4711\";proto=https"),
+            ("x-forwarded-for", "1.1.1.1"),
+        ]);
+        assert_eq!(
+            resolve(Some(ip("10.0.0.1")), &h, &trusted),
+            Some(ip("2001:db8::1"))
+        );
+    }
+
+    #[test]
+    fn falls_back_to_real_ip_then_peer_test() {
+        let trusted: TrustedProxies = "10.0.0.1".parse().unwrap();
+        let h = headers(&[("x-real-ip", "3.3.3.3")]);
+        assert_eq!(
+            resolve(Some(ip("10.0.0.1")), &h, &trusted),
+            Some(ip("3.3.3.3"))
+        );
+        let h = headers(&[]);
+        assert_eq!(
+            resolve(Some(ip("10.0.0.1")), &h, &trusted),
+            Some(ip("10.0.0.1"))
+        );
+    }
+}
@@ -0,0 +1,193 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate Analytics.
+
+// Substrate Analytics is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate Analytics is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
+
+use serde_json::Value;
+use std::fmt;
+use std::io::Read;
+use std::str::FromStr;
+
+/// Compression applied to binary frames
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    None,
+    /// Raw DEFLATE stream (RFC 1951)
+    Deflate,
+    Zstd,
+}
+
+/// Serialization of the log messages in binary frames
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Json,
+    MessagePack,
+    Cbor,
+}
+
+/// How a node encodes its binary frames, negotiated at handshake and written as
+/// `format` or `format+compression`, eg. `msgpack+zstd`. Text frames are always JSON.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameEncoding {
+    pub format: Format,
+    pub compression: Compression,
+}
+
+impl Default for FrameEncoding {
+    fn default() -> Self {
+        FrameEncoding {
+            format: Format::Json,
+            compression: Compression::None,
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Compression::None),
+            "deflate" => Ok(Compression::Deflate),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(format!("Unsupported compression: {}", s)),
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Format::Json),
+            "msgpack" => Ok(Format::MessagePack),
+            "cbor" => Ok(Format::Cbor),
+            _ => Err(format!("Unsupported format: {}", s)),
+        }
+    }
+}
+
+impl FromStr for FrameEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().splitn(2, '+');
+        let format = parts.next().unwrap_or_default().parse()?;
+        let compression = match parts.next() {
+            Some(c) => c.parse()?,
+            None => Compression::None,
+        };
+        Ok(FrameEncoding {
+            format,
+            compression,
+        })
+    }
+}
+
+impl fmt::Display for FrameEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let format = match self.format {
+            Format::Json => "json",
+            Format::MessagePack => "msgpack",
+            Format::Cbor => "cbor",
+        };
+        match self.compression {
+            Compression::None => write!(f, "{}", format),
+            Compression::Deflate => write!(f, "{}+deflate", format),
+            Compression::Zstd => write!(f, "{}+zstd", format),
+        }
+    }
+}
+
+impl FrameEncoding {
+    /// Decode a binary frame, returning the log message and the size of the frame once decompressed.
+    /// Frames that decompress to more than `max_size` bytes are rejected.
+    pub fn decode(&self, frame: &[u8], max_size: usize) -> Result<(Value, usize), String> {
+        let decompressed;
+        let bytes = match self.compression {
+            Compression::None => frame,
+            Compression::Deflate => {
+                decompressed = read_limited(flate2::read::DeflateDecoder::new(frame), max_size)?;
+                &decompressed[..]
+            }
+            Compression::Zstd => {
+                let decoder = zstd::stream::read::Decoder::new(frame).map_err(|e| e.to_string())?;
+                decompressed = read_limited(decoder, max_size)?;
+                &decompressed[..]
+            }
+        };
+        let value = match self.format {
+            Format::Json => serde_json::from_slice(bytes).map_err(|e| e.to_string())?,
+            Format::MessagePack => rmp_serde::from_read_ref(bytes).map_err(|e| e.to_string())?,
+            Format::Cbor => serde_cbor::from_slice(bytes).map_err(|e| e.to_string())?,
+        };
+        Ok((value, bytes.len()))
+    }
+}
+
+fn read_limited<R: Read>(reader: R, max_size: usize) -> Result<Vec<u8>, String> {
+    let mut buf = Vec::new();
+    reader
+        .take(max_size as u64 + 1)
+        .read_to_end(&mut buf)
+        .map_err(|e| e.to_string())?;
+    if buf.len() > max_size {
+        return Err(format!("Decompressed frame exceeds {} bytes", max_size));
+    }
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn parse_encoding_test() {
+        let e: FrameEncoding = "msgpack+zstd".parse().unwrap();
+        assert_eq!(e.format, Format::MessagePack);
+        assert_eq!(e.compression, Compression::Zstd);
+        assert_eq!(e.to_string(), "msgpack+zstd");
+        assert_eq!(
+            "json".parse::<FrameEncoding>(),
+            Ok(FrameEncoding::default())
+        );
+        assert!("json+gzip".parse::<FrameEncoding>().is_err());
+    }
+
+    #[test]
+    fn decode_deflate_cbor_test() {
+        let log = json!({"msg": "system.interval", "ts": "2020-10-19T10:00:00Z", "peers": 3});
+        let cbor = serde_cbor::to_vec(&log).unwrap();
+        let mut encoder =
+            flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&cbor).unwrap();
+        let frame = encoder.finish().unwrap();
+
+        let encoding: FrameEncoding = "cbor+deflate".parse().unwrap();
+        assert_eq!(encoding.decode(&frame, 1024), Ok((log, cbor.len())));
+        assert!(encoding.decode(&frame, 4).is_err());
+    }
+
+    #[test]
+    fn decode_zstd_msgpack_test() {
+        let log = json!({"msg": "block.import", "height": 10});
+        let msgpack = rmp_serde::to_vec_named(&log).unwrap();
+        let frame = zstd::stream::encode_all(&msgpack[..], 0).unwrap();
+
+        let encoding: FrameEncoding = "msgpack+zstd".parse().unwrap();
+        assert_eq!(encoding.decode(&frame, 1024), Ok((log, msgpack.len())));
+    }
+}
@@ -0,0 +1,190 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate Analytics.
+
+// Substrate Analytics is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate Analytics is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
+
+use actix::MailboxError;
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use std::fmt;
+
+/// Stable identifier of the kind of error, clients should match on this rather than the message
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The querystring couldn't be parsed as `Filters`, or they are inconsistent
+    InvalidFilters,
+    /// A route specific querystring parameter is invalid
+    InvalidParams,
+    /// A path segment, eg. an id, is invalid
+    InvalidPath,
+    /// The request body couldn't be parsed
+    InvalidBody,
+    /// Missing or wrong admin token
+    Forbidden,
+    NotFound,
+    /// The request conflicts with the current state, eg. deleting something still referenced
+    Conflict,
+    /// The database query failed, details are only logged
+    DatabaseError,
+    InternalError,
+}
+
+impl ErrorCode {
+    pub const ALL: [ErrorCode; 9] = [
+        ErrorCode::InvalidFilters,
+        ErrorCode::InvalidParams,
+        ErrorCode::InvalidPath,
+        ErrorCode::InvalidBody,
+        ErrorCode::Forbidden,
+        ErrorCode::NotFound,
+        ErrorCode::Conflict,
+        ErrorCode::DatabaseError,
+        ErrorCode::InternalError,
+    ];
+
+    pub fn status(self) -> StatusCode {
+        match self {
+            ErrorCode::InvalidFilters
+            | ErrorCode::InvalidParams
+            | ErrorCode::InvalidPath
+            | ErrorCode::InvalidBody => StatusCode::BAD_REQUEST,
+            ErrorCode::Forbidden => StatusCode::FORBIDDEN,
+            ErrorCode::NotFound => StatusCode::NOT_FOUND,
+            ErrorCode::Conflict => StatusCode::CONFLICT,
+            ErrorCode::DatabaseError | ErrorCode::InternalError => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::InvalidFilters => "invalid_filters",
+            ErrorCode::InvalidParams => "invalid_params",
+            ErrorCode::InvalidPath => "invalid_path",
+            ErrorCode::InvalidBody => "invalid_body",
+            ErrorCode::Forbidden => "forbidden",
+            ErrorCode::NotFound => "not_found",
+            ErrorCode::Conflict => "conflict",
+            ErrorCode::DatabaseError => "database_error",
+            ErrorCode::InternalError => "internal_error",
+        }
+    }
+}
+
+/// Error returned by route handlers, rendered as `{"code": ..., "message": ...}`
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ApiError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new<M: Into<String>>(code: ErrorCode, message: M) -> Self {
+        ApiError {
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn invalid_filters<M: fmt::Display>(message: M) -> Self {
+        Self::new(
+            ErrorCode::InvalidFilters,
+            format!("Invalid filters: {}", message),
+        )
+    }
+
+    pub fn invalid_params<M: fmt::Display>(message: M) -> Self {
+        Self::new(ErrorCode::InvalidParams, message.to_string())
+    }
+
+    pub fn invalid_body<M: fmt::Display>(message: M) -> Self {
+        Self::new(
+            ErrorCode::InvalidBody,
+            format!("Invalid request body: {}", message),
+        )
+    }
+
+    pub fn not_found<M: Into<String>>(message: M) -> Self {
+        Self::new(ErrorCode::NotFound, message)
+    }
+
+    pub fn conflict<M: Into<String>>(message: M) -> Self {
+        Self::new(ErrorCode::Conflict, message)
+    }
+
+    /// Log the cause of a failed query, which isn't exposed to clients
+    pub fn database<E: fmt::Display>(context: &str, e: E) -> Self {
+        error!("Could not complete {}: {}", context, e);
+        Self::new(ErrorCode::DatabaseError, "Error while processing query")
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code.as_str(), self.message)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.code.status()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(self)
+    }
+}
+
+impl From<MailboxError> for ApiError {
+    fn from(e: MailboxError) -> Self {
+        error!("Could not reach DbExecutor: {}", e);
+        Self::new(ErrorCode::InternalError, "Internal server error")
+    }
+}
+
+/// Parse the path segment `name` of a matched route
+pub fn path_param<T: std::str::FromStr>(
+    req: &actix_web::HttpRequest,
+    name: &str,
+) -> Result<T, ApiError> {
+    let value = req.match_info().get(name).unwrap_or_default();
+    value.parse().map_err(|_| {
+        ApiError::new(
+            ErrorCode::InvalidPath,
+            format!("Invalid `{}`: `{}`", name, value),
+        )
+    })
+}
+
+/// Renders `Json` extractor failures as `ApiError`s
+pub fn json_config() -> actix_web::web::JsonConfig {
+    actix_web::web::JsonConfig::default().error_handler(|e, _| ApiError::invalid_body(e).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_codes_serialize_as_their_str_test() {
+        for code in ErrorCode::ALL.iter() {
+            assert_eq!(json!(code), json!(code.as_str()));
+        }
+        assert_eq!(
+            json!(ApiError::invalid_params("`msg` is required")),
+            json!({"code": "invalid_params", "message": "`msg` is required"})
+        );
+    }
+}
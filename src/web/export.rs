@@ -0,0 +1,172 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate Analytics.
+
+// Substrate Analytics is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate Analytics is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
+
+use super::error::ApiError;
+use super::get_filters;
+use super::metrics::Metrics;
+use super::openapi::ApiScope;
+use crate::db::DbExecutor;
+use crate::export::{
+    csv_chunk, csv_header, ndjson_chunk, ExportCursor, ExportFormat, Fields, ParquetExport,
+};
+use actix::prelude::*;
+use actix_files::NamedFile;
+use actix_web::error::BlockingError;
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::http::Method;
+use actix_web::{error, web::Bytes, HttpRequest, HttpResponse};
+use futures::{stream, StreamExt};
+use tempfile::NamedTempFile;
+
+pub fn scope() -> ApiScope {
+    ApiScope::new("/export/").route("", Method::GET, export)
+}
+
+#[derive(Deserialize, Debug)]
+struct ExportParams {
+    format: Option<String>,
+    /// Comma separated fields of the log message to include as CSV or Parquet columns
+    fields: Option<String>,
+}
+
+async fn export(
+    req: HttpRequest,
+    db: actix_web::web::Data<Addr<DbExecutor>>,
+    metrics: actix_web::web::Data<Metrics>,
+) -> Result<HttpResponse, actix_web::Error> {
+    metrics.inc_req_count();
+    let filters = get_filters(&req)?;
+    let params = actix_web::web::Query::<ExportParams>::from_query(req.query_string())
+        .map_err(ApiError::invalid_params)?
+        .into_inner();
+    let format: ExportFormat = params
+        .format
+        .as_deref()
+        .unwrap_or("ndjson")
+        .parse()
+        .map_err(ApiError::invalid_params)?;
+    let fields: Fields = params
+        .fields
+        .as_deref()
+        .unwrap_or_default()
+        .parse()
+        .map_err(ApiError::invalid_params)?;
+    let db = db.get_ref().clone();
+    let cursor = ExportCursor::new(filters);
+    if format == ExportFormat::Parquet {
+        // Parquet needs the whole file written before it can be read, so it goes via a temp file
+        return export_parquet(&req, db, cursor, fields).await;
+    }
+    let disposition = format!(
+        "attachment; filename=\"substrate_logs.{}\"",
+        format.extension()
+    );
+    let header = match format {
+        ExportFormat::Csv => Some(Ok(Bytes::from(csv_header(&fields)))),
+        _ => None,
+    };
+    let pages = stream::unfold(Some(cursor), move |cursor| {
+        let db = db.clone();
+        let fields = fields.clone();
+        async move {
+            let mut cursor = cursor?;
+            match cursor.next(&db).await {
+                Ok(Some(rows)) => {
+                    let chunk = match format {
+                        ExportFormat::Csv => csv_chunk(&rows, &fields),
+                        _ => ndjson_chunk(&rows),
+                    };
+                    Some((Ok(Bytes::from(chunk)), Some(cursor)))
+                }
+                Ok(None) => None,
+                Err(e) => {
+                    error!("Could not complete export query: {}", e);
+                    Some((
+                        Err(error::ErrorInternalServerError(
+                            "Error while processing query",
+                        )),
+                        None,
+                    ))
+                }
+            }
+        }
+    });
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .header("Content-Disposition", disposition)
+        .streaming(stream::iter(header).chain(Box::pin(pages))))
+}
+
+async fn export_parquet(
+    req: &HttpRequest,
+    db: Addr<DbExecutor>,
+    mut cursor: ExportCursor,
+    fields: Fields,
+) -> Result<HttpResponse, actix_web::Error> {
+    let result = async {
+        // The temp file is removed when dropped, including when the request is cancelled
+        let (temp, mut parquet) = blocking(move || {
+            let temp = NamedTempFile::new().map_err(|e| e.to_string())?;
+            let parquet = ParquetExport::new(temp.reopen().map_err(|e| e.to_string())?, fields)
+                .map_err(|e| e.to_string())?;
+            Ok((temp, parquet))
+        })
+        .await?;
+        while let Some(rows) = cursor.next(&db).await? {
+            parquet = blocking(move || {
+                parquet.write(&rows).map_err(|e| e.to_string())?;
+                Ok(parquet)
+            })
+            .await?;
+        }
+        blocking(move || {
+            parquet.close().map_err(|e| e.to_string())?;
+            // The open file is still streamed from after it is removed
+            let file = NamedFile::open(temp.path()).map_err(|e| e.to_string())?;
+            if let Err(e) = temp.close() {
+                warn!("Unable to remove export file: {}", e);
+            }
+            Ok(file)
+        })
+        .await
+    }
+    .await;
+    match result {
+        Ok(file) => Ok(file
+            .set_content_type(ExportFormat::Parquet.content_type().parse().unwrap())
+            .set_content_disposition(ContentDisposition {
+                disposition: DispositionType::Attachment,
+                parameters: vec![DispositionParam::Filename(
+                    "substrate_logs.parquet".to_string(),
+                )],
+            })
+            .into_response(req)?),
+        Err(e) => Err(ApiError::database("parquet export", e).into()),
+    }
+}
+
+/// Run file IO on the blocking thread pool rather than the actix worker
+async fn blocking<F, T>(f: F) -> Result<T, String>
+where
+    F: FnOnce() -> Result<T, String> + Send + 'static,
+    T: Send + 'static,
+{
+    match actix_web::web::block(f).await {
+        Ok(v) => Ok(v),
+        Err(BlockingError::Error(e)) => Err(e),
+        Err(BlockingError::Canceled) => Err("Blocking thread pool is gone".to_string()),
+    }
+}
@@ -1,6 +1,8 @@
 use crate::cache::{Cache, Interest, Subscription};
+use crate::db::filters::{parse_predicates, FieldPredicate};
 use crate::db::peer_data::{PeerDataArray, PeerMessage, SubstrateLog};
 use crate::web::metrics::Metrics;
+use crate::web::shutdown::{Connections, Disconnect, Registration, ShutdownFlag};
 use actix::prelude::*;
 use actix_web::{web, web::Data, Error, HttpRequest, HttpResponse};
 use actix_web_actors::ws;
@@ -23,15 +25,22 @@ async fn ws_index(
     stream: web::Payload,
     cache: Data<Addr<Cache>>,
     metrics: actix_web::web::Data<Metrics>,
+    connections: Data<Addr<Connections>>,
+    shutdown: Data<ShutdownFlag>,
 ) -> Result<HttpResponse, Error> {
-    ws::start(WebSocket::new(cache, metrics), &r, stream)
+    if shutdown.is_set() {
+        return Err(actix_web::error::ErrorServiceUnavailable("Shutting down"));
+    }
+    ws::start(WebSocket::new(cache, metrics, connections), &r, stream)
 }
 
 struct WebSocket {
     hb: Instant,
     cache: Data<Addr<Cache>>,
     metrics: actix_web::web::Data<Metrics>,
+    connections: Data<Addr<Connections>>,
     aggregate_subscriptions: HashMap<PeerMessage, AggregateSubscription>,
+    field_predicates: HashMap<PeerMessage, Vec<FieldPredicate>>,
 }
 
 impl Drop for WebSocket {
@@ -44,7 +53,14 @@ impl Drop for WebSocket {
 impl Handler<PeerDataArray> for WebSocket {
     type Result = Result<(), &'static str>;
 
-    fn handle(&mut self, msg: PeerDataArray, ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, mut msg: PeerDataArray, ctx: &mut Self::Context) -> Self::Result {
+        if let Some(predicates) = self.field_predicates.get(&msg.peer_message) {
+            msg.data
+                .retain(|log| predicates.iter().all(|p| p.matches(&log.log)));
+            if msg.data.is_empty() {
+                return Ok(());
+            }
+        }
         match self.aggregate_subscriptions.get_mut(&msg.peer_message) {
             Some(subs) => {
                 subs.aggregate_remainder.append(&mut msg.data.into());
@@ -209,6 +225,25 @@ impl Actor for WebSocket {
         ctx.set_mailbox_capacity(64);
         self.hb(ctx);
         self.metrics.inc_concurrent_feed_count();
+        self.connections
+            .do_send(Registration::Register(ctx.address().recipient()));
+    }
+
+    fn stopped(&mut self, ctx: &mut Self::Context) {
+        self.connections
+            .do_send(Registration::Unregister(ctx.address().recipient()));
+    }
+}
+
+impl Handler<Disconnect> for WebSocket {
+    type Result = ();
+
+    fn handle(&mut self, msg: Disconnect, ctx: &mut Self::Context) -> Self::Result {
+        ctx.close(Some(ws::CloseReason {
+            code: ws::CloseCode::Restart,
+            description: Some(msg.reason),
+        }));
+        ctx.stop();
     }
 }
 
@@ -239,12 +274,18 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebSocket {
 }
 
 impl WebSocket {
-    fn new(cache: Data<Addr<Cache>>, metrics: actix_web::web::Data<Metrics>) -> Self {
+    fn new(
+        cache: Data<Addr<Cache>>,
+        metrics: actix_web::web::Data<Metrics>,
+        connections: Data<Addr<Connections>>,
+    ) -> Self {
         Self {
             hb: Instant::now(),
             cache,
             metrics,
+            connections,
             aggregate_subscriptions: HashMap::new(),
+            field_predicates: HashMap::new(),
         }
     }
 
@@ -252,7 +293,7 @@ impl WebSocket {
         &mut self,
         text: String,
         ctx: &mut <Self as Actor>::Context,
-    ) -> Result<(), &'static str> {
+    ) -> Result<(), String> {
         if let Ok(j) = serde_json::from_str::<Value>(&text) {
             let peer_id: String = j["peer_id"]
                 .as_str()
@@ -272,7 +313,7 @@ impl WebSocket {
                     Interest::Subscribe
                 }
                 "unsubscribe" => Interest::Unsubscribe,
-                _ => return Err("`interest` must be either `subscribe` or `unsubscribe`"),
+                _ => return Err("`interest` must be either `subscribe` or `unsubscribe`".into()),
             };
             let subscription = Subscription {
                 peer_id,
@@ -282,19 +323,41 @@ impl WebSocket {
                 interest,
             };
 
+            self.handle_field_predicates(&subscription, &j)?;
             self.handle_aggregate_subscription(&subscription, j)?;
 
             match self.cache.try_send(subscription) {
                 Ok(_) => debug!("Sent subscription"),
                 Err(e) => {
                     error!("Could not send subscription due to: {:?}", e);
-                    return Err("Internal server error");
+                    return Err("Internal server error".into());
                 }
             }
         }
         Ok(())
     }
 
+    fn handle_field_predicates(
+        &mut self,
+        subscription: &Subscription,
+        json: &Value,
+    ) -> Result<(), String> {
+        let peer_message = PeerMessage {
+            peer_id: subscription.peer_id.to_owned(),
+            msg: subscription.msg.to_owned(),
+        };
+        let predicates = match json["where"].as_str() {
+            Some(s) if subscription.interest == Interest::Subscribe => parse_predicates(s)?,
+            _ => Vec::new(),
+        };
+        if predicates.is_empty() {
+            self.field_predicates.remove(&peer_message);
+        } else {
+            self.field_predicates.insert(peer_message, predicates);
+        }
+        Ok(())
+    }
+
     fn handle_aggregate_subscription(
         &mut self,
         subscription: &Subscription,
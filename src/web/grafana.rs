@@ -0,0 +1,252 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate Analytics.
+
+// Substrate Analytics is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate Analytics is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
+
+use super::aggregate::aggregate_query;
+use super::error::ApiError;
+use super::metrics::Metrics;
+use super::openapi::ApiScope;
+use super::parse_filters;
+use crate::db::aggregate::{AggregateSeries, Aggregation};
+use crate::db::filters::Filters;
+use crate::db::grafana::{AnnotationsQuery, MetricNamesQuery};
+use crate::db::DbExecutor;
+use actix::prelude::*;
+use actix_web::http::Method;
+use actix_web::web::{Data, Json};
+use actix_web::HttpResponse;
+use chrono::{DateTime, Duration, Utc};
+use serde_json::Value;
+
+/// How far back `/search` looks for `msg` types and fields
+const SEARCH_WINDOW_S: i64 = 3600;
+
+/// Implements the Grafana JSON datasource protocol, see
+/// https://grafana.com/grafana/plugins/grafana-simple-json-datasource
+///
+/// Query targets use the querystring syntax of `/aggregate`, eg. `msg=block.import&field=height&functions=max`
+pub fn scope() -> ApiScope {
+    ApiScope::new("/grafana/")
+        .route("/search/", Method::POST, search)
+        .route("/query/", Method::POST, query)
+        .route("/annotations/", Method::POST, annotations)
+        .route("", Method::GET, test_connection)
+}
+
+#[derive(Deserialize, Debug)]
+struct SearchRequest {
+    #[serde(default)]
+    target: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Range {
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct QueryRequest {
+    range: Range,
+    interval_ms: Option<i64>,
+    targets: Vec<QueryTarget>,
+}
+
+#[derive(Deserialize, Debug)]
+struct QueryTarget {
+    #[serde(default)]
+    target: String,
+    #[serde(rename = "type", default)]
+    kind: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct AnnotationRequest {
+    range: Range,
+    annotation: Value,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct AnnotationParams {
+    /// `benchmarks` or `connects`, default both
+    source: Option<String>,
+    peer_id: Option<String>,
+}
+
+/// Grafana calls this to check the datasource is reachable
+async fn test_connection(metrics: Data<Metrics>) -> Result<HttpResponse, ApiError> {
+    metrics.inc_req_count();
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Targets for each recently seen `msg` type, and each of its numeric fields
+async fn search(
+    body: Json<SearchRequest>,
+    db: Data<Addr<DbExecutor>>,
+    metrics: Data<Metrics>,
+) -> Result<HttpResponse, ApiError> {
+    metrics.inc_req_count();
+    let since = Utc::now().naive_utc() - Duration::seconds(SEARCH_WINDOW_S);
+    match db.send(MetricNamesQuery(since)).await? {
+        Ok(names) => {
+            let mut targets: Vec<String> = Vec::new();
+            for name in names {
+                let msg_target = format!("msg={}", name.msg);
+                if !targets.contains(&msg_target) {
+                    targets.push(msg_target.clone());
+                }
+                if let Some(field) = name.field {
+                    if field.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                        targets.push(format!("{}&field={}&functions=mean", msg_target, field));
+                    }
+                }
+            }
+            targets.retain(|t| t.contains(&body.target));
+            Ok(HttpResponse::Ok().json(targets))
+        }
+        Err(e) => Err(ApiError::database("grafana search query", e)),
+    }
+}
+
+/// Name for the values of `function` in `series`, eg. `polkadot system.interval.peers mean`
+fn series_name(series: &AggregateSeries, aggregation: &Aggregation, function: &str) -> String {
+    let mut parts = Vec::new();
+    if let Some(group) = &series.group {
+        parts.push(group.to_owned());
+    }
+    match &aggregation.field {
+        Some(field) => parts.push(format!("{}.{}", aggregation.msg, field.join("."))),
+        None => parts.push(aggregation.msg.to_owned()),
+    }
+    parts.push(function.to_owned());
+    parts.join(" ")
+}
+
+fn time_series(aggregation: &Aggregation, series: Vec<AggregateSeries>) -> Vec<Value> {
+    let mut results = Vec::new();
+    for s in &series {
+        for function in &aggregation.functions {
+            let function = function.to_string();
+            let datapoints: Vec<Value> = s
+                .points
+                .iter()
+                .map(|p| json!([p.values[&function], p.time.timestamp_millis()]))
+                .collect();
+            results.push(json!({
+                "target": series_name(s, aggregation, &function),
+                "datapoints": datapoints,
+            }));
+        }
+    }
+    results
+}
+
+fn table(aggregation: &Aggregation, series: Vec<AggregateSeries>) -> Value {
+    let mut columns = vec![
+        json!({"text": "time", "type": "time"}),
+        json!({"text": "group", "type": "string"}),
+    ];
+    columns.extend(
+        aggregation
+            .functions
+            .iter()
+            .map(|f| json!({"text": f.to_string(), "type": "number"})),
+    );
+    let mut rows = Vec::new();
+    for s in series {
+        for p in &s.points {
+            let mut row = vec![json!(p.time.timestamp_millis()), json!(s.group)];
+            row.extend(
+                aggregation
+                    .functions
+                    .iter()
+                    .map(|f| p.values[&f.to_string()].clone()),
+            );
+            rows.push(Value::Array(row));
+        }
+    }
+    json!({"type": "table", "columns": columns, "rows": rows})
+}
+
+async fn query(
+    body: Json<QueryRequest>,
+    db: Data<Addr<DbExecutor>>,
+    metrics: Data<Metrics>,
+) -> Result<HttpResponse, ApiError> {
+    metrics.inc_req_count();
+    let default_bucket_s = body.interval_ms.map(|ms| (ms / 1000).max(1));
+    let mut results = Vec::new();
+    for target in body.targets.iter().filter(|t| !t.target.is_empty()) {
+        let filters = Filters {
+            start_time: Some(body.range.from.naive_utc()),
+            end_time: Some(body.range.to.naive_utc()),
+            ..parse_filters(&target.target)?
+        };
+        let query = aggregate_query(&target.target, filters, default_bucket_s)?;
+        let aggregation = query.aggregation().clone();
+        match db.send(query).await? {
+            Ok(series) => match target.kind.as_deref() {
+                Some("table") => results.push(table(&aggregation, series)),
+                _ => results.extend(time_series(&aggregation, series)),
+            },
+            Err(e) => return Err(ApiError::database("grafana query", e)),
+        }
+    }
+    Ok(HttpResponse::Ok().json(results))
+}
+
+/// Benchmark events and node connections, the annotation query may be `AnnotationParams` in
+/// querystring syntax
+async fn annotations(
+    body: Json<AnnotationRequest>,
+    db: Data<Addr<DbExecutor>>,
+    metrics: Data<Metrics>,
+) -> Result<HttpResponse, ApiError> {
+    metrics.inc_req_count();
+    let params = body.annotation["query"]
+        .as_str()
+        .map(actix_web::web::Query::<AnnotationParams>::from_query)
+        .transpose()
+        .map_err(ApiError::invalid_params)?
+        .map(|p| p.into_inner())
+        .unwrap_or_default();
+    let source = params.source.as_deref();
+    let query = AnnotationsQuery {
+        start_time: body.range.from.naive_utc(),
+        end_time: body.range.to.naive_utc(),
+        peer_id: params.peer_id,
+        benchmarks: source.is_none_or(|s| s == "benchmarks"),
+        connects: source.is_none_or(|s| s == "connects"),
+    };
+    match db.send(query).await? {
+        Ok(annotations) => {
+            let annotations: Vec<Value> = annotations
+                .into_iter()
+                .map(|a| {
+                    json!({
+                        "annotation": body.annotation,
+                        "time": a.time.timestamp_millis(),
+                        "title": a.title,
+                        "text": a.text,
+                        "tags": a.tags,
+                    })
+                })
+                .collect();
+            Ok(HttpResponse::Ok().json(annotations))
+        }
+        Err(e) => Err(ApiError::database("grafana annotations query", e)),
+    }
+}
@@ -0,0 +1,108 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate Analytics.
+
+// Substrate Analytics is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate Analytics is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
+
+use super::error::{path_param, ApiError};
+use super::metrics::Metrics;
+use super::openapi::ApiScope;
+use crate::db::{host_systems::*, models::NewHostSystem, DbExecutor};
+use actix::prelude::*;
+use actix_web::http::Method;
+use actix_web::{HttpRequest, HttpResponse};
+
+pub fn scope() -> ApiScope {
+    ApiScope::new("/host_systems/")
+        .route("/{host_system_id}/", Method::GET, one)
+        .route("/{host_system_id}/", Method::PUT, update)
+        .route("/{host_system_id}/", Method::DELETE, delete)
+        .route("", Method::GET, all)
+        .route("", Method::POST, new)
+}
+
+async fn all(
+    db: actix_web::web::Data<Addr<DbExecutor>>,
+    metrics: actix_web::web::Data<Metrics>,
+) -> Result<HttpResponse, ApiError> {
+    metrics.inc_req_count();
+    match db.send(HostSystemsQuery::All).await? {
+        Ok(r) => Ok(HttpResponse::Ok().json(r)),
+        Err(e) => Err(ApiError::database("host systems query", e)),
+    }
+}
+
+async fn one(
+    req: HttpRequest,
+    db: actix_web::web::Data<Addr<DbExecutor>>,
+    metrics: actix_web::web::Data<Metrics>,
+) -> Result<HttpResponse, ApiError> {
+    metrics.inc_req_count();
+    let host_system_id: i32 = path_param(&req, "host_system_id")?;
+    match db.send(HostSystemsQuery::One(host_system_id)).await? {
+        Ok(mut r) => match r.pop() {
+            Some(host_system) => Ok(HttpResponse::Ok().json(host_system)),
+            None => Err(ApiError::not_found("Host system not found")),
+        },
+        Err(e) => Err(ApiError::database("host systems query", e)),
+    }
+}
+
+async fn new(
+    item: actix_web::web::Json<NewHostSystem>,
+    db: actix_web::web::Data<Addr<DbExecutor>>,
+    metrics: actix_web::web::Data<Metrics>,
+) -> Result<HttpResponse, ApiError> {
+    metrics.inc_req_count();
+    let host_system = item.into_inner();
+    host_system.validate().map_err(ApiError::invalid_body)?;
+    match db.send(host_system).await? {
+        Ok(r) => Ok(HttpResponse::Ok().json(r)),
+        Err(e) => Err(ApiError::database("new host system insert", e)),
+    }
+}
+
+async fn update(
+    req: HttpRequest,
+    item: actix_web::web::Json<NewHostSystem>,
+    db: actix_web::web::Data<Addr<DbExecutor>>,
+    metrics: actix_web::web::Data<Metrics>,
+) -> Result<HttpResponse, ApiError> {
+    metrics.inc_req_count();
+    let id: i32 = path_param(&req, "host_system_id")?;
+    let host_system = item.into_inner();
+    host_system.validate().map_err(ApiError::invalid_body)?;
+    match db.send(UpdateHostSystem { id, host_system }).await? {
+        Ok(Some(r)) => Ok(HttpResponse::Ok().json(r)),
+        Ok(None) => Err(ApiError::not_found("Host system not found")),
+        Err(e) => Err(ApiError::database("host system update", e)),
+    }
+}
+
+async fn delete(
+    req: HttpRequest,
+    db: actix_web::web::Data<Addr<DbExecutor>>,
+    metrics: actix_web::web::Data<Metrics>,
+) -> Result<HttpResponse, ApiError> {
+    metrics.inc_req_count();
+    let host_system_id: i32 = path_param(&req, "host_system_id")?;
+    match db.send(DeleteHostSystem(host_system_id)).await? {
+        Ok(HostSystemDeletion::Deleted(r)) => Ok(HttpResponse::Ok().json(r)),
+        Ok(HostSystemDeletion::NotFound) => Err(ApiError::not_found("Host system not found")),
+        Ok(HostSystemDeletion::InUse(benchmarks)) => Err(ApiError::conflict(format!(
+            "Host system is used by benchmarks {:?}",
+            benchmarks
+        ))),
+        Err(e) => Err(ApiError::database("host system delete", e)),
+    }
+}
@@ -0,0 +1,370 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate Analytics.
+
+// Substrate Analytics is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate Analytics is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
+
+use super::client_ip::{client_addr, ClientAddr};
+use super::metrics::Metrics;
+use super::openapi::ApiScope;
+use super::root::{authenticate, unwrap_envelope, update_peer_info};
+use super::shutdown::ShutdownFlag;
+use crate::db::{
+    models::{NewPeerConnection, NewSubstrateLog, PeerConnection},
+    sessions::RecordDisconnect,
+    DbExecutor, FindPeerConnection, IngestOrigin,
+};
+use crate::{LogBuffer, NewSubstrateLogs, HTTP_INGEST_MAX_BYTES, INGEST_AUTH, TRUSTED_PROXIES};
+use actix::prelude::*;
+use actix_web::http::Method;
+use actix_web::{error, web::Bytes, Error, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+
+pub fn scope() -> ApiScope {
+    ApiScope::new("/ingest/")
+        .app_data(actix_web::web::PayloadConfig::new(*HTTP_INGEST_MAX_BYTES))
+        .route("/audit/", Method::POST, ingest_audit)
+        .route("", Method::POST, ingest)
+}
+
+/// Identity of the node the logs came from, given as query parameters. Only connections
+/// created by `/ingest/` from the same IP with the same token are appended to.
+#[derive(Deserialize, Debug, Default)]
+struct NodeIdentity {
+    /// Append to an existing `PeerConnection`
+    connection_id: Option<i32>,
+    /// Append to the most recent `PeerConnection` for this peer, or create one
+    peer_id: Option<String>,
+    name: Option<String>,
+    chain: Option<String>,
+    version: Option<String>,
+    implementation: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct Rejected {
+    line: usize,
+    reason: String,
+}
+
+#[derive(Serialize, Debug)]
+struct IngestResult {
+    peer_connection_id: i32,
+    accepted: usize,
+    rejected: Vec<Rejected>,
+}
+
+async fn ingest(
+    r: HttpRequest,
+    body: Bytes,
+    db: actix_web::web::Data<Addr<DbExecutor>>,
+    log_buffer: actix_web::web::Data<Addr<LogBuffer>>,
+    metrics: actix_web::web::Data<Metrics>,
+    shutdown: actix_web::web::Data<ShutdownFlag>,
+) -> Result<HttpResponse, Error> {
+    ingest_batch(r, body, db, log_buffer, metrics, shutdown, false).await
+}
+
+async fn ingest_audit(
+    r: HttpRequest,
+    body: Bytes,
+    db: actix_web::web::Data<Addr<DbExecutor>>,
+    log_buffer: actix_web::web::Data<Addr<LogBuffer>>,
+    metrics: actix_web::web::Data<Metrics>,
+    shutdown: actix_web::web::Data<ShutdownFlag>,
+) -> Result<HttpResponse, Error> {
+    ingest_batch(r, body, db, log_buffer, metrics, shutdown, true).await
+}
+
+async fn ingest_batch(
+    r: HttpRequest,
+    body: Bytes,
+    db: actix_web::web::Data<Addr<DbExecutor>>,
+    log_buffer: actix_web::web::Data<Addr<LogBuffer>>,
+    metrics: actix_web::web::Data<Metrics>,
+    shutdown: actix_web::web::Data<ShutdownFlag>,
+    audit: bool,
+) -> Result<HttpResponse, Error> {
+    metrics.inc_req_count();
+    // Held until the logs are in the `LogBuffer`, shutdown waits for it before the final save
+    let _request = shutdown
+        .start_request()
+        .ok_or_else(|| error::ErrorServiceUnavailable("Shutting down"))?;
+    let addr = client_addr(&r, &TRUSTED_PROXIES);
+    let ingest_token = if *INGEST_AUTH {
+        Some(authenticate(&r, &db, &addr.ip, audit).await?)
+    } else {
+        None
+    };
+    let identity = actix_web::web::Query::<NodeIdentity>::from_query(r.query_string())
+        .map_err(|e| error::ErrorBadRequest(e.to_string()))?
+        .into_inner();
+    metrics.inc_ws_bytes_received(body.len() as u64);
+    let (lines, mut rejected) = parse_body(&body);
+
+    let token_id = ingest_token.as_ref().map(|t| t.id);
+    let mut peer_connection = peer_connection_for(&db, &identity, &addr, token_id, audit).await?;
+    let _session = EndSession {
+        db: db.get_ref().clone(),
+        peer_connection_id: peer_connection.id,
+    };
+    let original = peer_connection.clone();
+    identity.apply(&mut peer_connection);
+    let token_chain = ingest_token.as_ref().and_then(|t| t.chain.as_ref());
+    let chain_allowed = |pc: &PeerConnection| match (token_chain, pc.chain.as_ref()) {
+        (Some(allowed), Some(chain)) => allowed == chain,
+        _ => true,
+    };
+    // Every chain reported in the batch is checked, not just the last one
+    let mut allowed = chain_allowed(&peer_connection);
+    for (_, log) in &lines {
+        if peer_connection.peer_id.is_none() || log["msg"].as_str() == Some("system.connected") {
+            update_peer_info(&mut peer_connection, log);
+            allowed &= chain_allowed(&peer_connection);
+        }
+    }
+    if !allowed {
+        warn!(
+            "Token not valid for chain: {:?}, rejecting batch from: {}",
+            peer_connection.chain, addr.ip
+        );
+        return Err(error::ErrorForbidden("Token not valid for this chain"));
+    }
+    if token_chain.is_some() && peer_connection.chain.is_none() {
+        return Err(error::ErrorForbidden(
+            "Token is restricted to a chain, which must be reported with `chain` or in a \
+             `system.connected` message before logs are accepted",
+        ));
+    }
+    if peer_connection != original {
+        db.send(peer_connection.clone())
+            .await?
+            .map_err(error::ErrorInternalServerError)?;
+    }
+
+    let mut accepted = Vec::with_capacity(lines.len());
+    for (line, logs) in lines {
+        let created_at = match logs["ts"].as_str().map(DateTime::parse_from_rfc3339) {
+            Some(Ok(ts)) => ts.naive_utc(),
+            Some(Err(e)) => {
+                rejected.push(Rejected {
+                    line,
+                    reason: format!("Unable to parse timestamp: {}", e),
+                });
+                continue;
+            }
+            None => {
+                rejected.push(Rejected {
+                    line,
+                    reason: "Missing `ts`".to_string(),
+                });
+                continue;
+            }
+        };
+        metrics.inc_ws_message_count();
+        accepted.push((
+            line,
+            NewSubstrateLog {
+                peer_connection_id: peer_connection.id,
+                created_at,
+                audit,
+                logs,
+            },
+        ));
+    }
+    let (accepted_lines, logs): (Vec<_>, Vec<_>) = accepted.into_iter().unzip();
+    let accepted = match log_buffer.send(NewSubstrateLogs(logs)).await {
+        Ok(Ok(())) => accepted_lines.len(),
+        res => {
+            error!("Failed to send NewSubstrateLogs to LogBuffer - {:?}", res);
+            rejected.extend(accepted_lines.into_iter().map(|line| Rejected {
+                line,
+                reason: "Log buffer unavailable".to_string(),
+            }));
+            0
+        }
+    };
+    rejected.sort_by_key(|r| r.line);
+    info!(
+        "Ingested batch from: {}, peer_connection_id: {}, accepted: {}, rejected: {}",
+        addr.ip,
+        peer_connection.id,
+        accepted,
+        rejected.len()
+    );
+    Ok(HttpResponse::Ok().json(IngestResult {
+        peer_connection_id: peer_connection.id,
+        accepted,
+        rejected,
+    }))
+}
+
+/// Records the end of the connection's session once the request is done with it, however the
+/// request ends, as there is no socket to close it. Later requests extend the session.
+struct EndSession {
+    db: Addr<DbExecutor>,
+    peer_connection_id: i32,
+}
+
+impl Drop for EndSession {
+    fn drop(&mut self) {
+        self.db.do_send(RecordDisconnect {
+            peer_connection_ids: vec![self.peer_connection_id],
+            disconnected_at: Utc::now().naive_utc(),
+            reason: "ingest request finished".to_string(),
+        });
+    }
+}
+
+impl NodeIdentity {
+    /// Update a connection with the identity given for this batch
+    fn apply(&self, pc: &mut PeerConnection) {
+        let fields = vec![
+            (&self.peer_id, &mut pc.peer_id),
+            (&self.name, &mut pc.name),
+            (&self.chain, &mut pc.chain),
+            (&self.version, &mut pc.version),
+            (&self.implementation, &mut pc.implementation),
+        ];
+        for (given, field) in fields {
+            if given.is_some() {
+                *field = given.clone();
+            }
+        }
+    }
+}
+
+async fn peer_connection_for(
+    db: &actix_web::web::Data<Addr<DbExecutor>>,
+    identity: &NodeIdentity,
+    addr: &ClientAddr,
+    token_id: Option<i32>,
+    audit: bool,
+) -> Result<PeerConnection, Error> {
+    if identity.connection_id.is_some() || identity.peer_id.is_some() {
+        let existing = db
+            .send(FindPeerConnection {
+                id: identity.connection_id,
+                peer_id: identity.peer_id.clone(),
+                audit,
+                ingest: Some(IngestOrigin {
+                    ip_addr: addr.ip.clone(),
+                    token_id,
+                }),
+            })
+            .await?
+            .map_err(error::ErrorInternalServerError)?;
+        match existing {
+            Some(pc) => return Ok(pc),
+            None if identity.connection_id.is_some() => {
+                return Err(error::ErrorNotFound("PeerConnection not found"));
+            }
+            None => (),
+        }
+    }
+    db.send(NewPeerConnection {
+        ip_addr: addr.ip.clone(),
+        peer_addr: addr.peer_addr.clone(),
+        peer_id: identity.peer_id.clone(),
+        name: identity.name.clone(),
+        chain: identity.chain.clone(),
+        version: identity.version.clone(),
+        authority: None,
+        startup_time: None,
+        implementation: identity.implementation.clone(),
+        http_ingest: true,
+        ingest_token_id: token_id,
+        audit,
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
+}
+
+/// Parse either a JSON array or NDJSON, returning messages with their (1 based) line or
+/// array position, and the entries that could not be parsed
+fn parse_body(body: &[u8]) -> (Vec<(usize, Value)>, Vec<Rejected>) {
+    let mut lines = Vec::new();
+    let mut rejected = Vec::new();
+    let is_array = body
+        .iter()
+        .find(|b| !b.is_ascii_whitespace())
+        .is_some_and(|b| *b == b'[');
+    if is_array {
+        match serde_json::from_slice::<Vec<Value>>(body) {
+            Ok(values) => {
+                for (i, value) in values.into_iter().enumerate() {
+                    match log_message(i + 1, value) {
+                        Ok(log) => lines.push(log),
+                        Err(r) => rejected.push(r),
+                    }
+                }
+            }
+            Err(e) => rejected.push(Rejected {
+                line: e.line(),
+                reason: e.to_string(),
+            }),
+        }
+    } else {
+        for (i, line) in body.split(|b| *b == b'\n').enumerate() {
+            if line.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+            match serde_json::from_slice::<Value>(line)
+                .map_err(|e| Rejected {
+                    line: i + 1,
+                    reason: e.to_string(),
+                })
+                .and_then(|value| log_message(i + 1, value))
+            {
+                Ok(log) => lines.push(log),
+                Err(r) => rejected.push(r),
+            }
+        }
+    }
+    (lines, rejected)
+}
+
+fn log_message(line: usize, value: Value) -> Result<(usize, Value), Rejected> {
+    match unwrap_envelope(value) {
+        (_, log) if log.is_object() => Ok((line, log)),
+        _ => Err(Rejected {
+            line,
+            reason: "Not a JSON object".to_string(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ndjson_and_arrays_test() {
+        let (lines, rejected) = parse_body(b"{\"msg\":\"a\"}\n\nnot json\n[1]\n{\"msg\":\"b\"}\n");
+        assert_eq!(
+            lines.iter().map(|(l, _)| *l).collect::<Vec<_>>(),
+            vec![1, 5]
+        );
+        assert_eq!(
+            rejected.iter().map(|r| r.line).collect::<Vec<_>>(),
+            vec![3, 4]
+        );
+
+        let (lines, rejected) =
+            parse_body(b" [{\"msg\":\"a\"}, 2, {\"id\":1,\"payload\":{\"msg\":\"b\"}}]");
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1].1, json!({"msg": "b"}));
+        assert_eq!(rejected[0].line, 2);
+    }
+}
@@ -14,10 +14,16 @@
 // You should have received a copy of the GNU General Public License
 // along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
 
+use super::openapi::ApiScope;
+use super::rate_limit::Verdict;
+use actix_web::http::Method;
 use actix_web::{http::StatusCode, HttpRequest, HttpResponse, Result as AWResult};
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
 use std::fmt;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use sysinfo::{NetworkExt, NetworksExt, System, SystemExt};
 
 #[derive(Clone, Default)]
@@ -26,12 +32,35 @@ pub struct Metrics {
     ws_connected_count: Arc<AtomicU64>,
     ws_dropped_count: Arc<AtomicU64>,
     ws_bytes_received: Arc<AtomicU64>,
+    ws_bytes_decoded: Arc<AtomicU64>,
     req_count: Arc<AtomicU64>,
     feeds_connected: Arc<AtomicU64>,
     feeds_disconnected: Arc<AtomicU64>,
+    db_batch_count: Arc<AtomicU64>,
+    db_rows_written: Arc<AtomicU64>,
+    db_rows_rejected: Arc<AtomicU64>,
+    db_batch_time_ms: Arc<AtomicU64>,
+    spool_segments: Arc<AtomicU64>,
+    spool_bytes: Arc<AtomicU64>,
+    spool_rows_written: Arc<AtomicU64>,
+    spool_rows_replayed: Arc<AtomicU64>,
+    spool_segments_dropped: Arc<AtomicU64>,
+    rate_limited_dropped: Arc<AtomicU64>,
+    rate_limited_sampled: Arc<AtomicU64>,
+    rate_limited_disconnected: Arc<AtomicU64>,
+    /// Counts of IPs with node connections open, dropped once the IP's last connection closes
+    rate_limited_ips: Arc<Mutex<BTreeMap<String, RateLimitedCounts>>>,
     system: Arc<System>,
 }
 
+/// Frames dropped or sampled, and connections closed, for exceeding rate limits
+#[derive(Clone, Copy, Default)]
+struct RateLimitedCounts {
+    dropped: u64,
+    sampled: u64,
+    disconnected: u64,
+}
+
 impl Metrics {
     pub fn inc_ws_message_count(&self) {
         self.ws_message_count.fetch_add(1, Ordering::Relaxed);
@@ -45,6 +74,9 @@ impl Metrics {
     pub fn inc_ws_bytes_received(&self, n: u64) {
         self.ws_bytes_received.fetch_add(n, Ordering::Relaxed);
     }
+    pub fn inc_ws_bytes_decoded(&self, n: u64) {
+        self.ws_bytes_decoded.fetch_add(n, Ordering::Relaxed);
+    }
     pub fn inc_req_count(&self) {
         self.req_count.fetch_add(1, Ordering::Relaxed);
     }
@@ -54,6 +86,43 @@ impl Metrics {
     pub fn dec_concurrent_feed_count(&self) {
         self.feeds_disconnected.fetch_add(1, Ordering::Relaxed);
     }
+    pub fn record_log_batch(&self, written: u64, rejected: u64, duration: Duration) {
+        self.db_batch_count.fetch_add(1, Ordering::Relaxed);
+        self.db_rows_written.fetch_add(written, Ordering::Relaxed);
+        self.db_rows_rejected.fetch_add(rejected, Ordering::Relaxed);
+        self.db_batch_time_ms
+            .fetch_add(duration.as_millis() as u64, Ordering::Relaxed);
+    }
+    pub fn set_spool_depth(&self, segments: u64, bytes: u64) {
+        self.spool_segments.store(segments, Ordering::Relaxed);
+        self.spool_bytes.store(bytes, Ordering::Relaxed);
+    }
+    pub fn inc_spool_rows_written(&self, n: u64) {
+        self.spool_rows_written.fetch_add(n, Ordering::Relaxed);
+    }
+    pub fn inc_spool_rows_replayed(&self, n: u64) {
+        self.spool_rows_replayed.fetch_add(n, Ordering::Relaxed);
+    }
+    pub fn inc_spool_segments_dropped(&self, n: u64) {
+        self.spool_segments_dropped.fetch_add(n, Ordering::Relaxed);
+    }
+    pub fn inc_rate_limited(&self, ip: &str, verdict: Verdict) {
+        let mut ips = self.rate_limited_ips.lock();
+        let counts = ips.entry(ip.to_string()).or_default();
+        let (counter, count) = match verdict {
+            Verdict::Drop => (&self.rate_limited_dropped, &mut counts.dropped),
+            Verdict::Sample => (&self.rate_limited_sampled, &mut counts.sampled),
+            Verdict::Disconnect => (&self.rate_limited_disconnected, &mut counts.disconnected),
+            Verdict::Accept => return,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        *count += 1;
+    }
+    /// Stop reporting `ip` once it has no node connections left, so the counts kept per IP are
+    /// limited by the number of connections
+    pub fn remove_rate_limited_ip(&self, ip: &str) {
+        self.rate_limited_ips.lock().remove(ip);
+    }
 
     fn bytes_io(&self, sys: &System) -> (u64, u64) {
         let mut total_sent = 0;
@@ -87,10 +156,15 @@ const CURRENT_SUBSTRATE_CONNECTIONS_TEMPLATE: &str =
      current_substrate_connections ";
 
 const SUBSTRATE_BYTES_RECEIVED_TEMPLATE: &str =
-    "# HELP substrate_bytes_received Total bytes received in binary and text WS messages from substrate clients.\n\
+    "# HELP substrate_bytes_received Total bytes received in binary and text WS messages from substrate clients, before decompression.\n\
      # TYPE substrate_bytes_received counter\n\
      substrate_bytes_received ";
 
+const SUBSTRATE_BYTES_DECODED_TEMPLATE: &str =
+    "# HELP substrate_bytes_decoded Total bytes of WS messages from substrate clients after decompression.\n\
+     # TYPE substrate_bytes_decoded counter\n\
+     substrate_bytes_decoded ";
+
 const BYTES_RECEIVED_TEMPLATE: &str =
     "# HELP bytes_received Total bytes received in binary and text WS messages.\n\
      # TYPE bytes_received counter\n\
@@ -121,6 +195,58 @@ const FEEDS_DROPPED_TEMPLATE: &str =
      # TYPE feeds_disconnected counter\n\
      feeds_disconnected ";
 
+const DB_BATCH_COUNT_TEMPLATE: &str =
+    "# HELP db_batch_count Number of log batches written to the DB.\n\
+     # TYPE db_batch_count counter\n\
+     db_batch_count ";
+
+const DB_ROWS_WRITTEN_TEMPLATE: &str =
+    "# HELP db_rows_written Number of log rows written to the DB.\n\
+     # TYPE db_rows_written counter\n\
+     db_rows_written ";
+
+const DB_ROWS_REJECTED_TEMPLATE: &str =
+    "# HELP db_rows_rejected Number of log rows that could not be written to the DB.\n\
+     # TYPE db_rows_rejected counter\n\
+     db_rows_rejected ";
+
+const DB_BATCH_TIME_TEMPLATE: &str =
+    "# HELP db_batch_time_ms Total time (ms) spent writing log batches to the DB.\n\
+     # TYPE db_batch_time_ms counter\n\
+     db_batch_time_ms ";
+
+const SPOOL_SEGMENTS_TEMPLATE: &str =
+    "# HELP spool_segments Number of segment files in the spool.\n\
+     # TYPE spool_segments gauge\n\
+     spool_segments ";
+
+const SPOOL_BYTES_TEMPLATE: &str = "# HELP spool_bytes Size of the spool on disk.\n\
+     # TYPE spool_bytes gauge\n\
+     spool_bytes ";
+
+const SPOOL_ROWS_WRITTEN_TEMPLATE: &str =
+    "# HELP spool_rows_written Number of log rows spooled while the DB was unavailable.\n\
+     # TYPE spool_rows_written counter\n\
+     spool_rows_written ";
+
+const SPOOL_ROWS_REPLAYED_TEMPLATE: &str =
+    "# HELP spool_rows_replayed Number of spooled log rows replayed to the DB.\n\
+     # TYPE spool_rows_replayed counter\n\
+     spool_rows_replayed ";
+
+const SPOOL_SEGMENTS_DROPPED_TEMPLATE: &str =
+    "# HELP spool_segments_dropped Number of spool segments dropped for exceeding size or age limits.\n\
+     # TYPE spool_segments_dropped counter\n\
+     spool_segments_dropped ";
+
+const RATE_LIMITED_TEMPLATE: &str =
+    "# HELP rate_limited Number of frames dropped or sampled, and connections closed, for exceeding rate limits.\n\
+     # TYPE rate_limited counter\n";
+
+const RATE_LIMITED_IP_TEMPLATE: &str =
+    "# HELP rate_limited_ip Number of frames dropped or sampled, and connections closed, for exceeding rate limits, per IP with node connections open.\n\
+     # TYPE rate_limited_ip counter\n";
+
 const LOAD_AVG_ONE_TEMPLATE: &str = "# HELP load_avg_one System load average one minute\n\
      # TYPE load_avg_one gauge\n\
      load_avg_one ";
@@ -174,6 +300,16 @@ impl fmt::Display for Metrics {
              {}{}\n\
              {}{}\n\
              {}{}\n\
+             {}{}\n\
+             {}{}\n\
+             {}{}\n\
+             {}{}\n\
+             {}{}\n\
+             {}{}\n\
+             {}{}\n\
+             {}{}\n\
+             {}{}\n\
+             {}{}\n\
              {}{}\n",
             WS_MESSAGE_COUNT_TEMPLATE,
             self.ws_message_count.load(Ordering::Relaxed),
@@ -186,6 +322,8 @@ impl fmt::Display for Metrics {
                 - self.ws_dropped_count.load(Ordering::Relaxed),
             SUBSTRATE_BYTES_RECEIVED_TEMPLATE,
             self.ws_bytes_received.load(Ordering::Relaxed),
+            SUBSTRATE_BYTES_DECODED_TEMPLATE,
+            self.ws_bytes_decoded.load(Ordering::Relaxed),
             BYTES_RECEIVED_TEMPLATE,
             total_rec,
             BYTES_SENT_TEMPLATE,
@@ -199,6 +337,24 @@ impl fmt::Display for Metrics {
             self.feeds_connected.load(Ordering::Relaxed),
             FEEDS_DROPPED_TEMPLATE,
             self.feeds_disconnected.load(Ordering::Relaxed),
+            DB_BATCH_COUNT_TEMPLATE,
+            self.db_batch_count.load(Ordering::Relaxed),
+            DB_ROWS_WRITTEN_TEMPLATE,
+            self.db_rows_written.load(Ordering::Relaxed),
+            DB_ROWS_REJECTED_TEMPLATE,
+            self.db_rows_rejected.load(Ordering::Relaxed),
+            DB_BATCH_TIME_TEMPLATE,
+            self.db_batch_time_ms.load(Ordering::Relaxed),
+            SPOOL_SEGMENTS_TEMPLATE,
+            self.spool_segments.load(Ordering::Relaxed),
+            SPOOL_BYTES_TEMPLATE,
+            self.spool_bytes.load(Ordering::Relaxed),
+            SPOOL_ROWS_WRITTEN_TEMPLATE,
+            self.spool_rows_written.load(Ordering::Relaxed),
+            SPOOL_ROWS_REPLAYED_TEMPLATE,
+            self.spool_rows_replayed.load(Ordering::Relaxed),
+            SPOOL_SEGMENTS_DROPPED_TEMPLATE,
+            self.spool_segments_dropped.load(Ordering::Relaxed),
             LOAD_AVG_ONE_TEMPLATE,
             load_avg.one,
             LOAD_AVG_FIVE_TEMPLATE,
@@ -213,12 +369,40 @@ impl fmt::Display for Metrics {
             sys.get_total_swap(),
             USED_SWAP_TEMPLATE,
             sys.get_used_swap(),
-        )
+        )?;
+        write!(f, "{}", RATE_LIMITED_TEMPLATE)?;
+        for (action, counter) in &[
+            ("dropped", &self.rate_limited_dropped),
+            ("sampled", &self.rate_limited_sampled),
+            ("disconnected", &self.rate_limited_disconnected),
+        ] {
+            writeln!(
+                f,
+                "rate_limited{{action=\"{}\"}} {}",
+                action,
+                counter.load(Ordering::Relaxed)
+            )?;
+        }
+        write!(f, "{}", RATE_LIMITED_IP_TEMPLATE)?;
+        for (ip, counts) in self.rate_limited_ips.lock().iter() {
+            for (action, count) in &[
+                ("dropped", counts.dropped),
+                ("sampled", counts.sampled),
+                ("disconnected", counts.disconnected),
+            ] {
+                writeln!(
+                    f,
+                    "rate_limited_ip{{ip=\"{}\",action=\"{}\"}} {}",
+                    ip, action, count
+                )?;
+            }
+        }
+        Ok(())
     }
 }
 
-pub fn configure(cfg: &mut actix_web::web::ServiceConfig) {
-    cfg.service(actix_web::web::scope("/metrics/").route("", actix_web::web::get().to(root)));
+pub fn scope() -> ApiScope {
+    ApiScope::new("/metrics/").route("", Method::GET, root)
 }
 
 async fn root(
@@ -14,23 +14,74 @@
 // You should have received a copy of the GNU General Public License
 // along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
 
+pub mod aggregate;
 pub mod benchmarks;
+pub mod chains;
+pub mod client_ip;
 pub mod dashboard;
+pub mod encoding;
+pub mod error;
+pub mod export;
 pub mod feed;
+pub mod grafana;
+pub mod host_systems;
+pub mod ingest;
 pub mod metrics;
 pub mod nodes;
+pub mod openapi;
+pub mod rate_limit;
 pub mod reputation;
 pub mod root;
+pub mod shutdown;
 pub mod stats;
+pub mod tokens;
 
 use crate::db::filters::Filters;
+use crate::web::error::ApiError;
+use crate::web::openapi::ApiScope;
 
-pub fn get_filters(req: &actix_web::HttpRequest) -> Filters {
-    match actix_web::web::Query::<Filters>::from_query(&req.query_string()) {
-        Ok(f) => f.clone(),
-        Err(_) => {
-            warn!("Error deserializing Filters from querystring");
-            Filters::default()
-        }
+/// Scopes of the JSON API, which `openapi` documents
+pub fn api_scopes() -> Vec<ApiScope> {
+    vec![
+        nodes::scope(),
+        reputation::scope(),
+        chains::scope(),
+        stats::scope(),
+        metrics::scope(),
+        benchmarks::scope(),
+        host_systems::scope(),
+        tokens::scope(),
+        ingest::scope(),
+        export::scope(),
+        aggregate::scope(),
+        grafana::scope(),
+    ]
+}
+
+pub fn configure_api(cfg: &mut actix_web::web::ServiceConfig) {
+    for scope in api_scopes() {
+        scope.configure(cfg);
     }
 }
+
+pub fn get_filters(req: &actix_web::HttpRequest) -> Result<Filters, ApiError> {
+    parse_filters(req.query_string())
+}
+
+/// `Filters` from a querystring, checked with `Filters::validate`
+pub fn parse_filters(query: &str) -> Result<Filters, ApiError> {
+    let filters = actix_web::web::Query::<Filters>::from_query(query)
+        .map_err(ApiError::invalid_filters)?
+        .into_inner();
+    filters.validate().map_err(ApiError::invalid_filters)?;
+    Ok(filters)
+}
+
+/// Token from an `Authorization: Bearer <token>` header
+pub fn bearer_token(req: &actix_web::HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(actix_web::http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim)
+}
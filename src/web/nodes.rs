@@ -14,39 +14,41 @@
 // You should have received a copy of the GNU General Public License
 // along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
 
+use super::error::{path_param, ApiError};
 use super::get_filters;
 use super::metrics::Metrics;
+use super::openapi::ApiScope;
+use crate::db::filters::max_age_start;
 use crate::db::nodes::{LogsQuery, NodesQuery, StatsQuery};
+use crate::db::sessions::SessionHistoryQuery;
 use crate::db::*;
 use actix::prelude::*;
+use actix_web::http::Method;
 use actix_web::{HttpRequest, HttpResponse};
+use chrono::Utc;
 
-pub fn configure(cfg: &mut actix_web::web::ServiceConfig) {
-    cfg.service(
-        actix_web::web::scope("/nodes/")
-            //            .route(
-            //                "/{peer_id}/peer_counts",
-            //                actix_web::web::get().to(peer_counts),
-            //            )
-            .route("/logs/", actix_web::web::get().to(logs))
-            .route("/log_stats/", actix_web::web::get().to(log_stats))
-            .route("", actix_web::web::get().to(all_nodes)),
-    );
+/// Window covered by the session history when no `start_time` or `max_age_s` is given
+const DEFAULT_SESSION_WINDOW_S: i64 = 86_400;
+
+pub fn scope() -> ApiScope {
+    ApiScope::new("/nodes/")
+        //        .route("/{peer_id}/peer_counts", Method::GET, peer_counts)
+        .route("/logs/", Method::GET, logs)
+        .route("/log_stats/", Method::GET, log_stats)
+        .route("/{peer_id}/sessions/", Method::GET, sessions)
+        .route("", Method::GET, all_nodes)
 }
 
 async fn all_nodes(
     req: HttpRequest,
     db: actix_web::web::Data<Addr<DbExecutor>>,
     metrics: actix_web::web::Data<Metrics>,
-) -> Result<HttpResponse, actix_web::Error> {
+) -> Result<HttpResponse, ApiError> {
     metrics.inc_req_count();
-    let filters = get_filters(&req);
+    let filters = get_filters(&req)?;
     match db.send(NodesQuery(filters)).await? {
         Ok(r) => Ok(HttpResponse::Ok().json(json!(r))),
-        Err(e) => {
-            error!("Could not complete all_nodes query: {}", e);
-            Ok(HttpResponse::InternalServerError().json(json!("Error while processing query")))
-        }
+        Err(e) => Err(ApiError::database("all_nodes query", e)),
     }
 }
 
@@ -54,15 +56,42 @@ async fn log_stats(
     req: HttpRequest,
     db: actix_web::web::Data<Addr<DbExecutor>>,
     metrics: actix_web::web::Data<Metrics>,
-) -> Result<HttpResponse, actix_web::Error> {
+) -> Result<HttpResponse, ApiError> {
     metrics.inc_req_count();
-    let filters = get_filters(&req);
+    let filters = get_filters(&req)?;
     match db.send(StatsQuery(filters)).await? {
         Ok(r) => Ok(HttpResponse::Ok().json(json!(r))),
-        Err(e) => {
-            error!("Could not complete log_stats query: {}", e);
-            Ok(HttpResponse::InternalServerError().json(json!("Error while processing query")))
-        }
+        Err(e) => Err(ApiError::database("log_stats query", e)),
+    }
+}
+
+async fn sessions(
+    req: HttpRequest,
+    db: actix_web::web::Data<Addr<DbExecutor>>,
+    metrics: actix_web::web::Data<Metrics>,
+) -> Result<HttpResponse, ApiError> {
+    metrics.inc_req_count();
+    let peer_id: String = path_param(&req, "peer_id")?;
+    let filters = get_filters(&req)?;
+    let now = Utc::now().naive_utc();
+    let end_time = filters.end_time.unwrap_or(now);
+    let start_time = match filters.start_time {
+        Some(start_time) => start_time,
+        None => max_age_start(
+            end_time,
+            filters.max_age_s.unwrap_or(DEFAULT_SESSION_WINDOW_S),
+        )
+        .map_err(ApiError::invalid_filters)?,
+    };
+    let query = SessionHistoryQuery {
+        peer_id,
+        start_time,
+        end_time,
+        now,
+    };
+    match db.send(query).await? {
+        Ok(r) => Ok(HttpResponse::Ok().json(json!(r))),
+        Err(e) => Err(ApiError::database("sessions query", e)),
     }
 }
 
@@ -86,14 +115,20 @@ async fn logs(
     req: HttpRequest,
     db: actix_web::web::Data<Addr<DbExecutor>>,
     metrics: actix_web::web::Data<Metrics>,
-) -> Result<HttpResponse, actix_web::Error> {
+) -> Result<HttpResponse, ApiError> {
     metrics.inc_req_count();
-    let filters = get_filters(&req);
+    let filters = get_filters(&req)?;
     match db.send(LogsQuery(filters)).await? {
-        Ok(r) => Ok(HttpResponse::Ok().json(json!(r))),
-        Err(e) => {
-            error!("Could not complete logs query: {}", e);
-            Ok(HttpResponse::InternalServerError().json(json!("Error while processing query")))
+        Ok(page) => {
+            let mut res = HttpResponse::Ok();
+            if let Some(next) = page.next {
+                res.header("X-Next-Cursor", next.to_string());
+            }
+            if let Some(prev) = page.prev {
+                res.header("X-Prev-Cursor", prev.to_string());
+            }
+            Ok(res.json(json!(page.logs)))
         }
+        Err(e) => Err(ApiError::database("logs query", e)),
     }
 }
@@ -0,0 +1,931 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate Analytics.
+
+// Substrate Analytics is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate Analytics is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
+
+use super::api_scopes;
+use super::error::{ApiError, ErrorCode};
+use super::metrics::Metrics;
+use actix_web::dev::Factory;
+use actix_web::http::Method;
+use actix_web::{FromRequest, HttpResponse, Responder};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::future::Future;
+
+lazy_static! {
+    static ref DOCUMENT: Value = document();
+}
+
+pub fn configure(cfg: &mut actix_web::web::ServiceConfig) {
+    cfg.service(actix_web::web::scope("/openapi/").route("", actix_web::web::get().to(openapi)));
+}
+
+async fn openapi(metrics: actix_web::web::Data<Metrics>) -> Result<HttpResponse, ApiError> {
+    metrics.inc_req_count();
+    Ok(HttpResponse::Ok().json(&*DOCUMENT))
+}
+
+/// A scope of the API whose routes are registered and listed in the document from the same
+/// definitions, so that the document can't leave any out
+pub struct ApiScope {
+    path: &'static str,
+    scope: actix_web::Scope,
+    /// Method and full path of each route
+    routes: Vec<(Method, String)>,
+}
+
+impl ApiScope {
+    pub fn new(path: &'static str) -> Self {
+        ApiScope {
+            path,
+            scope: actix_web::web::scope(path),
+            routes: Vec::new(),
+        }
+    }
+
+    pub fn app_data<U: 'static>(mut self, data: U) -> Self {
+        self.scope = self.scope.app_data(data);
+        self
+    }
+
+    pub fn route<F, T, R, U>(mut self, path: &str, method: Method, handler: F) -> Self
+    where
+        F: Factory<T, R, U>,
+        T: FromRequest + 'static,
+        R: Future<Output = U> + 'static,
+        U: Responder + 'static,
+    {
+        self.scope = self
+            .scope
+            .route(path, actix_web::web::method(method.clone()).to(handler));
+        self.routes
+            .push((method, format!("{}{}", self.path, path).replace("//", "/")));
+        self
+    }
+
+    pub fn configure(self, cfg: &mut actix_web::web::ServiceConfig) {
+        cfg.service(self.scope);
+    }
+}
+
+/// Querystring or path parameter of an operation
+struct Param {
+    name: &'static str,
+    path: bool,
+    required: bool,
+    schema: Value,
+    description: &'static str,
+}
+
+fn query(name: &'static str, schema: Value, description: &'static str) -> Param {
+    Param {
+        name,
+        path: false,
+        required: false,
+        schema,
+        description,
+    }
+}
+
+fn path(name: &'static str, schema: Value) -> Param {
+    Param {
+        name,
+        path: true,
+        required: true,
+        schema,
+        description: "",
+    }
+}
+
+fn string() -> Value {
+    json!({"type": "string"})
+}
+
+fn integer() -> Value {
+    json!({"type": "integer"})
+}
+
+fn date_time() -> Value {
+    json!({"type": "string", "format": "date-time", "example": "2020-10-01T00:00:00"})
+}
+
+fn schema_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+fn array_of(items: Value) -> Value {
+    json!({"type": "array", "items": items})
+}
+
+/// Parameters deserialized into `Filters`
+fn filter_params() -> Vec<Param> {
+    vec![
+        query("peer_id", string(), "Only include logs from this node"),
+        query("msg", string(), "Type of log message, eg. `block.import`"),
+        query("target", string(), "Origin of the log message"),
+        query(
+            "start_time",
+            date_time(),
+            "Include entries more recent than this",
+        ),
+        query(
+            "end_time",
+            date_time(),
+            "Include entries less recent than this",
+        ),
+        query(
+            "max_age_s",
+            integer(),
+            "Include entries at most this many seconds old",
+        ),
+        query(
+            "limit",
+            json!({"type": "integer", "minimum": 1}),
+            "Maximum number of results",
+        ),
+        query(
+            "before",
+            string(),
+            "Cursor from `X-Next-Cursor`, for older results",
+        ),
+        query(
+            "after",
+            string(),
+            "Cursor from `X-Prev-Cursor`, for newer results",
+        ),
+        query(
+            "where",
+            string(),
+            "`;` separated predicates on fields of the log message, eg. `height>=10;state.peerId?`",
+        ),
+    ]
+}
+
+/// Parameters deserialized into `AggregateParams`
+fn aggregate_params() -> Vec<Param> {
+    let mut params = vec![
+        Param {
+            required: true,
+            ..query("msg", string(), "Type of log message to aggregate")
+        },
+        query("field", string(), "`.` separated path of a numeric field"),
+        query("bucket_s", integer(), "Width of each time bucket in seconds"),
+        query(
+            "group_by",
+            json!({"type": "string", "enum": ["peer_id", "chain", "version", "name"]}),
+            "Return a series for each value of this node property",
+        ),
+        query(
+            "functions",
+            string(),
+            "Comma separated `count`, `sum`, `mean`, `min`, `max`, `stddev` or percentiles, eg. `p95`",
+        ),
+    ];
+    params.extend(
+        filter_params().into_iter().filter(|p| {
+            ["peer_id", "start_time", "end_time", "max_age_s", "where"].contains(&p.name)
+        }),
+    );
+    params
+}
+
+fn operation(summary: &str, params: Vec<Param>, body: Option<Value>, response: Value) -> Value {
+    let parameters: Vec<Value> = params
+        .into_iter()
+        .map(|p| {
+            let mut param = json!({
+                "name": p.name,
+                "in": if p.path { "path" } else { "query" },
+                "required": p.required,
+                "schema": p.schema,
+            });
+            if !p.description.is_empty() {
+                param["description"] = json!(p.description);
+            }
+            param
+        })
+        .collect();
+    let error = json!({"content": {"application/json": {"schema": schema_ref("Error")}}});
+    let mut op = json!({
+        "summary": summary,
+        "parameters": parameters,
+        "responses": {
+            "200": {"description": "OK", "content": {"application/json": {"schema": response}}},
+            "400": {"description": "Invalid request", "content": error["content"]},
+            "500": {"description": "Server error", "content": error["content"]},
+        },
+    });
+    if let Some(body) = body {
+        op["requestBody"] = json!({
+            "required": true,
+            "content": {"application/json": {"schema": body}},
+        });
+    }
+    op
+}
+
+/// Replace the JSON response of `op` with one of `content_type`
+fn text_response(mut op: Value, content_type: &str) -> Value {
+    op["responses"]["200"]["content"] = json!({ content_type: {"schema": string()} });
+    op
+}
+
+/// Parameters deserialized into `NodeIdentity`
+fn ingest_params() -> Vec<Param> {
+    vec![
+        query(
+            "connection_id",
+            integer(),
+            "Append to this existing peer connection",
+        ),
+        query(
+            "peer_id",
+            string(),
+            "Append to the most recent peer connection for this node, or create one",
+        ),
+        query("name", string(), "Name of the node"),
+        query("chain", string(), "Chain the node is on"),
+        query("version", string(), "Version of the node"),
+        query("implementation", string(), "Implementation of the node"),
+    ]
+}
+
+fn ingest_operation(summary: &str) -> Value {
+    let mut op = operation(
+        summary,
+        ingest_params(),
+        None,
+        json!({
+            "type": "object",
+            "properties": {
+                "peer_connection_id": integer(),
+                "accepted": integer(),
+                "rejected": array_of(json!({
+                    "type": "object",
+                    "properties": {"line": integer(), "reason": string()},
+                })),
+            },
+        }),
+    );
+    op["requestBody"] = json!({
+        "required": true,
+        "description": "Newline delimited JSON log messages",
+        "content": {"application/x-ndjson": {"schema": string()}},
+    });
+    op
+}
+
+/// Operation of each route of `api_scopes`, by path and method
+fn paths() -> Vec<(&'static str, &'static str, Value)> {
+    let object = json!({"type": "object"});
+    vec![
+        (
+            "/nodes/",
+            "get",
+            operation(
+                "Logged nodes",
+                filter_params(),
+                None,
+                array_of(object.clone()),
+            ),
+        ),
+        (
+            "/nodes/logs/",
+            "get",
+            operation(
+                "Log messages of a node, newest first",
+                filter_params(),
+                None,
+                array_of(schema_ref("Log")),
+            ),
+        ),
+        (
+            "/nodes/log_stats/",
+            "get",
+            operation(
+                "Quantity of each type of log message",
+                filter_params(),
+                None,
+                array_of(json!({
+                    "type": "object",
+                    "properties": {"qty": integer(), "log_type": string()},
+                })),
+            ),
+        ),
+        (
+            "/nodes/{peer_id}/sessions/",
+            "get",
+            operation(
+                "Connection sessions, uptime and reconnects of a node",
+                vec![
+                    path("peer_id", string()),
+                    query("start_time", date_time(), "Start of the window"),
+                    query(
+                        "end_time",
+                        date_time(),
+                        "End of the window, defaults to now",
+                    ),
+                    query(
+                        "max_age_s",
+                        integer(),
+                        "Length of the window when no start_time is given, defaults to 86400",
+                    ),
+                ],
+                None,
+                object.clone(),
+            ),
+        ),
+        (
+            "/chains/",
+            "get",
+            operation(
+                "Connected nodes of each chain by version, implementation and role",
+                vec![query("chain", string(), "Only include this chain")],
+                None,
+                array_of(object.clone()),
+            ),
+        ),
+        (
+            "/chains/history/",
+            "get",
+            operation(
+                "Connected nodes of each chain by version, sampled over time",
+                vec![
+                    query("chain", string(), "Only include this chain"),
+                    query("start_time", date_time(), "Start of the history"),
+                    query(
+                        "end_time",
+                        date_time(),
+                        "End of the history, defaults to now",
+                    ),
+                    query(
+                        "max_age_s",
+                        integer(),
+                        "Length of the history when no start_time is given, defaults to 86400",
+                    ),
+                    query(
+                        "bucket_s",
+                        integer(),
+                        "Seconds between samples, defaults to 3600",
+                    ),
+                ],
+                None,
+                array_of(object.clone()),
+            ),
+        ),
+        (
+            "/reputation/",
+            "get",
+            operation(
+                "Reputation of all peers",
+                filter_params(),
+                None,
+                array_of(object.clone()),
+            ),
+        ),
+        (
+            "/reputation/logged/",
+            "get",
+            operation(
+                "Reputation of logged nodes",
+                filter_params(),
+                None,
+                array_of(object.clone()),
+            ),
+        ),
+        (
+            "/reputation/{peer_id}/",
+            "get",
+            operation(
+                "Reputation of a peer from the POV of other nodes",
+                {
+                    let mut params = filter_params();
+                    params.push(path("peer_id", string()));
+                    params
+                },
+                None,
+                array_of(object.clone()),
+            ),
+        ),
+        (
+            "/reputation/mock/{qty}/",
+            "get",
+            operation(
+                "Mock reputation data",
+                vec![path("qty", integer())],
+                None,
+                array_of(object.clone()),
+            ),
+        ),
+        (
+            "/benchmarks/",
+            "get",
+            operation(
+                "Benchmarks, newest first",
+                vec![],
+                None,
+                array_of(schema_ref("Benchmark")),
+            ),
+        ),
+        (
+            "/benchmarks/",
+            "post",
+            operation(
+                "Create a benchmark",
+                vec![],
+                Some(json!({"type": "object", "properties": {"setup": object}})),
+                schema_ref("Benchmark"),
+            ),
+        ),
+        (
+            "/benchmarks/events/",
+            "post",
+            operation(
+                "Record a benchmark event",
+                vec![],
+                Some(schema_ref("NewBenchmarkEvent")),
+                object.clone(),
+            ),
+        ),
+        (
+            "/benchmarks/example/",
+            "get",
+            operation("Empty response", vec![], None, object.clone()),
+        ),
+        (
+            "/benchmarks/compare/",
+            "get",
+            operation(
+                "Compare the profiling timings of benchmarks with the first one",
+                vec![
+                    Param {
+                        required: true,
+                        ..query(
+                            "ids",
+                            string(),
+                            "Comma separated benchmark ids, the first one is the baseline",
+                        )
+                    },
+                    query(
+                        "alpha",
+                        json!({"type": "number"}),
+                        "Significance level, divided by the number of tests. Default: 0.01",
+                    ),
+                    query(
+                        "min_effect",
+                        json!({"type": "number"}),
+                        "Smallest absolute Cohen's d reported as a change. Default: 0.2",
+                    ),
+                ],
+                None,
+                object.clone(),
+            ),
+        ),
+        (
+            "/benchmarks/{benchmark_id}/events/",
+            "get",
+            operation(
+                "Events of a benchmark",
+                vec![path("benchmark_id", integer())],
+                None,
+                array_of(object.clone()),
+            ),
+        ),
+        (
+            "/benchmarks/{benchmark_id}/",
+            "delete",
+            operation(
+                "Delete a benchmark and its events, unpinning its logs",
+                vec![path("benchmark_id", integer())],
+                None,
+                schema_ref("Benchmark"),
+            ),
+        ),
+        (
+            "/benchmarks/{benchmark_id}/report/",
+            "get",
+            operation(
+                "Phases of a benchmark with the telemetry of its node during each phase",
+                vec![path("benchmark_id", integer())],
+                None,
+                object.clone(),
+            ),
+        ),
+        (
+            "/benchmarks/{benchmark_id}/state/",
+            "post",
+            operation(
+                "Stop a running benchmark, saving its summary",
+                vec![path("benchmark_id", integer())],
+                Some(json!({
+                    "type": "object",
+                    "required": ["state"],
+                    "properties": {"state": {"type": "string", "enum": ["finished", "failed", "aborted"]}},
+                })),
+                schema_ref("Benchmark"),
+            ),
+        ),
+        (
+            "/benchmarks/{benchmark_id}/targets/",
+            "get",
+            operation(
+                "Log targets of the benchmarked node",
+                vec![path("benchmark_id", integer())],
+                None,
+                array_of(json!({"type": "object", "properties": {"target": string()}})),
+            ),
+        ),
+        (
+            "/host_systems/",
+            "get",
+            operation(
+                "Host systems benchmarks run on",
+                vec![],
+                None,
+                array_of(schema_ref("HostSystem")),
+            ),
+        ),
+        (
+            "/host_systems/",
+            "post",
+            operation(
+                "Create a host system",
+                vec![],
+                Some(schema_ref("NewHostSystem")),
+                schema_ref("HostSystem"),
+            ),
+        ),
+        (
+            "/host_systems/{host_system_id}/",
+            "get",
+            operation(
+                "A host system",
+                vec![path("host_system_id", integer())],
+                None,
+                schema_ref("HostSystem"),
+            ),
+        ),
+        (
+            "/host_systems/{host_system_id}/",
+            "put",
+            operation(
+                "Replace a host system",
+                vec![path("host_system_id", integer())],
+                Some(schema_ref("NewHostSystem")),
+                schema_ref("HostSystem"),
+            ),
+        ),
+        (
+            "/host_systems/{host_system_id}/",
+            "delete",
+            operation(
+                "Delete a host system no benchmark refers to",
+                vec![path("host_system_id", integer())],
+                None,
+                schema_ref("HostSystem"),
+            ),
+        ),
+        (
+            "/stats/db/",
+            "get",
+            operation(
+                "Table and index sizes",
+                vec![],
+                None,
+                array_of(object.clone()),
+            ),
+        ),
+        (
+            "/stats/version/",
+            "get",
+            text_response(
+                operation("Build and version information", vec![], None, string()),
+                "text/html",
+            ),
+        ),
+        (
+            "/metrics/",
+            "get",
+            text_response(
+                operation("Prometheus metrics", vec![], None, string()),
+                "text/plain",
+            ),
+        ),
+        (
+            "/admin/tokens/",
+            "get",
+            operation(
+                "Issued ingest tokens, requires the admin token",
+                vec![],
+                None,
+                array_of(schema_ref("IngestToken")),
+            ),
+        ),
+        (
+            "/admin/tokens/",
+            "post",
+            operation(
+                "Issue an ingest token, which is only returned in this response",
+                vec![],
+                Some(json!({
+                    "type": "object",
+                    "required": ["scope"],
+                    "properties": {
+                        "description": string(),
+                        "scope": {"type": "string", "enum": ["normal", "audit"]},
+                        "chain": string(),
+                        "expires_at": date_time(),
+                    },
+                })),
+                schema_ref("IngestToken"),
+            ),
+        ),
+        (
+            "/admin/tokens/{token_id}/",
+            "delete",
+            operation(
+                "Revoke an ingest token",
+                vec![path("token_id", integer())],
+                None,
+                schema_ref("IngestToken"),
+            ),
+        ),
+        (
+            "/ingest/",
+            "post",
+            ingest_operation("Store a batch of log messages"),
+        ),
+        (
+            "/ingest/audit/",
+            "post",
+            ingest_operation("Store a batch of log messages that are never purged"),
+        ),
+        (
+            "/grafana/",
+            "get",
+            operation(
+                "Grafana JSON datasource connection test",
+                vec![],
+                None,
+                object.clone(),
+            ),
+        ),
+        (
+            "/grafana/search/",
+            "post",
+            operation(
+                "Grafana JSON datasource metric search",
+                vec![],
+                Some(json!({"type": "object", "properties": {"target": string()}})),
+                array_of(string()),
+            ),
+        ),
+        (
+            "/grafana/query/",
+            "post",
+            operation(
+                "Grafana JSON datasource query, targets are `/aggregate/` querystrings",
+                vec![],
+                Some(object.clone()),
+                array_of(object.clone()),
+            ),
+        ),
+        (
+            "/grafana/annotations/",
+            "post",
+            operation(
+                "Grafana JSON datasource annotations for benchmarks and node connections",
+                vec![],
+                Some(object.clone()),
+                array_of(object.clone()),
+            ),
+        ),
+        (
+            "/aggregate/",
+            "get",
+            operation(
+                "Time bucketed statistics of a numeric field",
+                aggregate_params(),
+                None,
+                array_of(json!({
+                    "type": "object",
+                    "properties": {
+                        "group": {"type": "string", "nullable": true},
+                        "points": array_of(json!({
+                            "type": "object",
+                            "properties": {"time": date_time()},
+                            "additionalProperties": {"type": "number", "nullable": true},
+                        })),
+                    },
+                })),
+            ),
+        ),
+        (
+            "/export/",
+            "get",
+            operation(
+                "Stored log messages in NDJSON, CSV or Parquet",
+                {
+                    let mut params = filter_params();
+                    params.push(query(
+                        "format",
+                        json!({"type": "string", "enum": ["ndjson", "csv", "parquet"]}),
+                        "Default: `ndjson`",
+                    ));
+                    params.push(query(
+                        "fields",
+                        string(),
+                        "Comma separated fields to include as columns",
+                    ));
+                    params
+                },
+                None,
+                string(),
+            ),
+        ),
+    ]
+}
+
+fn document() -> Value {
+    let mut operations: BTreeMap<(String, String), Value> = paths()
+        .into_iter()
+        .map(|(path, method, op)| ((path.to_string(), method.to_string()), op))
+        .collect();
+    let mut paths_object = Map::new();
+    for scope in api_scopes() {
+        for (method, path) in scope.routes {
+            let method = method.as_str().to_lowercase();
+            // A route without an operation is still listed, the tests check there are none
+            let op = operations
+                .remove(&(path.clone(), method.clone()))
+                .unwrap_or_else(|| json!({"responses": {"200": {"description": "OK"}}}));
+            paths_object
+                .entry(path)
+                .or_insert_with(|| json!({}))
+                .as_object_mut()
+                .expect("Paths are always objects")
+                .insert(method, op);
+        }
+    }
+    let codes: Vec<&str> = ErrorCode::ALL.iter().map(|c| c.as_str()).collect();
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": env!("CARGO_PKG_DESCRIPTION"),
+        },
+        "paths": paths_object,
+        "components": {
+            "schemas": {
+                "Error": {
+                    "type": "object",
+                    "required": ["code", "message"],
+                    "properties": {
+                        "code": {"type": "string", "enum": codes},
+                        "message": string(),
+                    },
+                },
+                "Log": {
+                    "type": "object",
+                    "properties": {
+                        "id": integer(),
+                        "ip_addr": string(),
+                        "peer_id": string(),
+                        "msg": string(),
+                        "created_at": date_time(),
+                        "logs": {"type": "object"},
+                    },
+                },
+                "Benchmark": {
+                    "type": "object",
+                    "properties": {
+                        "id": integer(),
+                        "setup": {"type": "object"},
+                        "created_at": date_time(),
+                        "state": {
+                            "type": "string",
+                            "enum": ["running", "finished", "failed", "aborted"],
+                        },
+                        "finished_at": date_time(),
+                        "summary": {
+                            "type": "object",
+                            "properties": {
+                                "duration_ms": integer(),
+                                "message_count": integer(),
+                                "message_counts": {"type": "object"},
+                                "timings": {"type": "object"},
+                            },
+                        },
+                        "host_system": schema_ref("HostSystem"),
+                    },
+                },
+                "NewHostSystem": {
+                    "type": "object",
+                    "required": ["description", "os", "cpu_qty", "cpu_clock", "ram_mb", "disk_info"],
+                    "properties": {
+                        "description": string(),
+                        "os": string(),
+                        "cpu_qty": integer(),
+                        "cpu_clock": integer(),
+                        "ram_mb": integer(),
+                        "disk_info": string(),
+                    },
+                },
+                "HostSystem": {
+                    "type": "object",
+                    "properties": {
+                        "id": integer(),
+                        "description": string(),
+                        "os": string(),
+                        "cpu_qty": integer(),
+                        "cpu_clock": integer(),
+                        "ram_mb": integer(),
+                        "disk_info": string(),
+                    },
+                },
+                "IngestToken": {
+                    "type": "object",
+                    "properties": {
+                        "id": integer(),
+                        "description": string(),
+                        "scope": {"type": "string", "enum": ["normal", "audit"]},
+                        "chain": string(),
+                        "expires_at": date_time(),
+                        "revoked_at": date_time(),
+                        "created_at": date_time(),
+                        "token": string(),
+                    },
+                },
+                "NewBenchmarkEvent": {
+                    "type": "object",
+                    "required": ["benchmark_id", "name", "phase", "created_at"],
+                    "properties": {
+                        "benchmark_id": integer(),
+                        "name": string(),
+                        "phase": string(),
+                        "created_at": date_time(),
+                    },
+                },
+            },
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn document_lists_path_params_and_error_codes_test() {
+        let doc = document();
+        for (path, methods) in doc["paths"].as_object().unwrap() {
+            for op in methods.as_object().unwrap().values() {
+                let path_params: Vec<&Value> = op["parameters"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .filter(|p| p["in"] == "path")
+                    .collect();
+                assert_eq!(path.matches('{').count(), path_params.len(), "{}", path);
+                for p in path_params {
+                    assert!(path.contains(&format!("{{{}}}", p["name"].as_str().unwrap())));
+                }
+            }
+        }
+        assert_eq!(
+            doc["components"]["schemas"]["Error"]["properties"]["code"]["enum"]
+                .as_array()
+                .unwrap()
+                .len(),
+            ErrorCode::ALL.len()
+        );
+    }
+
+    #[test]
+    fn document_describes_every_route_test() {
+        let routes: BTreeSet<(String, String)> = api_scopes()
+            .into_iter()
+            .flat_map(|scope| scope.routes)
+            .map(|(method, path)| (method.as_str().to_lowercase(), path))
+            .collect();
+        let documented: BTreeSet<(String, String)> = paths()
+            .into_iter()
+            .map(|(path, method, _)| (method.to_string(), path.to_string()))
+            .collect();
+        assert_eq!(routes, documented);
+    }
+}
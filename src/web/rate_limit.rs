@@ -0,0 +1,328 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate Analytics.
+
+// Substrate Analytics is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate Analytics is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
+
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Token bucket refilled at `rate` per second, holding at most `capacity` tokens.
+/// A rate of 0 means unlimited.
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: u64, burst: Duration, now: Instant) -> Self {
+        let rate = rate as f64;
+        let capacity = (rate * burst.as_secs_f64()).max(rate);
+        TokenBucket {
+            rate,
+            capacity,
+            tokens: capacity,
+            last: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last = now;
+    }
+
+    // Anything larger than the bucket can hold is allowed through when the bucket is full
+    fn has(&self, n: f64) -> bool {
+        self.rate == 0.0 || self.tokens >= n.min(self.capacity)
+    }
+
+    fn take(&mut self, n: f64) {
+        if self.rate > 0.0 {
+            self.tokens -= n.min(self.capacity);
+        }
+    }
+}
+
+/// Limits on messages and bytes per second, 0 means unlimited
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    pub msgs_per_s: u64,
+    pub bytes_per_s: u64,
+    /// How long a source may exceed its rate before being limited
+    pub burst: Duration,
+}
+
+impl Limits {
+    pub fn is_unlimited(&self) -> bool {
+        self.msgs_per_s == 0 && self.bytes_per_s == 0
+    }
+}
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    msgs: TokenBucket,
+    bytes: TokenBucket,
+}
+
+impl RateLimiter {
+    pub fn new(limits: Limits) -> Self {
+        let now = Instant::now();
+        RateLimiter {
+            msgs: TokenBucket::new(limits.msgs_per_s, limits.burst, now),
+            bytes: TokenBucket::new(limits.bytes_per_s, limits.burst, now),
+        }
+    }
+
+    /// Returns true, and uses up tokens, if a frame of `len` bytes is within the limits
+    pub fn check(&mut self, len: usize) -> bool {
+        self.check_at(len, Instant::now())
+    }
+
+    fn check_at(&mut self, len: usize, now: Instant) -> bool {
+        if self.allows_at(len, now) {
+            self.take(len);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Whether a frame of `len` bytes is within the limits, without using up tokens
+    fn allows_at(&mut self, len: usize, now: Instant) -> bool {
+        self.msgs.refill(now);
+        self.bytes.refill(now);
+        self.msgs.has(1.0) && self.bytes.has(len as f64)
+    }
+
+    fn take(&mut self, len: usize) {
+        self.msgs.take(1.0);
+        self.bytes.take(len as f64);
+    }
+}
+
+/// Rate limiters shared by all connections from the same IP
+#[derive(Clone)]
+pub struct IpRateLimits {
+    limits: Limits,
+    limiters: Arc<Mutex<HashMap<String, (usize, RateLimiter)>>>,
+}
+
+impl IpRateLimits {
+    pub fn new(limits: Limits) -> Self {
+        IpRateLimits {
+            limits,
+            limiters: Default::default(),
+        }
+    }
+
+    /// Register a connection from `ip`, the limiter for `ip` is kept until all its connections are released
+    pub fn acquire(&self, ip: &str) {
+        let limits = self.limits;
+        self.limiters
+            .lock()
+            .entry(ip.to_string())
+            .or_insert_with(|| (0, RateLimiter::new(limits)))
+            .0 += 1;
+    }
+
+    /// Release a connection from `ip`, returns true if it was the last one
+    pub fn release(&self, ip: &str) -> bool {
+        let mut limiters = self.limiters.lock();
+        if let Some((connections, _)) = limiters.get_mut(ip) {
+            *connections = connections.saturating_sub(1);
+            if *connections == 0 {
+                limiters.remove(ip);
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn check(&self, ip: &str, len: usize) -> bool {
+        if self.limits.is_unlimited() {
+            return true;
+        }
+        match self.limiters.lock().get_mut(ip) {
+            Some((_, limiter)) => limiter.check(len),
+            None => true,
+        }
+    }
+}
+
+/// What to do with frames that exceed the rate limit
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OverLimitAction {
+    Drop,
+    /// Keep one in every `n` over-limit frames
+    Sample(u64),
+}
+
+impl FromStr for OverLimitAction {
+    type Err = &'static str;
+
+    /// Either `drop` or `sample:N`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop" => Ok(OverLimitAction::Drop),
+            _ => match s.strip_prefix("sample:").map(str::parse) {
+                Some(Ok(n)) if n > 0 => Ok(OverLimitAction::Sample(n)),
+                _ => Err("Over limit action must be `drop` or `sample:N`"),
+            },
+        }
+    }
+}
+
+/// Outcome for a single frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Verdict {
+    Accept,
+    Drop,
+    Sample,
+    Disconnect,
+}
+
+/// Per connection limiter, also tracking how often the connection has gone over the limit
+pub struct ConnectionLimiter {
+    limiter: RateLimiter,
+    action: OverLimitAction,
+    over_limit: u64,
+    offences: u64,
+    window_start: Instant,
+    max_offences: u64,
+    offence_window: Duration,
+}
+
+impl ConnectionLimiter {
+    pub fn new(
+        limits: Limits,
+        action: OverLimitAction,
+        max_offences: u64,
+        offence_window: Duration,
+    ) -> Self {
+        ConnectionLimiter {
+            limiter: RateLimiter::new(limits),
+            action,
+            over_limit: 0,
+            offences: 0,
+            window_start: Instant::now(),
+            max_offences,
+            offence_window,
+        }
+    }
+
+    /// Decide what to do with a frame of `len` bytes, `ip_allows` runs the per-IP check.
+    /// Connection tokens are only used up if the per-IP check also passes
+    pub fn verdict(&mut self, len: usize, ip_allows: impl FnOnce() -> bool) -> Verdict {
+        self.verdict_at(len, ip_allows, Instant::now())
+    }
+
+    fn verdict_at(
+        &mut self,
+        len: usize,
+        ip_allows: impl FnOnce() -> bool,
+        now: Instant,
+    ) -> Verdict {
+        if self.limiter.allows_at(len, now) && ip_allows() {
+            self.limiter.take(len);
+            return Verdict::Accept;
+        }
+        if now.saturating_duration_since(self.window_start) > self.offence_window {
+            self.window_start = now;
+            self.offences = 0;
+        }
+        self.offences += 1;
+        if self.max_offences > 0 && self.offences > self.max_offences {
+            return Verdict::Disconnect;
+        }
+        self.over_limit += 1;
+        match self.action {
+            OverLimitAction::Sample(n) if self.over_limit.is_multiple_of(n) => Verdict::Sample,
+            _ => Verdict::Drop,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(msgs_per_s: u64, bytes_per_s: u64) -> Limits {
+        Limits {
+            msgs_per_s,
+            bytes_per_s,
+            burst: Duration::from_secs(1),
+        }
+    }
+
+    #[test]
+    fn bucket_refills_over_time_test() {
+        let mut limiter = RateLimiter::new(limits(2, 0));
+        let now = Instant::now();
+        assert!(limiter.check_at(10, now));
+        assert!(limiter.check_at(10, now));
+        assert!(!limiter.check_at(10, now));
+        assert!(limiter.check_at(10, now + Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn byte_limit_allows_oversized_frame_when_full_test() {
+        let mut limiter = RateLimiter::new(limits(0, 100));
+        let now = Instant::now();
+        assert!(limiter.check_at(1000, now));
+        assert!(!limiter.check_at(1, now));
+    }
+
+    #[test]
+    fn samples_and_disconnects_offenders_test() {
+        let mut conn = ConnectionLimiter::new(
+            limits(1, 0),
+            OverLimitAction::Sample(2),
+            3,
+            Duration::from_secs(60),
+        );
+        let now = Instant::now();
+        assert_eq!(conn.verdict_at(1, || true, now), Verdict::Accept);
+        assert_eq!(conn.verdict_at(1, || true, now), Verdict::Drop);
+        assert_eq!(conn.verdict_at(1, || true, now), Verdict::Sample);
+        assert_eq!(conn.verdict_at(1, || true, now), Verdict::Drop);
+        assert_eq!(conn.verdict_at(1, || true, now), Verdict::Disconnect);
+    }
+
+    #[test]
+    fn ip_limit_does_not_use_connection_tokens_test() {
+        let mut conn = ConnectionLimiter::new(
+            limits(1, 0),
+            OverLimitAction::Drop,
+            0,
+            Duration::from_secs(60),
+        );
+        let now = Instant::now();
+        assert_eq!(conn.verdict_at(1, || false, now), Verdict::Drop);
+        assert_eq!(conn.verdict_at(1, || true, now), Verdict::Accept);
+    }
+
+    #[test]
+    fn parse_over_limit_action_test() {
+        assert_eq!("drop".parse(), Ok(OverLimitAction::Drop));
+        assert_eq!("sample:10".parse(), Ok(OverLimitAction::Sample(10)));
+        assert!("sample:0".parse::<OverLimitAction>().is_err());
+        assert!("keep".parse::<OverLimitAction>().is_err());
+    }
+}
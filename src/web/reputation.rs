@@ -14,39 +14,37 @@
 // You should have received a copy of the GNU General Public License
 // along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
 
+use super::error::{path_param, ApiError};
 use super::get_filters;
 use super::metrics::Metrics;
+use super::openapi::ApiScope;
 use crate::db::{
     reputation::{PeerReputationQuery, PeerReputationsQuery},
     DbExecutor,
 };
 use actix::prelude::*;
+use actix_web::http::Method;
 use actix_web::{HttpRequest, HttpResponse};
 
-pub fn configure(cfg: &mut actix_web::web::ServiceConfig) {
-    cfg.service(
-        actix_web::web::scope("/reputation/")
-            .route("/logged/", actix_web::web::get().to(logged))
-            .route("/mock/{qty}/", actix_web::web::get().to(mock))
-            .route("/{peer_id}/", actix_web::web::get().to(single))
-            .route("", actix_web::web::get().to(all)),
-    );
+pub fn scope() -> ApiScope {
+    ApiScope::new("/reputation/")
+        .route("/logged/", Method::GET, logged)
+        .route("/mock/{qty}/", Method::GET, mock)
+        .route("/{peer_id}/", Method::GET, single)
+        .route("", Method::GET, all)
 }
 
 async fn logged(
     req: HttpRequest,
     db: actix_web::web::Data<Addr<DbExecutor>>,
     metrics: actix_web::web::Data<Metrics>,
-) -> Result<HttpResponse, actix_web::Error> {
+) -> Result<HttpResponse, ApiError> {
     metrics.inc_req_count();
-    let filters = get_filters(&req);
+    let filters = get_filters(&req)?;
     let res = db.send(PeerReputationsQuery::Logged(filters)).await?;
     match res {
         Ok(r) => Ok(HttpResponse::Ok().json(json!(r))),
-        Err(e) => {
-            error!("Could not complete stats query: {:?}", e);
-            Ok(HttpResponse::InternalServerError().json(json!("Error while processing query")))
-        }
+        Err(e) => Err(ApiError::database("stats query", e)),
     }
 }
 
@@ -54,21 +52,14 @@ async fn single(
     req: HttpRequest,
     db: actix_web::web::Data<Addr<DbExecutor>>,
     metrics: actix_web::web::Data<Metrics>,
-) -> Result<HttpResponse, actix_web::Error> {
+) -> Result<HttpResponse, ApiError> {
     metrics.inc_req_count();
-    let peer_id = req
-        .match_info()
-        .get("peer_id")
-        .expect("peer_id should be available because the route matched")
-        .to_string();
-    let filters = get_filters(&req);
+    let peer_id: String = path_param(&req, "peer_id")?;
+    let filters = get_filters(&req)?;
     let res = db.send(PeerReputationQuery { peer_id, filters }).await?;
     match res {
         Ok(r) => Ok(HttpResponse::Ok().json(json!(r))),
-        Err(e) => {
-            error!("Could not complete single peer reputation query: {:?}", e);
-            Ok(HttpResponse::InternalServerError().json(json!("Error while processing query")))
-        }
+        Err(e) => Err(ApiError::database("single peer reputation query", e)),
     }
 }
 
@@ -76,16 +67,13 @@ async fn all(
     req: HttpRequest,
     db: actix_web::web::Data<Addr<DbExecutor>>,
     metrics: actix_web::web::Data<Metrics>,
-) -> Result<HttpResponse, actix_web::Error> {
+) -> Result<HttpResponse, ApiError> {
     metrics.inc_req_count();
-    let filters = get_filters(&req);
+    let filters = get_filters(&req)?;
     let res = db.send(PeerReputationsQuery::All(filters)).await?;
     match res {
         Ok(r) => Ok(HttpResponse::Ok().json(json!(r))),
-        Err(e) => {
-            error!("Could not complete all peer reputation query: {:?}", e);
-            Ok(HttpResponse::InternalServerError().json(json!("Error while processing query")))
-        }
+        Err(e) => Err(ApiError::database("all peer reputation query", e)),
     }
 }
 
@@ -93,24 +81,12 @@ async fn mock(
     req: HttpRequest,
     db: actix_web::web::Data<Addr<DbExecutor>>,
     metrics: actix_web::web::Data<Metrics>,
-) -> Result<HttpResponse, actix_web::Error> {
+) -> Result<HttpResponse, ApiError> {
     metrics.inc_req_count();
-    let qty: usize = match req
-        .match_info()
-        .get("qty")
-        .expect("qty should be available because the route matched")
-        .to_string()
-        .parse()
-    {
-        Ok(v) => v,
-        _ => std::usize::MAX,
-    };
+    let qty: usize = path_param(&req, "qty")?;
     let res = db.send(PeerReputationsQuery::Mock(qty)).await?;
     match res {
         Ok(r) => Ok(HttpResponse::Ok().json(json!(r))),
-        Err(e) => {
-            error!("Could not complete mock reputation query: {:?}", e);
-            Ok(HttpResponse::InternalServerError().json(json!("Error while processing query")))
-        }
+        Err(e) => Err(ApiError::database("mock reputation query", e)),
     }
 }
@@ -14,18 +14,30 @@
 // You should have received a copy of the GNU General Public License
 // along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
 
+use super::bearer_token;
+use super::client_ip::{client_addr, ClientAddr};
+use super::encoding::FrameEncoding;
 use super::metrics::Metrics;
+use super::rate_limit::{ConnectionLimiter, IpRateLimits, Limits, Verdict};
+use super::shutdown::{Connections, Disconnect, Registration, ShutdownFlag};
 use crate::db::{
-    models::{NewPeerConnection, NewSubstrateLog, PeerConnection},
+    models::{IngestToken, NewPeerConnection, NewSubstrateLog, PeerConnection},
+    sessions::RecordDisconnect,
+    tokens::ValidateToken,
     DbExecutor,
 };
-use crate::{LogBuffer, CLIENT_TIMEOUT_S, HEARTBEAT_INTERVAL, WS_MAX_PAYLOAD};
+use crate::{
+    LogBuffer, CLIENT_TIMEOUT_S, HEARTBEAT_INTERVAL, INGEST_AUTH, RATE_LIMIT_ACTION,
+    RATE_LIMIT_BURST_S, RATE_LIMIT_MAX_OFFENCES, RATE_LIMIT_OFFENCE_WINDOW_S, TRUSTED_PROXIES,
+    WS_CONN_BYTES_PER_S, WS_CONN_MSGS_PER_S, WS_MAX_NODES_PER_CONNECTION, WS_MAX_PAYLOAD,
+};
 use actix::prelude::*;
 use actix_http::ws::Codec;
 use actix_web::{error, Error, HttpRequest, HttpResponse};
 use actix_web_actors::ws;
-use chrono::DateTime;
+use chrono::{DateTime, Utc};
 use serde_json::Value;
+use std::collections::HashMap;
 use std::fmt;
 use std::time::Instant;
 
@@ -53,45 +65,92 @@ struct NodeSocket {
     db: actix_web::web::Data<Addr<DbExecutor>>,
     log_buffer: actix_web::web::Data<Addr<LogBuffer>>,
     metrics: actix_web::web::Data<Metrics>,
+    connections: actix_web::web::Data<Addr<Connections>>,
+    ip_limits: actix_web::web::Data<IpRateLimits>,
+    limiter: ConnectionLimiter,
     // Indicate if the logs should be saved to a different table that is not automatically purged
     peer_connection: PeerConnection,
+    // Envelope node id that `peer_connection` belongs to, if any
+    primary_node: Option<String>,
+    // Further logical nodes sending envelopes over this socket
+    nodes: HashMap<String, PeerConnection>,
+    // Token the node authenticated with, if ingest authentication is enabled
+    ingest_token: Option<IngestToken>,
+    // Encoding of binary frames negotiated at handshake
+    encoding: FrameEncoding,
     msg_count: MessageCount,
+    // Why the connection is being closed, persisted with the end of the session
+    disconnect_reason: Option<String>,
 }
 
 impl Drop for NodeSocket {
     fn drop(&mut self) {
         self.metrics.inc_ws_dropped_count();
         debug!("Dropped WS connection to ip: {}", self.ip);
+        let mut peer_connection_ids = vec![self.peer_connection.id];
+        peer_connection_ids.extend(self.nodes.values().map(|pc| pc.id));
+        self.db.do_send(RecordDisconnect {
+            peer_connection_ids,
+            disconnected_at: Utc::now().naive_utc(),
+            reason: self
+                .disconnect_reason
+                .take()
+                .unwrap_or_else(|| "connection closed".to_string()),
+        });
     }
 }
 
 impl NodeSocket {
+    #[allow(clippy::too_many_arguments)]
     async fn new(
-        ip: String,
+        addr: ClientAddr,
         db: actix_web::web::Data<Addr<DbExecutor>>,
         log_buffer: actix_web::web::Data<Addr<LogBuffer>>,
         metrics: actix_web::web::Data<Metrics>,
+        connections: actix_web::web::Data<Addr<Connections>>,
+        ip_limits: actix_web::web::Data<IpRateLimits>,
+        ingest_token: Option<IngestToken>,
+        encoding: FrameEncoding,
         audit: bool,
     ) -> Result<Self, String> {
+        let limits = Limits {
+            msgs_per_s: *WS_CONN_MSGS_PER_S,
+            bytes_per_s: *WS_CONN_BYTES_PER_S,
+            burst: *RATE_LIMIT_BURST_S,
+        };
         Ok(Self {
-            peer_connection: Self::create_peer_connection(&db, &ip, audit).await?,
-            ip,
+            peer_connection: Self::create_peer_connection(&db, &addr, audit).await?,
+            ip: addr.ip,
             db,
             log_buffer,
             metrics,
+            connections,
+            ip_limits,
+            limiter: ConnectionLimiter::new(
+                limits,
+                *RATE_LIMIT_ACTION,
+                *RATE_LIMIT_MAX_OFFENCES,
+                *RATE_LIMIT_OFFENCE_WINDOW_S,
+            ),
+            ingest_token,
+            encoding,
+            primary_node: None,
+            nodes: HashMap::new(),
             hb: Instant::now(),
             msg_count: MessageCount::default(),
+            disconnect_reason: None,
         })
     }
 
     async fn create_peer_connection(
         db: &actix_web::web::Data<Addr<DbExecutor>>,
-        ip: &str,
+        addr: &ClientAddr,
         audit: bool,
     ) -> Result<PeerConnection, String> {
         let res = db
             .send(NewPeerConnection {
-                ip_addr: String::from(ip), //
+                ip_addr: addr.ip.clone(),
+                peer_addr: addr.peer_addr.clone(),
                 peer_id: None,
                 name: None,
                 chain: None,
@@ -99,6 +158,8 @@ impl NodeSocket {
                 authority: None,
                 startup_time: None,
                 implementation: None,
+                http_ingest: false,
+                ingest_token_id: None,
                 audit,
             })
             .await;
@@ -111,67 +172,278 @@ impl NodeSocket {
         }
     }
 
+    /// Stop the actor, recording `reason` unless one was already given
+    fn disconnect(&mut self, reason: String, ctx: &mut <Self as Actor>::Context) {
+        if self.disconnect_reason.is_none() {
+            self.disconnect_reason = Some(reason);
+        }
+        ctx.stop();
+    }
+
     fn hb(&self, ctx: &mut <Self as Actor>::Context) {
         let ip = self.ip.clone();
         ctx.run_interval(*HEARTBEAT_INTERVAL, move |act, ctx| {
             if Instant::now().duration_since(act.hb) > *CLIENT_TIMEOUT_S {
                 info!("Websocket heartbeat failed for: {} - DISCONNECTING", ip);
-                ctx.stop();
+                act.disconnect("heartbeat timeout".to_string(), ctx);
                 return;
             }
             ctx.ping(&[]);
         });
     }
 
-    fn update_peer_id(&mut self, peer_id: &str) {
-        debug!("Found peerId: {}, for ip address: {}", &peer_id, &self.ip);
-        self.peer_connection.peer_id = Some(peer_id.to_string());
-        match self.db.try_send(self.peer_connection.clone()) {
-            Ok(_) => debug!(
-                "Saved new peer connection record (ID: {:?}) for peer_id: {}",
-                self.peer_connection.id, peer_id
-            ),
-            _ => error!(
-                "Failed to send updated PeerConnection to DB actor for peer_connection_id: {}",
-                self.peer_connection.id
-            ),
+    /// Apply the per-connection and per-IP rate limits to a frame of `len` bytes,
+    /// returns false if the frame should be discarded
+    fn admit(&mut self, len: usize, ctx: &mut <Self as Actor>::Context) -> bool {
+        let ip_limits = &self.ip_limits;
+        let ip = &self.ip;
+        let verdict = self.limiter.verdict(len, || ip_limits.check(ip, len));
+        if verdict != Verdict::Accept {
+            self.metrics.inc_rate_limited(&self.ip, verdict);
+        }
+        match verdict {
+            Verdict::Accept | Verdict::Sample => true,
+            Verdict::Drop => false,
+            Verdict::Disconnect => {
+                warn!(
+                    "Rate limit repeatedly exceeded, disconnecting: {} - message count: ({})",
+                    self.ip, self.msg_count
+                );
+                ctx.close(Some(ws::CloseReason {
+                    code: ws::CloseCode::Policy,
+                    description: Some("Rate limit exceeded".to_string()),
+                }));
+                self.disconnect("rate limit exceeded".to_string(), ctx);
+                false
+            }
         }
     }
 
-    fn update_peer_info(&mut self, log: &Value) {
-        if let Some(peer_id) = log["network_id"].as_str() {
-            self.peer_connection.peer_id = Some(peer_id.to_string());
-            debug!("Found peerId: {}, for ip address: {}", peer_id, &self.ip);
+    /// A token restricted to a chain may only be used by nodes reporting that chain, `None`
+    /// until the node has reported its chain
+    fn chain_allowed(&self, peer_connection: &PeerConnection) -> Option<bool> {
+        match (
+            self.ingest_token.as_ref().and_then(|t| t.chain.as_ref()),
+            peer_connection.chain.as_ref(),
+        ) {
+            (None, _) => Some(true),
+            (Some(allowed), Some(chain)) => Some(allowed == chain),
+            (Some(_), None) => None,
         }
-        if let Some(name) = log["name"].as_str() {
-            self.peer_connection.name = Some(name.to_string());
+    }
+
+    fn peer_connection(&self, node: Option<&str>) -> Option<&PeerConnection> {
+        match node {
+            Some(id) if self.primary_node.as_deref() != Some(id) => self.nodes.get(id),
+            _ => Some(&self.peer_connection),
         }
-        if let Some(chain) = log["chain"].as_str() {
-            self.peer_connection.chain = Some(chain.to_string());
+    }
+
+    /// `PeerConnection` for a logical node, `None` for frames that are not wrapped in an envelope
+    fn peer_connection_mut(&mut self, node: Option<&str>) -> Option<&mut PeerConnection> {
+        match node {
+            Some(id) if self.primary_node.as_deref() != Some(id) => self.nodes.get_mut(id),
+            _ => Some(&mut self.peer_connection),
+        }
+    }
+
+    /// Unwrap a telemetry envelope, creating a `PeerConnection` the first time a node id is seen
+    fn handle_envelope(&mut self, id: String, payload: Value, ctx: &mut <Self as Actor>::Context) {
+        if self.primary_node.as_ref() == Some(&id) || self.nodes.contains_key(&id) {
+            self.handle_log(Some(&id), payload, ctx);
+            return;
         }
-        if let Some(version) = log["version"].as_str() {
-            self.peer_connection.version = Some(version.to_string());
+        // The first node on the socket uses the record created during the handshake
+        if self.primary_node.is_none() && self.peer_connection.peer_id.is_none() {
+            self.primary_node = Some(id.clone());
+            self.handle_log(Some(&id), payload, ctx);
+            return;
         }
-        if let Some(authority) = log["authority"].as_bool() {
-            self.peer_connection.authority = Some(authority);
+        if self.nodes.len() >= *WS_MAX_NODES_PER_CONNECTION {
+            warn!(
+                "Too many nodes on connection from: {}, ignoring node id: {}",
+                self.ip, id
+            );
+            return;
+        }
+        let new_peer_connection = NewPeerConnection {
+            ip_addr: self.ip.clone(),
+            peer_addr: self.peer_connection.peer_addr.clone(),
+            peer_id: None,
+            name: None,
+            chain: None,
+            version: None,
+            authority: None,
+            startup_time: None,
+            implementation: None,
+            http_ingest: false,
+            ingest_token_id: None,
+            audit: self.peer_connection.audit,
+        };
+        // Hold back further frames until the record exists so that none are attributed to the wrong node
+        ctx.wait(
+            self.db.send(new_peer_connection).into_actor(self).map(
+                move |res, act, ctx| match res {
+                    Ok(Ok(peer_connection)) => {
+                        debug!(
+                            "Created PeerConnection record, id: {}, for node id: {} on ip: {}",
+                            peer_connection.id, id, act.ip
+                        );
+                        act.nodes.insert(id.clone(), peer_connection);
+                        act.handle_log(Some(&id), payload, ctx);
+                    }
+                    Ok(Err(e)) => {
+                        error!("Unable to save PeerConnection for node id: {} - {}", id, e)
+                    }
+                    Err(e) => error!("Failed to send NewPeerConnection to DB actor - {:?}", e),
+                },
+            ),
+        );
+    }
+
+    fn handle_log(&mut self, node: Option<&str>, logs: Value, ctx: &mut <Self as Actor>::Context) {
+        let ip = self.ip.clone();
+        let peer_connection = match self.peer_connection_mut(node) {
+            Some(pc) => pc,
+            None => return,
+        };
+        // Details are searched for until the peer id is found, then taken from every
+        // `system.connected` so that a change of chain is checked against the token
+        if peer_connection.peer_id.is_none() || logs["msg"].as_str() == Some("system.connected") {
+            let previous = peer_connection.clone();
+            if update_peer_info(peer_connection, &logs) && *peer_connection != previous {
+                debug!(
+                    "Found peerId: {:?}, chain: {:?}, for ip address: {}",
+                    peer_connection.peer_id, peer_connection.chain, &ip
+                );
+                let peer_connection = peer_connection.clone();
+                let id = peer_connection.id;
+                if self.chain_allowed(&peer_connection) != Some(false) {
+                    match self.db.try_send(peer_connection) {
+                        Ok(_) => debug!("Saved peer connection record (ID: {:?})", id),
+                        _ => error!(
+                            "Failed to send updated PeerConnection to DB actor for peer_connection_id: {}",
+                            id
+                        ),
+                    }
+                }
+            }
         }
-        if let Some(startup_time) = log["startup_time"].as_str() {
-            if let Ok(startup_time) = startup_time.parse::<i64>() {
-                self.peer_connection.startup_time = Some(startup_time);
+        let (peer_connection_id, audit, chain_allowed) = match self.peer_connection(node) {
+            Some(pc) => (pc.id, pc.audit, self.chain_allowed(pc)),
+            None => return,
+        };
+        match chain_allowed {
+            Some(true) => (),
+            // Nothing is kept until the node shows it's on the token's chain
+            None => {
+                debug!(
+                    "Dropping log from: {}, node: {:?} - chain not reported yet",
+                    ip, node
+                );
+                return;
+            }
+            Some(false) => {
+                warn!("Token not valid for chain, disconnecting: {}", ip);
+                ctx.close(Some(ws::CloseReason {
+                    code: ws::CloseCode::Policy,
+                    description: Some("Token not valid for this chain".to_string()),
+                }));
+                self.disconnect("token not valid for chain".to_string(), ctx);
+                return;
             }
         }
-        if let Some(implementation) = log["implementation"].as_str() {
-            self.peer_connection.implementation = Some(implementation.to_string());
+        if let Some(ts) = logs["ts"].as_str() {
+            if let Ok(ts_utc) = DateTime::parse_from_rfc3339(ts) {
+                self.log_buffer
+                    .try_send(NewSubstrateLog {
+                        peer_connection_id,
+                        created_at: ts_utc.naive_utc(),
+                        audit,
+                        logs,
+                    })
+                    .unwrap_or_else(|e| {
+                        error!("Failed to send NewSubstrateLog to DB actor - {:?}", e)
+                    });
+            } else {
+                warn!("Unable to parse_from_rfc3339 for timestamp: {:?}", ts);
+            }
+        } else {
+            warn!("Unable to find timestamp in logs: {:?}", logs);
         }
-        match self.db.try_send(self.peer_connection.clone()) {
-            Ok(_) => debug!(
-                "Saved new peer connection record (ID: {:?}) for peer_id: {:?}",
-                self.peer_connection.id, self.peer_connection.peer_id
-            ),
-            _ => error!(
-                "Failed to send updated PeerConnection to DB actor for peer_connection_id: {}",
-                self.peer_connection.id
-            ),
+    }
+}
+
+/// Split a `{"id": .., "payload": {..}}` envelope into the node id and the payload
+pub(crate) fn unwrap_envelope(logs: Value) -> (Option<String>, Value) {
+    let is_envelope = logs.get("msg").is_none()
+        && logs.get("payload").is_some_and(Value::is_object)
+        && logs
+            .get("id")
+            .is_some_and(|id| id.is_u64() || id.is_string());
+    if !is_envelope {
+        return (None, logs);
+    }
+    let id = match &logs["id"] {
+        Value::String(s) => s.clone(),
+        id => id.to_string(),
+    };
+    match logs {
+        Value::Object(mut map) => {
+            let mut payload = map.remove("payload").unwrap_or(Value::Null);
+            // Some nodes put the timestamp on the envelope rather than the payload
+            if payload.get("ts").is_none() {
+                if let Some(ts) = map.remove("ts") {
+                    payload["ts"] = ts;
+                }
+            }
+            (Some(id), payload)
+        }
+        logs => (None, logs),
+    }
+}
+
+/// Update `peer_connection` with the node details found in a log message, returns true if
+/// the peer id was found
+pub(crate) fn update_peer_info(peer_connection: &mut PeerConnection, log: &Value) -> bool {
+    match log["msg"].as_str() {
+        Some("system.connected") => {
+            if let Some(peer_id) = log["network_id"].as_str() {
+                peer_connection.peer_id = Some(peer_id.to_string());
+            }
+            if let Some(name) = log["name"].as_str() {
+                peer_connection.name = Some(name.to_string());
+            }
+            if let Some(chain) = log["chain"].as_str() {
+                peer_connection.chain = Some(chain.to_string());
+            }
+            if let Some(version) = log["version"].as_str() {
+                peer_connection.version = Some(version.to_string());
+            }
+            if let Some(authority) = log["authority"].as_bool() {
+                peer_connection.authority = Some(authority);
+            }
+            if let Some(startup_time) = log["startup_time"].as_str() {
+                if let Ok(startup_time) = startup_time.parse::<i64>() {
+                    peer_connection.startup_time = Some(startup_time);
+                }
+            }
+            if let Some(implementation) = log["implementation"].as_str() {
+                peer_connection.implementation = Some(implementation.to_string());
+            }
+            true
+        }
+        // Support older versions of substrate
+        _ => {
+            if let Some(peer_id) = log["state"]["peerId"]
+                .as_str()
+                .or_else(|| log["network_state"]["peerId"].as_str())
+            {
+                peer_connection.peer_id = Some(peer_id.to_string());
+                true
+            } else {
+                false
+            }
         }
     }
 }
@@ -182,6 +454,40 @@ impl Actor for NodeSocket {
     // Initiate the heartbeat process on start
     fn started(&mut self, ctx: &mut Self::Context) {
         self.hb(ctx);
+        self.ip_limits.acquire(&self.ip);
+        self.connections
+            .do_send(Registration::Register(ctx.address().recipient()));
+    }
+
+    fn stopped(&mut self, ctx: &mut Self::Context) {
+        if self.ip_limits.release(&self.ip) {
+            self.metrics.remove_rate_limited_ip(&self.ip);
+        }
+        self.connections
+            .do_send(Registration::Unregister(ctx.address().recipient()));
+    }
+}
+
+impl Handler<Disconnect> for NodeSocket {
+    type Result = ();
+
+    fn handle(&mut self, msg: Disconnect, ctx: &mut Self::Context) -> Self::Result {
+        info!(
+            "Disconnecting: {} ({}) - message count: ({})",
+            self.ip, msg.reason, self.msg_count
+        );
+        ctx.close(Some(ws::CloseReason {
+            code: ws::CloseCode::Restart,
+            description: Some(msg.reason.clone()),
+        }));
+        if self.disconnect_reason.is_none() {
+            self.disconnect_reason = Some(msg.reason);
+        }
+        // Keep reading until the node answers with its own close frame, so frames it has
+        // already sent are still saved
+        ctx.run_later(*CLIENT_TIMEOUT_S, |act, ctx| {
+            act.disconnect("close not acknowledged".to_string(), ctx)
+        });
     }
 }
 
@@ -205,7 +511,11 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for NodeSocket {
             Ok(ws::Message::Text(text)) => {
                 self.metrics
                     .inc_ws_bytes_received(text.as_bytes().len() as u64);
+                self.metrics.inc_ws_bytes_decoded(text.len() as u64);
                 self.msg_count.text += 1;
+                if !self.admit(text.len(), ctx) {
+                    return;
+                }
                 logs = match serde_json::from_str(&text) {
                     Ok(a) => Some(a),
                     Err(e) => {
@@ -217,53 +527,43 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for NodeSocket {
             Ok(ws::Message::Binary(bin)) => {
                 self.metrics.inc_ws_bytes_received(bin.len() as u64);
                 self.msg_count.binary += 1;
-                logs = match serde_json::from_slice(&bin[..]) {
-                    Ok(a) => Some(a),
+                if !self.admit(bin.len(), ctx) {
+                    return;
+                }
+                logs = match self.encoding.decode(&bin[..], *WS_MAX_PAYLOAD) {
+                    Ok((a, decoded_len)) => {
+                        self.metrics.inc_ws_bytes_decoded(decoded_len as u64);
+                        Some(a)
+                    }
                     Err(e) => {
-                        error!("{:?}", e);
+                        error!(
+                            "Unable to decode {} frame from {}: {}",
+                            self.encoding, ip, e
+                        );
                         return;
                     }
                 };
             }
-            Ok(ws::Message::Close(_)) => {
+            Ok(ws::Message::Close(reason)) => {
                 info!(
                     "Close received, disconnecting: {} - message count: ({})",
                     ip, self.msg_count
                 );
-                ctx.stop();
+                let reason = match reason {
+                    Some(r) => format!("close frame: {:?}", r.code),
+                    None => "close frame".to_string(),
+                };
+                self.disconnect(reason, ctx);
             }
             //            ws::Message::Nop => (),
-            _ => ctx.stop(),
+            Err(e) => self.disconnect(format!("protocol error: {}", e), ctx),
+            _ => self.disconnect("unsupported frame".to_string(), ctx),
         }
         if let Some(logs) = logs {
             self.metrics.inc_ws_message_count();
-            if self.peer_connection.peer_id.is_none() {
-                debug!("Searching for peerId for ip address: {}", &ip);
-                if let Some(_msg @ "system.connected") = logs["msg"].as_str() {
-                    self.update_peer_info(&logs);
-                // Support older versions of substrate
-                } else if let Some(peer_id) = logs["state"]["peerId"].as_str() {
-                    self.update_peer_id(peer_id);
-                } else if let Some(peer_id) = logs["network_state"]["peerId"].as_str() {
-                    self.update_peer_id(peer_id);
-                }
-            }
-            if let Some(ts) = logs["ts"].as_str() {
-                if let Ok(ts_utc) = DateTime::parse_from_rfc3339(ts) {
-                    self.log_buffer
-                        .try_send(NewSubstrateLog {
-                            peer_connection_id: self.peer_connection.id,
-                            created_at: ts_utc.naive_utc(),
-                            logs,
-                        })
-                        .unwrap_or_else(|e| {
-                            error!("Failed to send NewSubstrateLog to DB actor - {:?}", e)
-                        });
-                } else {
-                    warn!("Unable to parse_from_rfc3339 for timestamp: {:?}", ts);
-                }
-            } else {
-                warn!("Unable to find timestamp in logs: {:?}", logs);
+            match unwrap_envelope(logs) {
+                (Some(id), payload) => self.handle_envelope(id, payload, ctx),
+                (None, logs) => self.handle_log(None, logs, ctx),
             }
         }
     }
@@ -278,50 +578,108 @@ pub fn configure(cfg: &mut actix_web::web::ServiceConfig) {
 }
 
 // Websocket handshake and start actor
+#[allow(clippy::too_many_arguments)]
 async fn ws_index(
     r: HttpRequest,
     stream: actix_web::web::Payload,
     db: actix_web::web::Data<Addr<DbExecutor>>,
     log_buffer: actix_web::web::Data<Addr<LogBuffer>>,
     metrics: actix_web::web::Data<Metrics>,
+    connections: actix_web::web::Data<Addr<Connections>>,
+    shutdown: actix_web::web::Data<ShutdownFlag>,
+    ip_limits: actix_web::web::Data<IpRateLimits>,
 ) -> Result<HttpResponse, Error> {
-    establish_connection(r, stream, db, log_buffer, metrics, false).await
+    establish_connection(
+        r,
+        stream,
+        db,
+        log_buffer,
+        metrics,
+        connections,
+        shutdown,
+        ip_limits,
+        false,
+    )
+    .await
 }
 
 // Websocket handshake and start actor
+#[allow(clippy::too_many_arguments)]
 async fn ws_index_permanent(
     r: HttpRequest,
     stream: actix_web::web::Payload,
     db: actix_web::web::Data<Addr<DbExecutor>>,
     log_buffer: actix_web::web::Data<Addr<LogBuffer>>,
     metrics: actix_web::web::Data<Metrics>,
+    connections: actix_web::web::Data<Addr<Connections>>,
+    shutdown: actix_web::web::Data<ShutdownFlag>,
+    ip_limits: actix_web::web::Data<IpRateLimits>,
 ) -> Result<HttpResponse, Error> {
-    establish_connection(r, stream, db, log_buffer, metrics, true).await
+    establish_connection(
+        r,
+        stream,
+        db,
+        log_buffer,
+        metrics,
+        connections,
+        shutdown,
+        ip_limits,
+        true,
+    )
+    .await
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn establish_connection(
     r: HttpRequest,
     stream: actix_web::web::Payload,
     db: actix_web::web::Data<Addr<DbExecutor>>,
     log_buffer: actix_web::web::Data<Addr<LogBuffer>>,
     metrics: actix_web::web::Data<Metrics>,
+    connections: actix_web::web::Data<Addr<Connections>>,
+    shutdown: actix_web::web::Data<ShutdownFlag>,
+    ip_limits: actix_web::web::Data<IpRateLimits>,
     audit: bool,
 ) -> Result<HttpResponse, Error> {
-    let ip = r
-        .connection_info()
-        .remote_addr()
-        .unwrap_or("Unable to decode remote IP")
-        .to_string();
+    if shutdown.is_set() {
+        return Err(error::ErrorServiceUnavailable("Shutting down"));
+    }
+    let addr = client_addr(&r, &TRUSTED_PROXIES);
+    let ip = addr.ip.clone();
+    let ingest_token = if *INGEST_AUTH {
+        Some(authenticate(&r, &db, &ip, audit).await?)
+    } else {
+        None
+    };
+    let (encoding, protocol) = negotiate_encoding(&r)?;
     debug_headers(&r);
-    info!("Establishing ws connection to node: {}", ip);
-    match NodeSocket::new(ip.clone(), db, log_buffer, metrics.clone(), audit).await {
+    info!(
+        "Establishing ws connection to node: {}, encoding: {}",
+        ip, encoding
+    );
+    match NodeSocket::new(
+        addr,
+        db,
+        log_buffer,
+        metrics.clone(),
+        connections,
+        ip_limits,
+        ingest_token,
+        encoding,
+        audit,
+    )
+    .await
+    {
         Ok(ns) => {
             metrics.inc_ws_connected_count();
             debug!(
                 "Created PeerConnection record, id: {}, for ip: {}",
                 ns.peer_connection.id, ip
             );
-            let mut res = ws::handshake(&r)?;
+            let mut res = match &protocol {
+                Some(protocol) => ws::handshake_with_protocols(&r, &[protocol.as_str()])?,
+                None => ws::handshake(&r)?,
+            };
             let codec = Codec::new().max_size(*WS_MAX_PAYLOAD);
             let ws_context = ws::WebsocketContext::with_codec(ns, stream, codec);
             Ok(res.streaming(ws_context))
@@ -336,6 +694,85 @@ async fn establish_connection(
     }
 }
 
+#[derive(Deserialize)]
+struct ConnectParams {
+    token: Option<String>,
+    /// Encoding of binary frames, if not negotiated with a WS subprotocol
+    encoding: Option<String>,
+}
+
+/// Pick the encoding of binary frames, from the first supported WS subprotocol requested or
+/// the `encoding` query parameter. Returns the subprotocol to accept, if any.
+fn negotiate_encoding(r: &HttpRequest) -> Result<(FrameEncoding, Option<String>), Error> {
+    let requested = r
+        .headers()
+        .get_all(actix_web::http::header::SEC_WEBSOCKET_PROTOCOL)
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim);
+    for protocol in requested {
+        if let Ok(encoding) = protocol.parse::<FrameEncoding>() {
+            return Ok((encoding, Some(protocol.to_string())));
+        }
+    }
+    let param = actix_web::web::Query::<ConnectParams>::from_query(r.query_string())
+        .ok()
+        .and_then(|q| q.into_inner().encoding);
+    match param {
+        Some(encoding) => encoding
+            .parse()
+            .map(|e| (e, None))
+            .map_err(error::ErrorBadRequest),
+        None => Ok((FrameEncoding::default(), None)),
+    }
+}
+
+/// Check the token presented by a connecting node, either as the `token` query parameter
+/// or an `Authorization: Bearer` header
+pub(crate) async fn authenticate(
+    r: &HttpRequest,
+    db: &actix_web::web::Data<Addr<DbExecutor>>,
+    ip: &str,
+    audit: bool,
+) -> Result<IngestToken, Error> {
+    let token = actix_web::web::Query::<ConnectParams>::from_query(r.query_string())
+        .ok()
+        .and_then(|q| q.into_inner().token)
+        .or_else(|| bearer_token(r).map(str::to_string));
+    let token = match token {
+        Some(t) => t,
+        None => {
+            warn!(
+                "No ingest token provided, rejecting connection from: {}",
+                ip
+            );
+            return Err(error::ErrorUnauthorized("Ingest token required"));
+        }
+    };
+    match db.send(ValidateToken(token)).await {
+        Ok(Ok(Some(t))) => {
+            if t.scope().allows(audit) {
+                Ok(t)
+            } else {
+                warn!("Token {} lacks audit scope, ip: {}", t.id, ip);
+                Err(error::ErrorForbidden("Token does not allow audit logging"))
+            }
+        }
+        Ok(Ok(None)) => {
+            warn!("Invalid ingest token, rejecting connection from: {}", ip);
+            Err(error::ErrorUnauthorized("Invalid ingest token"))
+        }
+        Ok(Err(e)) => {
+            error!("Failed to validate ingest token: {:?}", e);
+            Err(error::ErrorInternalServerError("Unable to validate token"))
+        }
+        Err(e) => {
+            error!("Failed to send ValidateToken to DB actor - {:?}", e);
+            Err(error::ErrorInternalServerError("Unable to validate token"))
+        }
+    }
+}
+
 fn debug_headers(req: &HttpRequest) {
     let head = req.head();
     let headers = head.headers();
@@ -349,3 +786,22 @@ fn debug_headers(req: &HttpRequest) {
         trace!("HEADER MAP: Value: {:?}", v);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unwraps_envelope_test() {
+        let (id, payload) =
+            unwrap_envelope(json!({"id": 1, "payload": {"msg": "system.interval"}}));
+        assert_eq!(id, Some("1".to_string()));
+        assert_eq!(payload, json!({"msg": "system.interval"}));
+
+        let (_, payload) = unwrap_envelope(json!({"id": "a", "ts": "t", "payload": {"msg": "m"}}));
+        assert_eq!(payload, json!({"msg": "m", "ts": "t"}));
+
+        let bare = json!({"id": 1, "msg": "system.interval", "payload": {}});
+        assert_eq!(unwrap_envelope(bare.clone()), (None, bare));
+    }
+}
@@ -0,0 +1,147 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate Analytics.
+
+// Substrate Analytics is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate Analytics is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
+
+use actix::prelude::*;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Set once shutdown has started, after which no new WS connections or ingest requests are
+/// accepted. Also counts the ingest requests still adding logs, so shutdown can wait for them.
+#[derive(Clone, Default)]
+pub struct ShutdownFlag {
+    shutting_down: Arc<AtomicBool>,
+    active_requests: Arc<AtomicUsize>,
+}
+
+impl ShutdownFlag {
+    pub fn set(&self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    /// Track a request until the returned guard is dropped, or `None` if shutdown has started
+    pub fn start_request(&self) -> Option<ActiveRequest> {
+        // Counted before checking the flag, so shutdown sees it once the flag is set
+        self.active_requests.fetch_add(1, Ordering::SeqCst);
+        let request = ActiveRequest(self.active_requests.clone());
+        if self.is_set() {
+            None
+        } else {
+            Some(request)
+        }
+    }
+
+    pub fn active_requests(&self) -> usize {
+        self.active_requests.load(Ordering::SeqCst)
+    }
+}
+
+pub struct ActiveRequest(Arc<AtomicUsize>);
+
+impl Drop for ActiveRequest {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Ask a WS actor to send a close frame with `reason` and stop once the peer has closed
+#[derive(Clone)]
+pub struct Disconnect {
+    pub reason: String,
+}
+
+impl Message for Disconnect {
+    type Result = ();
+}
+
+pub enum Registration {
+    Register(Recipient<Disconnect>),
+    Unregister(Recipient<Disconnect>),
+}
+
+impl Message for Registration {
+    type Result = ();
+}
+
+/// Disconnect every registered WS actor, returning how many were sent `Disconnect`
+pub struct DisconnectAll {
+    pub reason: String,
+}
+
+impl Message for DisconnectAll {
+    type Result = usize;
+}
+
+/// Number of WS actors that are still registered, ie. haven't stopped yet
+pub struct CountConnections;
+
+impl Message for CountConnections {
+    type Result = usize;
+}
+
+/// Keeps track of connected node and feed WS actors so they can be closed cleanly on shutdown
+#[derive(Default)]
+pub struct Connections {
+    recipients: HashSet<Recipient<Disconnect>>,
+}
+
+impl Actor for Connections {
+    type Context = Context<Self>;
+}
+
+impl Handler<Registration> for Connections {
+    type Result = ();
+
+    fn handle(&mut self, msg: Registration, _: &mut Self::Context) -> Self::Result {
+        match msg {
+            Registration::Register(r) => {
+                self.recipients.insert(r);
+            }
+            Registration::Unregister(r) => {
+                self.recipients.remove(&r);
+            }
+        }
+    }
+}
+
+impl Handler<DisconnectAll> for Connections {
+    type Result = usize;
+
+    fn handle(&mut self, msg: DisconnectAll, _: &mut Self::Context) -> Self::Result {
+        // Actors stay registered until they have stopped
+        let n = self.recipients.len();
+        for r in self.recipients.iter() {
+            if let Err(e) = r.do_send(Disconnect {
+                reason: msg.reason.clone(),
+            }) {
+                debug!("Unable to send Disconnect: {:?}", e);
+            }
+        }
+        n
+    }
+}
+
+impl Handler<CountConnections> for Connections {
+    type Result = usize;
+
+    fn handle(&mut self, _msg: CountConnections, _: &mut Self::Context) -> Self::Result {
+        self.recipients.len()
+    }
+}
@@ -14,9 +14,12 @@
 // You should have received a copy of the GNU General Public License
 // along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
 
+use super::error::ApiError;
 use super::metrics::Metrics;
+use super::openapi::ApiScope;
 use crate::db::{stats::Query, DbExecutor};
 use actix::prelude::*;
+use actix_web::http::Method;
 use actix_web::{http::StatusCode, HttpResponse, Result as AWResult};
 
 lazy_static! {
@@ -28,26 +31,21 @@ lazy_static! {
     );
 }
 
-pub fn configure(cfg: &mut actix_web::web::ServiceConfig) {
-    cfg.service(
-        actix_web::web::scope("/stats/")
-            .route("/db/", actix_web::web::get().to(send_query))
-            .route("/version/", actix_web::web::get().to(version)),
-    );
+pub fn scope() -> ApiScope {
+    ApiScope::new("/stats/")
+        .route("/db/", Method::GET, send_query)
+        .route("/version/", Method::GET, version)
 }
 
 async fn send_query(
     db: actix_web::web::Data<Addr<DbExecutor>>,
     metrics: actix_web::web::Data<Metrics>,
-) -> Result<HttpResponse, actix_web::Error> {
+) -> Result<HttpResponse, ApiError> {
     metrics.inc_req_count();
     let res = db.send(Query::Db).await?;
     match res {
         Ok(r) => Ok(HttpResponse::Ok().json(r)),
-        Err(e) => {
-            error!("Could not complete stats query: {}", e);
-            Ok(HttpResponse::InternalServerError().json(json!("Error while processing query")))
-        }
+        Err(e) => Err(ApiError::database("stats query", e)),
     }
 }
 
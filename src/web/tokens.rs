@@ -0,0 +1,90 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate Analytics.
+
+// Substrate Analytics is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate Analytics is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate Analytics.  If not, see <http://www.gnu.org/licenses/>.
+
+use super::bearer_token;
+use super::error::{path_param, ApiError, ErrorCode};
+use super::metrics::Metrics;
+use super::openapi::ApiScope;
+use crate::db::{tokens::*, DbExecutor};
+use crate::ADMIN_TOKEN;
+use actix::prelude::*;
+use actix_web::http::Method;
+use actix_web::{HttpRequest, HttpResponse};
+
+pub fn scope() -> ApiScope {
+    ApiScope::new("/admin/tokens/")
+        .route("/{token_id}/", Method::DELETE, revoke)
+        .route("", Method::GET, all)
+        .route("", Method::POST, issue)
+}
+
+fn is_admin(req: &HttpRequest) -> bool {
+    match (ADMIN_TOKEN.as_ref(), bearer_token(req)) {
+        (Some(admin_token), Some(token)) => admin_token == token,
+        _ => false,
+    }
+}
+
+async fn all(
+    req: HttpRequest,
+    db: actix_web::web::Data<Addr<DbExecutor>>,
+    metrics: actix_web::web::Data<Metrics>,
+) -> Result<HttpResponse, ApiError> {
+    metrics.inc_req_count();
+    if !is_admin(&req) {
+        return Err(ApiError::new(ErrorCode::Forbidden, "Admin token required"));
+    }
+    let res = db.send(TokensQuery).await?;
+    match res {
+        Ok(r) => Ok(HttpResponse::Ok().json(r)),
+        Err(e) => Err(ApiError::database("tokens query", e)),
+    }
+}
+
+async fn issue(
+    req: HttpRequest,
+    item: actix_web::web::Json<IssueToken>,
+    db: actix_web::web::Data<Addr<DbExecutor>>,
+    metrics: actix_web::web::Data<Metrics>,
+) -> Result<HttpResponse, ApiError> {
+    metrics.inc_req_count();
+    if !is_admin(&req) {
+        return Err(ApiError::new(ErrorCode::Forbidden, "Admin token required"));
+    }
+    let res = db.send(item.into_inner()).await?;
+    match res {
+        Ok(r) => Ok(HttpResponse::Ok().json(r)),
+        Err(e) => Err(ApiError::database("issue token", e)),
+    }
+}
+
+async fn revoke(
+    req: HttpRequest,
+    db: actix_web::web::Data<Addr<DbExecutor>>,
+    metrics: actix_web::web::Data<Metrics>,
+) -> Result<HttpResponse, ApiError> {
+    metrics.inc_req_count();
+    if !is_admin(&req) {
+        return Err(ApiError::new(ErrorCode::Forbidden, "Admin token required"));
+    }
+    let token_id: i32 = path_param(&req, "token_id")?;
+    let res = db.send(RevokeToken(token_id)).await?;
+    match res {
+        Ok(r) if r.is_null() => Err(ApiError::not_found("Token not found")),
+        Ok(r) => Ok(HttpResponse::Ok().json(r)),
+        Err(e) => Err(ApiError::database("revoke token", e)),
+    }
+}